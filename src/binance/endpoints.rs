@@ -0,0 +1,45 @@
+use super::{
+    constants::USDT_M_BASE_HTTP_ENDPOINT,
+    websocket::requests::{BinanceAssetType, FuturesType},
+};
+
+pub const WS_URL_ENV: &str = "BINANCE_WS_URL";
+pub const HTTP_URL_ENV: &str = "BINANCE_HTTP_URL";
+
+/// Base URLs of the websocket and REST APIs, tried in order until one works.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Endpoints {
+    pub ws: Vec<String>,
+    pub http: Vec<String>,
+}
+impl Endpoints {
+    pub fn usd_margined() -> Self {
+        Self {
+            ws: BinanceAssetType::Futures(FuturesType::USDMargined).get_ws_base_url_list(),
+            http: USDT_M_BASE_HTTP_ENDPOINT
+                .iter()
+                .map(|endpoint| endpoint.to_string())
+                .collect(),
+        }
+    }
+    /// The USDⓈ-M futures endpoints, each overridable with the `BINANCE_WS_URL`
+    /// and `BINANCE_HTTP_URL` environment variables.
+    pub fn from_env() -> Self {
+        let mut endpoints = Self::usd_margined();
+        if let Ok(ws) = std::env::var(WS_URL_ENV) {
+            endpoints.ws = vec![ws];
+        }
+        if let Ok(http) = std::env::var(HTTP_URL_ENV) {
+            endpoints.http = vec![http];
+        }
+        endpoints
+    }
+    /// Endpoints of a local server, such as the mock used in tests.
+    #[cfg(test)]
+    pub fn local(ws: &str, http: &str) -> Self {
+        Self {
+            ws: vec![ws.to_string()],
+            http: vec![http.to_string()],
+        }
+    }
+}
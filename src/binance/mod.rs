@@ -1,4 +1,5 @@
 pub mod constants;
+pub mod endpoints;
 pub mod models;
 pub mod rest;
 pub mod websocket;
@@ -1,45 +1,174 @@
-use std::sync::Arc;
+use std::{error::Error, sync::Arc};
 
-use gbdt::{config::Config, gradient_boost::GBDT};
-use log::info;
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
 
-use crate::MIN_TICKS_FOR_SIGNAL;
+use crate::{
+    model::{
+        drift::TrainingDistribution,
+        evaluation::FoldMetrics,
+        feature_registry::{feature_size, FeatureSchema},
+        introspection::ModelReport,
+        labeling::Labeler,
+        predictor::{gbdt::GbdtParams, ModelKind, Predictor},
+        registry::{ModelMetadata, ModelRegistry, TrainingWindow},
+    },
+    MIN_TICKS_FOR_SIGNAL,
+};
 
-pub type ModelMutex = Arc<tokio::sync::Mutex<ModelData>>;
+/// The served model. Inference reads it without locking, training swaps in a new one.
+pub type ModelSwap = Arc<ArcSwap<ModelData>>;
 
-pub fn new_model_data() -> ModelMutex {
-    match GBDT::load_model("gbdt.model") {
-        Ok(model) => {
-            info!("Loaded model");
-            Arc::new(tokio::sync::Mutex::new(ModelData::new(model)))
-        }
-        Err(_) => {
-            info!("No model found, creating new model");
-            let mut cfg = Config::new();
-            cfg.set_feature_size(18);
-            cfg.set_max_depth(6);
-            cfg.set_iterations(500);
-            cfg.set_shrinkage(0.1);
-            cfg.set_loss("SquaredError");
-            cfg.set_debug(false);
-            cfg.set_data_sample_ratio(1.0);
-            cfg.set_feature_sample_ratio(1.0);
-            cfg.set_training_optimization_level(2);
-            Arc::new(tokio::sync::Mutex::new(ModelData::new(GBDT::new(&cfg))))
+/// Serves the registry's promoted model if it is of this kind, was labelled by this labeler and
+/// was trained on the current features, otherwise a new unfitted one. Models trained later are
+/// configured with `gbdt_params`. Fails if a new model can't be created.
+pub fn new_model_data(
+    kind: ModelKind,
+    labeler: Labeler,
+    gbdt_params: GbdtParams,
+    registry: ModelRegistry,
+) -> Result<ModelSwap, Box<dyn Error>> {
+    let loaded = match registry.promoted() {
+        Ok(Some(version)) => load_model_data(kind, labeler, &gbdt_params, &registry, version),
+        Ok(None) => Err("no model was promoted".into()),
+        Err(e) => Err(e),
+    };
+    let model_data = match loaded {
+        Ok(model_data) => model_data,
+        Err(e) => {
+            warn!("Not using the promoted model: {}", e);
+            info!("Creating new {} model", kind);
+            let model = kind.new_predictor(feature_size(), &labeler, &gbdt_params)?;
+            ModelData::new(kind, labeler, gbdt_params, model, false, registry)
         }
+    };
+    Ok(Arc::new(ArcSwap::from_pointee(model_data)))
+}
+
+/// Registry `version` ready to serve, if it is of this kind, was labelled by this labeler and was
+/// trained on the current features.
+pub fn load_model_data(
+    kind: ModelKind,
+    labeler: Labeler,
+    gbdt_params: &GbdtParams,
+    registry: &ModelRegistry,
+    version: u64,
+) -> Result<ModelData, Box<dyn Error>> {
+    let (model, metadata) = registry.load(version)?;
+    if metadata.kind != kind {
+        return Err(format!("model version {} is a {} model", version, metadata.kind).into());
     }
+    if metadata.labeler != labeler {
+        return Err(format!(
+            "model version {} was labelled by {:?}",
+            version, metadata.labeler
+        )
+        .into());
+    }
+    info!(
+        "Loaded model version {}: {}",
+        metadata.version,
+        model.describe()
+    );
+    let mut model_data = ModelData::new(
+        kind,
+        labeler,
+        gbdt_params.clone(),
+        model,
+        true,
+        registry.clone(),
+    );
+    model_data.mae = Some(metadata.mae);
+    model_data.registry_version = Some(metadata.version);
+    model_data.trained_at = Some(metadata.trained_at);
+    model_data.training_window = Some(metadata.training_window);
+    model_data.metrics = Some(metadata.metrics);
+    model_data.distribution = registry
+        .distribution(metadata.version)
+        .map_err(|e| warn!("Not monitoring drift: {}", e))
+        .ok();
+    Ok(model_data)
 }
 
 #[derive(Clone)]
 pub struct ModelData {
-    pub model: GBDT,
+    pub kind: ModelKind,
+    /// How the model's targets are labelled, and so how long its predictions look ahead.
+    pub labeler: Labeler,
+    /// Parameters the gbdt models trained by this process are configured with, read at startup.
+    pub gbdt_params: GbdtParams,
+    pub model: Arc<dyn Predictor>,
+    /// False until the model has been fitted or loaded, an unfitted model is always replaced.
+    pub fitted: bool,
     pub mae: Option<i32>,
+    /// Number of times the model was trained by this process.
+    pub version: u64,
+    pub trained_at: Option<DateTime<Utc>>,
+    /// Rows and walk-forward scores of the last training run.
+    pub training_window: Option<TrainingWindow>,
+    pub metrics: Option<FoldMetrics>,
+    pub report: Option<ModelReport>,
+    /// Distribution of the features the model was fitted on, live features are compared with.
+    pub distribution: Option<TrainingDistribution>,
+    pub registry: ModelRegistry,
+    /// Registry version of the served model, the parent of the next one saved.
+    pub registry_version: Option<u64>,
 }
 impl ModelData {
-    pub fn new(model: GBDT) -> Self {
+    pub fn new(
+        kind: ModelKind,
+        labeler: Labeler,
+        gbdt_params: GbdtParams,
+        model: Box<dyn Predictor>,
+        fitted: bool,
+        registry: ModelRegistry,
+    ) -> Self {
         Self {
-            model,
+            kind,
+            labeler,
+            gbdt_params,
+            model: Arc::from(model),
+            fitted,
             mae: Some(MIN_TICKS_FOR_SIGNAL),
+            version: 0,
+            trained_at: None,
+            training_window: None,
+            metrics: None,
+            report: None,
+            distribution: None,
+            registry,
+            registry_version: None,
         }
     }
+    /// Registers the model as a new version with the schema of the features it was trained on,
+    /// and promotes it. Returns the new version.
+    pub fn save(&mut self) -> Result<u64, Box<dyn Error>> {
+        let version = self.register()?;
+        self.registry.promote(version)?;
+        self.registry_version = Some(version);
+        Ok(version)
+    }
+    /// Registers the model as a new version without serving it. Returns the new version.
+    pub fn register(&self) -> Result<u64, Box<dyn Error>> {
+        let metadata = ModelMetadata {
+            version: self.registry.next_version()?,
+            parent: self.registry_version,
+            kind: self.kind,
+            labeler: self.labeler,
+            config: self.model.describe(),
+            schema_hash: FeatureSchema::current().hash,
+            trained_at: self.trained_at.unwrap_or_else(Utc::now),
+            training_window: self.training_window.ok_or("the model was never trained")?,
+            metrics: self.metrics.ok_or("the model was never scored")?,
+            mae: self.mae.unwrap_or(MIN_TICKS_FOR_SIGNAL),
+        };
+        self.registry.register(
+            self.model.as_ref(),
+            &metadata,
+            self.report.as_ref(),
+            self.distribution.as_ref(),
+        )?;
+        Ok(metadata.version)
+    }
 }
@@ -18,6 +18,14 @@ pub fn new_orderbooks_rwl() -> OrderBooksRWL {
     Arc::new(RwLock::new(OrderBook::default()))
 }
 use rust_decimal::Decimal;
+
+/// Levels of each side averaged into the weighted mid.
+const WEIGHTED_MID_LEVELS: usize = 5;
+/// Distance from the mid, in basis points, within which resting size is summed.
+const DEPTH_BPS: i64 = 10;
+/// Levels of each side the book slope is measured over.
+const SLOPE_LEVELS: usize = 10;
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Eq)]
 pub struct PriceSize {
     pub price: Decimal,
@@ -32,6 +40,9 @@ pub struct OrderBook {
     pub last_update_id: i64,
     pub time: DateTime<Utc>,
     pub is_valid: bool,
+    /// True until the first update after a REST snapshot has been applied.
+    #[serde(default)]
+    pub from_snapshot: bool,
 }
 impl OrderBook {
     pub fn is_empty(&self) -> bool {
@@ -61,50 +72,108 @@ impl OrderBook {
             round_to_nearest_tick(ask_price_volume / ask_total, tick_size);
         let num_ticks_from_best_ask = (ask_price_volume_weighted - self.asks[0].price) / tick_size;
         let bids_asks_ratio = bid_total / ask_total;
-        let bid_notional = self
-            .bids
-            .par_iter()
-            .map(|b| b.price * b.size)
-            .sum::<Decimal>();
-        let ask_notional = self
-            .asks
-            .par_iter()
-            .map(|a| a.price * a.size)
-            .sum::<Decimal>();
+        let best_bid = &self.bids[0];
+        let best_ask = &self.asks[0];
+        let mid = (best_bid.price + best_ask.price) / Decimal::TWO;
+        // both fall back to the mid when the levels they weigh have no size
+        let top_size = best_bid.size + best_ask.size;
+        let microprice = if top_size.is_zero() {
+            mid
+        } else {
+            (best_bid.price * best_ask.size + best_ask.price * best_bid.size) / top_size
+        };
+        let weighted_mid = match (
+            vwap(&self.bids, WEIGHTED_MID_LEVELS),
+            vwap(&self.asks, WEIGHTED_MID_LEVELS),
+        ) {
+            (Some(bid_vwap), Some(ask_vwap)) => {
+                let bid_size = cumulative_size(&self.bids, WEIGHTED_MID_LEVELS);
+                let ask_size = cumulative_size(&self.asks, WEIGHTED_MID_LEVELS);
+                (bid_vwap * ask_size + ask_vwap * bid_size) / (bid_size + ask_size)
+            }
+            _ => mid,
+        };
+        let near_mid = mid * Decimal::new(DEPTH_BPS, 4);
         Some(BookFeatures {
             bid_total,
             ask_total,
             num_ticks_from_best_bid,
             num_ticks_from_best_ask,
             bids_asks_ratio,
-            bid_notional,
-            ask_notional,
+            bid_notional: bid_price_volume,
+            ask_notional: ask_price_volume,
+            mid_price: mid,
+            spread_ticks: (best_ask.price - best_bid.price) / tick_size,
+            microprice_ticks_from_mid: (microprice - mid) / tick_size,
+            weighted_mid_ticks_from_mid: (weighted_mid - mid) / tick_size,
+            imbalance_1: imbalance(&self.bids, &self.asks, 1),
+            imbalance_5: imbalance(&self.bids, &self.asks, 5),
+            imbalance_10: imbalance(&self.bids, &self.asks, 10),
+            imbalance_20: imbalance(&self.bids, &self.asks, 20),
+            bid_depth_near_mid: depth_within(&self.bids, mid, near_mid),
+            ask_depth_near_mid: depth_within(&self.asks, mid, near_mid),
+            bid_slope: slope(&self.bids, mid, tick_size),
+            ask_slope: slope(&self.asks, mid, tick_size),
+            ticks_to_largest_bid: ticks_to_largest(&self.bids, tick_size),
+            ticks_to_largest_ask: ticks_to_largest(&self.asks, tick_size),
         })
     }
+    /// A book seeded from a depth update, without the levels it removes.
     pub fn new_from_update(update: OrderbookMessage) -> Self {
         Self {
-            bids: update.bids,
-            asks: update.asks,
+            bids: without_empty_levels(update.bids),
+            asks: without_empty_levels(update.asks),
             last_update_id: update.last_update_id,
             time: update.time,
             is_valid: true,
             first_update_id: update.first_update_id,
+            from_snapshot: false,
+        }
+    }
+    pub fn new_from_snapshot(snapshot: DepthSnapshot) -> Self {
+        Self {
+            bids: without_empty_levels(snapshot.bids),
+            asks: without_empty_levels(snapshot.asks),
+            first_update_id: snapshot.last_update_id,
+            last_update_id: snapshot.last_update_id,
+            time: snapshot.time,
+            is_valid: true,
+            from_snapshot: true,
         }
     }
     pub fn update(&mut self, update: OrderbookMessage) {
-        let orderly = match update.prev_last_update_id {
-            Some(previous) => previous == self.last_update_id,
-            None => self.last_update_id == update.first_update_id - 1,
+        if self.from_snapshot && update.last_update_id < self.last_update_id {
+            debug!(
+                "Dropping update {} older than the snapshot",
+                update.last_update_id
+            );
+            return;
+        }
+        let orderly = if self.from_snapshot {
+            // the first update after a snapshot must straddle the snapshot's update id
+            update.first_update_id <= self.last_update_id
+        } else {
+            match update.prev_last_update_id {
+                Some(previous) => previous == self.last_update_id,
+                None => self.last_update_id == update.first_update_id - 1,
+            }
         };
+        self.from_snapshot = false;
         if !orderly {
             warn!("Orderbook update for {} not orderly", update.symbol);
             self.is_valid = false;
         }
 
-        let update_bids_map: HashMap<_, _> =
-            update.bids.into_par_iter().map(|b| (b.price, b.size)).collect();
-        let update_asks_map: HashMap<_, _> =
-            update.asks.into_par_iter().map(|a| (a.price, a.size)).collect();
+        let update_bids_map: HashMap<_, _> = update
+            .bids
+            .into_par_iter()
+            .map(|b| (b.price, b.size))
+            .collect();
+        let update_asks_map: HashMap<_, _> = update
+            .asks
+            .into_par_iter()
+            .map(|a| (a.price, a.size))
+            .collect();
 
         // Process bids and asks in parallel
         rayon::join(
@@ -144,6 +213,20 @@ pub struct OrderbookMessage {
     #[serde(rename = "pu")]
     pub prev_last_update_id: Option<i64>,
 }
+/// Response of the REST depth endpoint, used to (re)build the book.
+#[serde_as]
+#[derive(Default, Debug, Clone, PartialEq, Deserialize)]
+pub struct DepthSnapshot {
+    #[serde(rename = "lastUpdateId")]
+    pub last_update_id: i64,
+    #[serde(rename = "E", default)]
+    #[serde_as(as = "TimestampMilliSeconds")]
+    pub time: DateTime<Utc>,
+    #[serde(with = "orderbook_serde")]
+    pub bids: Vec<PriceSize>,
+    #[serde(with = "orderbook_serde")]
+    pub asks: Vec<PriceSize>,
+}
 mod orderbook_serde {
     use rust_decimal::Decimal;
     use serde::{self, Deserialize, Deserializer};
@@ -174,6 +257,64 @@ pub struct UpdateCSVFormat {
     pub quantity: Decimal,
 }
 
+/// Size of the best `levels` price levels of one side of the book.
+fn cumulative_size(orders: &[PriceSize], levels: usize) -> Decimal {
+    orders.iter().take(levels).map(|o| o.size).sum()
+}
+
+/// Volume weighted price of the best `levels` levels, `None` if they have no size.
+fn vwap(orders: &[PriceSize], levels: usize) -> Option<Decimal> {
+    let size = cumulative_size(orders, levels);
+    if size.is_zero() {
+        return None;
+    }
+    let notional: Decimal = orders.iter().take(levels).map(|o| o.price * o.size).sum();
+    Some(notional / size)
+}
+
+/// Bid minus ask size over their total, within the best `levels` levels of each side, zero when
+/// neither has any size.
+fn imbalance(bids: &[PriceSize], asks: &[PriceSize], levels: usize) -> Decimal {
+    let bid_size = cumulative_size(bids, levels);
+    let ask_size = cumulative_size(asks, levels);
+    let total = bid_size + ask_size;
+    if total.is_zero() {
+        return Decimal::ZERO;
+    }
+    (bid_size - ask_size) / total
+}
+
+/// `orders` without the levels of zero size, which a depth update uses to remove a level.
+fn without_empty_levels(mut orders: Vec<PriceSize>) -> Vec<PriceSize> {
+    orders.retain(|o| !o.size.is_zero());
+    orders
+}
+
+/// Size resting within `distance` of the mid.
+fn depth_within(orders: &[PriceSize], mid: Decimal, distance: Decimal) -> Decimal {
+    orders
+        .iter()
+        .take_while(|o| (o.price - mid).abs() <= distance)
+        .map(|o| o.size)
+        .sum()
+}
+
+/// Size per tick between the mid and the `SLOPE_LEVELS`th level, a steeper book is harder to move.
+fn slope(orders: &[PriceSize], mid: Decimal, tick_size: Decimal) -> Decimal {
+    let last = &orders[orders.len().min(SLOPE_LEVELS) - 1];
+    let ticks = (last.price - mid).abs() / tick_size;
+    if ticks.is_zero() {
+        return Decimal::ZERO;
+    }
+    cumulative_size(orders, SLOPE_LEVELS) / ticks
+}
+
+/// Ticks between the best level and the level with the most size.
+fn ticks_to_largest(orders: &[PriceSize], tick_size: Decimal) -> Decimal {
+    let largest = orders.iter().max_by_key(|o| o.size).unwrap();
+    (largest.price - orders[0].price).abs() / tick_size
+}
+
 fn update_orders(orders: &mut Vec<PriceSize>, updates: &HashMap<Decimal, Decimal>) {
     orders.par_iter_mut().for_each(|order| {
         if let Some(size) = updates.get(&order.price) {
@@ -202,4 +343,81 @@ pub struct BookFeatures {
     pub bids_asks_ratio: Decimal,
     pub bid_notional: Decimal,
     pub ask_notional: Decimal,
+    /// Midpoint of the best bid and ask.
+    pub mid_price: Decimal,
+    pub spread_ticks: Decimal,
+    pub microprice_ticks_from_mid: Decimal,
+    pub weighted_mid_ticks_from_mid: Decimal,
+    pub imbalance_1: Decimal,
+    pub imbalance_5: Decimal,
+    pub imbalance_10: Decimal,
+    pub imbalance_20: Decimal,
+    pub bid_depth_near_mid: Decimal,
+    pub ask_depth_near_mid: Decimal,
+    pub bid_slope: Decimal,
+    pub ask_slope: Decimal,
+    pub ticks_to_largest_bid: Decimal,
+    pub ticks_to_largest_ask: Decimal,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::dec;
+
+    fn levels(levels: &[(&str, &str)]) -> Vec<PriceSize> {
+        levels
+            .iter()
+            .map(|(price, size)| PriceSize {
+                price: dec(price),
+                size: dec(size),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn computes_microstructure_features() {
+        let book = OrderBook {
+            bids: levels(&[("100.0", "1"), ("99.9", "3"), ("99.8", "2")]),
+            asks: levels(&[("100.1", "3"), ("100.2", "1"), ("100.3", "5")]),
+            is_valid: true,
+            ..Default::default()
+        };
+        let features = book.to_features(dec("0.1")).unwrap();
+        assert_eq!(features.bid_notional, dec("599.3"));
+        assert_eq!(features.mid_price, dec("100.05"));
+        assert_eq!(features.spread_ticks, dec("1"));
+        assert_eq!(features.microprice_ticks_from_mid, dec("-0.25"));
+        assert!(features.weighted_mid_ticks_from_mid < Decimal::ZERO);
+        assert_eq!(features.imbalance_1, dec("-0.5"));
+        assert_eq!(features.imbalance_5, dec("-0.2"));
+        assert_eq!(features.imbalance_20, features.imbalance_5);
+        assert_eq!(features.bid_depth_near_mid, dec("1"));
+        assert_eq!(features.ask_depth_near_mid, dec("3"));
+        assert_eq!(features.bid_slope, dec("2.4"));
+        assert_eq!(features.ask_slope, dec("3.6"));
+        assert_eq!(features.ticks_to_largest_bid, dec("1"));
+        assert_eq!(features.ticks_to_largest_ask, dec("2"));
+    }
+
+    #[test]
+    fn levels_without_size_dont_divide_by_zero() {
+        let book = OrderBook {
+            bids: levels(&[("100.0", "0"), ("99.9", "3")]),
+            asks: levels(&[("100.1", "0"), ("100.2", "1")]),
+            is_valid: true,
+            ..Default::default()
+        };
+        let features = book.to_features(dec("0.1")).unwrap();
+        assert_eq!(features.microprice_ticks_from_mid, Decimal::ZERO);
+        assert_eq!(features.imbalance_1, Decimal::ZERO);
+        assert_eq!(features.imbalance_5, dec("0.5"));
+
+        let seeded = OrderBook::new_from_update(OrderbookMessage {
+            bids: levels(&[("100.0", "0"), ("99.9", "3")]),
+            asks: levels(&[("100.1", "1")]),
+            ..Default::default()
+        });
+        assert_eq!(seeded.bids, levels(&[("99.9", "3")]));
+    }
 }
@@ -1,6 +1,93 @@
-use super::models::fapi_exchange_info::USDMExchangeInfo;
+use std::time::Duration;
 
-pub async fn get_exchange_info() -> Result<USDMExchangeInfo, reqwest::Error> {
-    let url = "https://fapi.binance.com/fapi/v1/exchangeInfo";
-    reqwest::get(url).await?.json().await
+use log::warn;
+
+use super::{
+    endpoints::Endpoints,
+    models::{fapi_exchange_info::USDMExchangeInfo, orderbook::DepthSnapshot},
+};
+use crate::REST_TIMEOUT_MS;
+
+pub const DEPTH_SNAPSHOT_LIMIT: i32 = 1000;
+
+pub async fn get_exchange_info(endpoints: &Endpoints) -> Result<USDMExchangeInfo, reqwest::Error> {
+    get_first_ok(endpoints, "/fapi/v1/exchangeInfo").await
+}
+
+pub async fn get_depth_snapshot(
+    endpoints: &Endpoints,
+    symbol: &str,
+    limit: i32,
+) -> Result<DepthSnapshot, reqwest::Error> {
+    get_first_ok(
+        endpoints,
+        &format!("/fapi/v1/depth?symbol={}&limit={}", symbol, limit),
+    )
+    .await
+}
+
+/// GETs `path` from each HTTP endpoint in turn, returning the first successful response
+/// or the last error. Each request gives up after `REST_TIMEOUT_MS`.
+async fn get_first_ok<T: serde::de::DeserializeOwned>(
+    endpoints: &Endpoints,
+    path: &str,
+) -> Result<T, reqwest::Error> {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_millis(REST_TIMEOUT_MS))
+        .build()?;
+    let mut last_error = None;
+    for base_url in endpoints.http.iter() {
+        let url = format!("{}{}", base_url, path);
+        match client.get(&url).send().await {
+            Ok(response) => match response.error_for_status() {
+                Ok(response) => return response.json().await,
+                Err(e) => {
+                    warn!("Error requesting {}: {:?}", url, e);
+                    last_error = Some(e);
+                }
+            },
+            Err(e) => {
+                warn!("Error requesting {}: {:?}", url, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.expect("no HTTP endpoints configured"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, dec, mock_http::MockHttpServer};
+
+    #[tokio::test]
+    async fn exchange_info_falls_back_to_the_next_endpoint() {
+        let http = MockHttpServer::start(test_support::exchange_info(), Default::default()).await;
+        let endpoints = Endpoints {
+            ws: vec![],
+            http: vec!["http://127.0.0.1:1".to_string(), http.base_url.clone()],
+        };
+        let exchange_info = get_exchange_info(&endpoints).await.unwrap();
+        let market = &exchange_info.symbols[0];
+        assert_eq!(market.symbol, test_support::SYMBOL);
+        assert_eq!(market.get_tick_size(), Some(dec("0.10")));
+        assert_eq!(http.requests(), vec!["/fapi/v1/exchangeInfo"]);
+    }
+
+    #[tokio::test]
+    async fn depth_snapshot_is_parsed() {
+        let depth = test_support::depth_snapshot(42, &[("100.0", "1.5")], &[("100.1", "2")]);
+        let http = MockHttpServer::start(test_support::exchange_info(), depth).await;
+        let endpoints = Endpoints::local("ws://127.0.0.1:1", &http.base_url);
+        let snapshot = get_depth_snapshot(&endpoints, test_support::SYMBOL, 5)
+            .await
+            .unwrap();
+        assert_eq!(snapshot.last_update_id, 42);
+        assert_eq!(snapshot.bids[0].price, dec("100.0"));
+        assert_eq!(snapshot.asks[0].size, dec("2"));
+        assert_eq!(
+            http.requests(),
+            vec!["/fapi/v1/depth?symbol=BTCUSDT&limit=5"]
+        );
+    }
 }
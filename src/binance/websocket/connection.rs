@@ -1,11 +1,16 @@
 use crate::{
     binance::{
+        endpoints::Endpoints,
         models::{fapi_exchange_info::Symbol, orderbook::OrderBooksRWL},
         websocket::handlers::book_ticker::handle_book_ticker,
     },
-    model::data_handling::Dfrwl,
+    model::{
+        data_handling::Dfrwl,
+        order_flow::{new_order_flow_rwl, OrderFlowRwl},
+    },
+    monitoring::{latency::LatencyRwl, Telemetry},
 };
-use chrono::Duration;
+use chrono::{DateTime, Duration, TimeZone, Utc};
 use futures_util::{
     stream::{SplitSink, SplitStream},
     SinkExt, StreamExt,
@@ -13,7 +18,7 @@ use futures_util::{
 use log::{debug, error, warn};
 use rust_decimal::Decimal;
 use serde_json::{Map, Value};
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
 use tokio::{net::TcpStream, sync::Notify};
 use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
@@ -25,6 +30,18 @@ use super::{
 
 type OutgoingSocket = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
 type IncomingSocket = SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>;
+
+/// Shared state the incoming messages are routed to.
+#[derive(Clone)]
+struct StreamContext {
+    orderbooks_rwl: OrderBooksRWL,
+    notify: Arc<Notify>,
+    tick_size: Decimal,
+    dataframe_rwl: Dfrwl,
+    order_flow_rwl: OrderFlowRwl,
+    telemetry: Telemetry,
+    endpoints: Endpoints,
+}
 /// Establishes a websocket connection to Binance and persists it for the duration of the program.
 /// If disconnected, it will attempt to reconnect uo to 5 times at an ever-increasing interval up to 26 seconds.
 /// It will try a max of 5 times before exiting the program.
@@ -33,14 +50,20 @@ pub async fn establish_and_persist(
     market: Symbol,
     notify: Arc<Notify>,
     dataframe_rwl: Dfrwl,
+    telemetry: Telemetry,
+    endpoints: Endpoints,
 ) {
     let mut bad_attempts = 0;
+    let order_flow_rwl = new_order_flow_rwl();
     loop {
         if establish(
             orderbooks_rwl.clone(),
             market.clone(),
             notify.clone(),
             dataframe_rwl.clone(),
+            order_flow_rwl.clone(),
+            telemetry.clone(),
+            endpoints.clone(),
         )
         .await
         {
@@ -53,6 +76,7 @@ pub async fn establish_and_persist(
             return;
         }
         tokio::time::sleep(Duration::seconds(bad_attempts * 5 + 1).to_std().unwrap()).await;
+        telemetry.metrics.reconnects.inc();
     }
 }
 /// Establishes a single websocket connection to Binance. Returns true if there was an error.
@@ -61,6 +85,9 @@ async fn establish(
     market: Symbol,
     notify: Arc<Notify>,
     dataframe_rwl: Dfrwl,
+    order_flow_rwl: OrderFlowRwl,
+    telemetry: Telemetry,
+    endpoints: Endpoints,
 ) -> bool {
     let request = DataRequest::new(
         BinanceAssetType::Futures(FuturesType::USDMargined),
@@ -69,24 +96,35 @@ async fn establish(
             Stream::Depth(market.symbol.to_string(), 100),
         ],
     );
-    for endpoint in request.get_ws_urls().iter() {
+    for endpoint in request.get_ws_urls(&endpoints.ws).iter() {
         debug!("Attempting WS connection to {}", endpoint);
         match tokio_tungstenite::connect_async(endpoint).await {
             Ok((stream, response)) => {
                 debug!("Connected to {endpoint} status: {}", response.status());
                 let (sender, receiver) = stream.split();
                 let ping_pong = Arc::new(Notify::new());
-                let tick_size = market.get_tick_size().unwrap();
+                let context = StreamContext {
+                    orderbooks_rwl: orderbooks_rwl.clone(),
+                    notify,
+                    tick_size: market.get_tick_size().unwrap(),
+                    dataframe_rwl: dataframe_rwl.clone(),
+                    order_flow_rwl: order_flow_rwl.clone(),
+                    telemetry: telemetry.clone(),
+                    endpoints: endpoints.clone(),
+                };
+                telemetry.status.write().await.connected = true;
                 tokio::select! {
-                    _= tokio::spawn(process_incoming_message(receiver, ping_pong.clone(),orderbooks_rwl.clone(),notify,tick_size,dataframe_rwl.clone())) => {
+                    _= tokio::spawn(process_incoming_message(receiver, ping_pong.clone(),context)) => {
                         error!("Incoming message processing failed");
-                        return true;
                     }
                     _= tokio::spawn(process_outgoing_message(sender, ping_pong.clone(),request.clone())) => {
                         error!("Outgoing message processing failed");
-                        return true;
                     }
                 }
+                telemetry.status.write().await.connected = false;
+                // updates were missed while disconnected, resync on the next one
+                orderbooks_rwl.write().await.is_valid = false;
+                return true;
             }
             Err(e) => {
                 error!("{:?}", e);
@@ -100,23 +138,12 @@ async fn establish(
 async fn process_incoming_message(
     mut receiver: IncomingSocket,
     ping_pong: Arc<Notify>,
-    orderbooks_rwl: OrderBooksRWL,
-    notify: Arc<Notify>,
-    tick_size: Decimal,
-    dataframe_rwl: Dfrwl,
+    context: StreamContext,
 ) {
     while let Some(result) = receiver.next().await {
         match result {
             Ok(message) => {
-                process_message(
-                    message,
-                    ping_pong.clone(),
-                    orderbooks_rwl.clone(),
-                    notify.clone(),
-                    tick_size,
-                    dataframe_rwl.clone(),
-                )
-                .await;
+                process_message(message, ping_pong.clone(), &context).await;
             }
             Err(e) => {
                 warn!("Error receiving message: {:?}", e);
@@ -125,44 +152,65 @@ async fn process_incoming_message(
     }
 }
 
-async fn process_message(
-    message: Message,
-    ping_pong: Arc<Notify>,
-    orderbooks_rwl: OrderBooksRWL,
-    notify: Arc<Notify>,
-    tick_size: Decimal,
-    dataframe_rwl: Dfrwl,
-) {
+async fn process_message(message: Message, ping_pong: Arc<Notify>, context: &StreamContext) {
+    let received_at = Utc::now();
+    let received_instant = Instant::now();
     match message {
         Message::Text(text_message) => {
             //debug!("Received message: {}", text_message);
             match serde_json::from_str::<Map<String, Value>>(&text_message) {
                 Ok(unrouted_message) => match unrouted_message.contains_key("data") {
-                    true => match unrouted_message["data"]["e"].as_str().unwrap() {
-                        "depthUpdate" => {
-                            handle_depth_update_message(
-                                unrouted_message["data"].clone(),
-                                orderbooks_rwl.clone(),
-                            )
-                            .await;
-                        }
-                        "trade" => {
-                            handle_trades(
-                                unrouted_message["data"].clone(),
-                                orderbooks_rwl.clone(),
-                                notify.clone(),
-                                tick_size,
-                                dataframe_rwl.clone(),
-                            )
-                            .await;
-                        }
-                        "bookTicker" => {
-                            handle_book_ticker(unrouted_message["data"].clone()).await;
-                        }
-                        _ => {
-                            debug!("Unrecognized message: {:?}", unrouted_message);
+                    true => {
+                        let event_type = unrouted_message["data"]["e"].as_str().unwrap();
+                        context
+                            .telemetry
+                            .metrics
+                            .messages
+                            .with_label_values(&[event_type])
+                            .inc();
+                        match event_type {
+                            "depthUpdate" => {
+                                handle_depth_update_message(
+                                    unrouted_message["data"].clone(),
+                                    context.orderbooks_rwl.clone(),
+                                    context.order_flow_rwl.clone(),
+                                    context.telemetry.metrics.clone(),
+                                    &context.endpoints,
+                                )
+                                .await;
+                            }
+                            "trade" => {
+                                handle_trades(
+                                    unrouted_message["data"].clone(),
+                                    context.orderbooks_rwl.clone(),
+                                    context.notify.clone(),
+                                    context.tick_size,
+                                    context.dataframe_rwl.clone(),
+                                    context.order_flow_rwl.clone(),
+                                    context.telemetry.metrics.clone(),
+                                )
+                                .await;
+                            }
+                            "bookTicker" => {
+                                handle_book_ticker(
+                                    unrouted_message["data"].clone(),
+                                    context.telemetry.metrics.clone(),
+                                )
+                                .await;
+                            }
+                            _ => {
+                                debug!("Unrecognized message: {:?}", unrouted_message);
+                            }
                         }
-                    },
+                        context.telemetry.status.write().await.last_message_at = Some(received_at);
+                        record_latency(
+                            &unrouted_message,
+                            context.telemetry.latency.clone(),
+                            received_at,
+                            received_instant,
+                        )
+                        .await;
+                    }
                     false => {
                         if unrouted_message.keys().len() == 2
                             && unrouted_message.contains_key("result")
@@ -179,6 +227,12 @@ async fn process_message(
                 },
                 Err(e) => {
                     error!("Error parsing message: {:?}", e);
+                    context
+                        .telemetry
+                        .metrics
+                        .parse_errors
+                        .with_label_values(&["unrouted"])
+                        .inc();
                 }
             }
         }
@@ -199,6 +253,26 @@ async fn process_message(
     }
 }
 
+/// Records the exchange to receive and receive to handled latency of a combined stream message.
+async fn record_latency(
+    unrouted_message: &Map<String, Value>,
+    latency_rwl: LatencyRwl,
+    received_at: DateTime<Utc>,
+    received_instant: Instant,
+) {
+    let stream = match unrouted_message["stream"].as_str() {
+        Some(stream) => stream,
+        None => return,
+    };
+    let event_time = unrouted_message["data"]["E"]
+        .as_i64()
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single());
+    latency_rwl
+        .write()
+        .await
+        .record_message(stream, event_time, received_at, received_instant);
+}
+
 async fn process_outgoing_message(
     mut sender: OutgoingSocket,
     ping_pong: Arc<tokio::sync::Notify>,
@@ -225,3 +299,108 @@ async fn process_outgoing_message(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        binance::models::orderbook::new_orderbooks_rwl,
+        model::{data_handling::new_dataframe_rwl, store::Retention},
+        monitoring::{latency::LatencyStage, new_telemetry},
+        test_support::{
+            self,
+            mock_http::MockHttpServer,
+            mock_ws::{MockWsServer, Step},
+        },
+    };
+
+    fn trades(ids: std::ops::Range<i64>) -> Vec<Step> {
+        ids.map(|id| Step::trade(test_support::trade(id, "100.0", "0.5", id % 2 == 0)))
+            .collect()
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn streams_into_the_dataframe_and_reconnects_after_a_disconnect() {
+        let depth = test_support::depth_snapshot(100, &[("100.0", "1")], &[("100.1", "1")]);
+        let http = MockHttpServer::start(test_support::exchange_info(), depth).await;
+        let mut first_session = vec![Step::depth_update(test_support::depth_update(
+            98,
+            105,
+            94,
+            &[("99.9", "2")],
+            &[("100.2", "2")],
+        ))];
+        first_session.extend(trades(0..5));
+        first_session.extend([Step::Ping, Step::Disconnect]);
+        let mut second_session = vec![Step::depth_update(test_support::depth_update(
+            99,
+            120,
+            98,
+            &[],
+            &[],
+        ))];
+        second_session.extend(trades(5..8));
+        let ws = MockWsServer::start(vec![first_session, second_session]).await;
+
+        let orderbooks_rwl = new_orderbooks_rwl();
+        let dataframe_rwl = new_dataframe_rwl(Retention::default());
+        let telemetry = new_telemetry(test_support::SYMBOL);
+        let connection = tokio::spawn(establish_and_persist(
+            orderbooks_rwl.clone(),
+            test_support::market(),
+            Arc::new(Notify::new()),
+            dataframe_rwl.clone(),
+            telemetry.clone(),
+            Endpoints::local(&ws.base_url, &http.base_url),
+        ));
+        let handled_trades = || async {
+            telemetry
+                .latency
+                .read()
+                .await
+                .summaries()
+                .iter()
+                .find(|summary| {
+                    summary.stream == test_support::trade_stream()
+                        && summary.stage == LatencyStage::ReceiveToHandled
+                })
+                .map(|summary| summary.count)
+                .unwrap_or_default()
+        };
+        tokio::time::timeout(Duration::from_secs(30), async {
+            while handled_trades().await < 8 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("trades were not all handled");
+        connection.abort();
+
+        assert_eq!(dataframe_rwl.read().await.len(), 8);
+
+        assert_eq!(ws.connections(), 2);
+        assert!(ws.pongs() >= 1);
+        let subscription = &ws.subscriptions()[0];
+        assert_eq!(subscription["method"], "SUBSCRIBE");
+        assert_eq!(
+            subscription["params"],
+            serde_json::json!([test_support::trade_stream(), test_support::depth_stream()])
+        );
+        let metrics = &telemetry.metrics;
+        assert_eq!(metrics.reconnects.get(), 1);
+        assert_eq!(metrics.book_resyncs.get(), 2);
+        assert_eq!(metrics.messages.with_label_values(&["trade"]).get(), 8);
+        assert_eq!(
+            metrics.messages.with_label_values(&["depthUpdate"]).get(),
+            2
+        );
+        let book = orderbooks_rwl.read().await;
+        assert!(book.is_valid);
+        assert_eq!(book.last_update_id, 120);
+        let status = telemetry.status.read().await;
+        assert!(status.connected);
+        assert!(status.last_message_at.is_some());
+    }
+}
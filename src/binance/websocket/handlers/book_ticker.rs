@@ -1,15 +1,21 @@
+use std::sync::Arc;
+
 use log::error;
 use serde_json::Value;
 
-use crate::binance::models::book_ticker::BookTicker;
+use crate::{binance::models::book_ticker::BookTicker, monitoring::metrics::Metrics};
 
-pub async fn handle_book_ticker(message: Value) {
+pub async fn handle_book_ticker(message: Value, metrics: Arc<Metrics>) {
     match serde_json::from_value::<BookTicker>(message) {
         Ok(ticker) => {
             println!("{:#?}", ticker);
         }
         Err(e) => {
             error!("Error parsing message: {:?}", e);
+            metrics
+                .parse_errors
+                .with_label_values(&["bookTicker"])
+                .inc();
         }
     }
 }
@@ -1,19 +1,202 @@
-use crate::binance::models::orderbook::{OrderBook, OrderBooksRWL, OrderbookMessage};
-use log::error;
+use std::sync::Arc;
+
+use crate::{
+    binance::{
+        endpoints::Endpoints,
+        models::orderbook::{OrderBook, OrderBooksRWL, OrderbookMessage},
+        rest::{get_depth_snapshot, DEPTH_SNAPSHOT_LIMIT},
+    },
+    model::order_flow::OrderFlowRwl,
+    monitoring::metrics::Metrics,
+};
+use log::{error, info, warn};
 use serde_json::Value;
 
-pub async fn handle_depth_update_message(message: Value, orderbooks_rwl: OrderBooksRWL) {
+/// Applies a depth update to the book and accumulates the order flow it implies. An empty or
+/// invalid book is first rebuilt from a REST snapshot, or from the update itself if the snapshot
+/// can't be fetched. The snapshot is fetched before the book and order flow are locked, so a slow
+/// request doesn't hold up the readers of either.
+pub async fn handle_depth_update_message(
+    message: Value,
+    orderbooks_rwl: OrderBooksRWL,
+    order_flow_rwl: OrderFlowRwl,
+    metrics: Arc<Metrics>,
+    endpoints: &Endpoints,
+) {
     match serde_json::from_value::<OrderbookMessage>(message) {
         Ok(update) => {
-            let mut book = orderbooks_rwl.write().await;
-            if book.is_empty() {
-                *book = OrderBook::new_from_update(update);
+            let needs_resync = {
+                let book = orderbooks_rwl.read().await;
+                book.is_empty() || !book.is_valid
+            };
+            let snapshot = if needs_resync {
+                metrics.book_resyncs.inc();
+                Some(get_depth_snapshot(endpoints, &update.symbol, DEPTH_SNAPSHOT_LIMIT).await)
             } else {
-                book.update(update);
+                None
+            };
+            let mut book = orderbooks_rwl.write().await;
+            let mut order_flow = order_flow_rwl.write().await;
+            if let Some(snapshot) = snapshot {
+                order_flow.reset();
+                match snapshot {
+                    Ok(snapshot) => {
+                        info!(
+                            "Resynced {} book from snapshot {}",
+                            update.symbol, snapshot.last_update_id
+                        );
+                        *book = OrderBook::new_from_snapshot(snapshot);
+                    }
+                    Err(e) => {
+                        warn!("Could not fetch depth snapshot, seeding book from update: {e:?}");
+                        *book = OrderBook::new_from_update(update);
+                        return;
+                    }
+                }
+            }
+            book.update(update);
+            if book.is_valid {
+                order_flow.update(&book);
             }
         }
         Err(e) => {
             error!("Error parsing message: {:?}", e);
+            metrics
+                .parse_errors
+                .with_label_values(&["depthUpdate"])
+                .inc();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        binance::models::orderbook::new_orderbooks_rwl,
+        model::order_flow::new_order_flow_rwl,
+        test_support::{self, dec, mock_http::MockHttpServer},
+    };
+
+    async fn mock_exchange() -> MockHttpServer {
+        let depth = test_support::depth_snapshot(
+            100,
+            &[("100.0", "1"), ("99.9", "2")],
+            &[("100.1", "1"), ("100.2", "2")],
+        );
+        MockHttpServer::start(test_support::exchange_info(), depth).await
+    }
+
+    #[tokio::test]
+    async fn book_is_synced_from_snapshot_and_resynced_after_a_gap() {
+        let http = mock_exchange().await;
+        let endpoints = Endpoints::local("ws://127.0.0.1:1", &http.base_url);
+        let orderbooks_rwl = new_orderbooks_rwl();
+        let order_flow_rwl = new_order_flow_rwl();
+        let metrics = Arc::new(Metrics::new());
+        let apply = |update: Value| {
+            handle_depth_update_message(
+                update,
+                orderbooks_rwl.clone(),
+                order_flow_rwl.clone(),
+                metrics.clone(),
+                &endpoints,
+            )
+        };
+        // older than the snapshot, dropped
+        apply(test_support::depth_update(
+            90,
+            95,
+            89,
+            &[("100.0", "9")],
+            &[],
+        ))
+        .await;
+        {
+            let book = orderbooks_rwl.read().await;
+            assert!(book.is_valid && book.from_snapshot);
+            assert_eq!(book.last_update_id, 100);
+            assert_eq!(book.bids[0].size, dec("1"));
+        }
+        // straddles the snapshot, applied
+        apply(test_support::depth_update(
+            98,
+            105,
+            94,
+            &[("100.0", "3")],
+            &[],
+        ))
+        .await;
+        // in order, removes the best ask
+        apply(test_support::depth_update(
+            106,
+            110,
+            105,
+            &[],
+            &[("100.1", "0")],
+        ))
+        .await;
+        {
+            let book = orderbooks_rwl.read().await;
+            assert!(book.is_valid && !book.from_snapshot);
+            assert_eq!(book.last_update_id, 110);
+            assert_eq!(book.bids[0].size, dec("3"));
+            assert_eq!(book.asks[0].price, dec("100.2"));
         }
+        // a gap invalidates the book and the next update resyncs it
+        apply(test_support::depth_update(120, 125, 115, &[], &[])).await;
+        assert!(!orderbooks_rwl.read().await.is_valid);
+        apply(test_support::depth_update(126, 130, 125, &[], &[])).await;
+        assert_eq!(metrics.book_resyncs.get(), 2);
+        assert_eq!(
+            http.requests()
+                .iter()
+                .filter(|path| path.starts_with("/fapi/v1/depth"))
+                .count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn book_is_seeded_from_the_update_without_a_snapshot() {
+        let endpoints = Endpoints::local("ws://127.0.0.1:1", "http://127.0.0.1:1");
+        let orderbooks_rwl = new_orderbooks_rwl();
+        let order_flow_rwl = new_order_flow_rwl();
+        let metrics = Arc::new(Metrics::new());
+        let update = test_support::depth_update(1, 5, 0, &[("100.0", "1")], &[("100.1", "1")]);
+        handle_depth_update_message(
+            update,
+            orderbooks_rwl.clone(),
+            order_flow_rwl.clone(),
+            metrics.clone(),
+            &endpoints,
+        )
+        .await;
+        let book = orderbooks_rwl.read().await;
+        assert!(book.is_valid);
+        assert_eq!(book.last_update_id, 5);
+        assert_eq!(book.bids[0].price, dec("100.0"));
+        assert_eq!(metrics.book_resyncs.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn unparseable_update_is_counted() {
+        let endpoints = Endpoints::local("ws://127.0.0.1:1", "http://127.0.0.1:1");
+        let metrics = Arc::new(Metrics::new());
+        handle_depth_update_message(
+            serde_json::json!({"e": "depthUpdate"}),
+            new_orderbooks_rwl(),
+            new_order_flow_rwl(),
+            metrics.clone(),
+            &endpoints,
+        )
+        .await;
+        assert_eq!(
+            metrics
+                .parse_errors
+                .with_label_values(&["depthUpdate"])
+                .get(),
+            1
+        );
     }
 }
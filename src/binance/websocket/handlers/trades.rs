@@ -7,7 +7,11 @@ use tokio::sync::Notify;
 
 use crate::{
     binance::models::{orderbook::OrderBooksRWL, trades::Trade},
-    model::data_handling::{Dfrwl, Observation},
+    model::{
+        data_handling::{Dfrwl, Observation},
+        order_flow::OrderFlowRwl,
+    },
+    monitoring::metrics::Metrics,
     ROLLING_WINDOW,
 };
 
@@ -17,17 +21,21 @@ pub async fn handle_trades(
     notify: Arc<Notify>,
     tick_size: Decimal,
     dataframe_rwl: Dfrwl,
+    order_flow_rwl: OrderFlowRwl,
+    metrics: Arc<Metrics>,
 ) {
     match serde_json::from_value::<Trade>(message) {
         Ok(trade) => {
             if let Some(book_features) = orderbooks_rwl.read().await.clone().to_features(tick_size)
             {
                 let mut write = dataframe_rwl.write().await;
-                let obs = Observation::from_trade_and_book(trade.to_features(), book_features);
-                write.data.push(obs);
-                write.calculate_rolling_features();
+                let order_flow = order_flow_rwl.write().await.take_features();
+                let obs = Observation::from_trade_and_book(trade.to_features(), book_features)
+                    .with_order_flow(order_flow);
+                write.push(obs);
+                metrics.dataframe_len.set(write.len() as i64);
                 //info the last observation
-                if write.data.len() % (2 * ROLLING_WINDOW) == 0 {
+                if write.pushed() % (2 * ROLLING_WINDOW) == 0 {
                     notify.notify_one();
                 }
             } else {
@@ -36,6 +44,7 @@ pub async fn handle_trades(
         }
         Err(e) => {
             error!("Error parsing trade message: {:?}", e);
+            metrics.parse_errors.with_label_values(&["trade"]).inc();
         }
     }
 }
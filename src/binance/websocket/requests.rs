@@ -143,7 +143,8 @@ impl DataRequest {
             streams,
         }
     }
-    pub fn get_ws_urls(&self) -> Vec<String> {
+    /// Combined stream URLs on each of the given base URLs.
+    pub fn get_ws_urls(&self, base_urls: &[String]) -> Vec<String> {
         let individual_streams = self
             .streams
             .iter()
@@ -151,9 +152,7 @@ impl DataRequest {
             .collect::<Vec<String>>();
         let combined_streams = individual_streams.join("/");
         let path = format!("/stream?streams={}", combined_streams);
-        return self
-            .asset_type
-            .get_ws_base_url_list()
+        return base_urls
             .iter()
             .map(|base_url| {
                 url::Url::parse(&format!("{}{}", base_url, path))
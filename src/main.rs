@@ -1,33 +1,197 @@
 use crate::{
-    binance::models::{model_config::new_model_data, orderbook::new_orderbooks_rwl},
+    binance::{
+        endpoints::Endpoints,
+        models::{model_config::new_model_data, orderbook::new_orderbooks_rwl},
+    },
     model::{
-        data_handling::new_dataframe_rwl, features::manage_model, inference::make_predictions,
+        data_handling::new_dataframe_rwl,
+        drift::DriftAction,
+        export::{export, export_periodically, ExportArgs},
+        features::manage_model,
+        inference::make_predictions,
+        introspection::{inspect_model, InspectModelArgs},
+        labeling::Labeler,
+        predictor::{gbdt::GbdtParams, ModelKind},
+        registry::{run_models_command, ModelRegistry, ModelsCommand},
+        rolling::Window,
+        search::{search, SearchArgs},
+        store::Retention,
+        train::{train, TrainArgs},
+        weighting::SampleWeighting,
     },
+    monitoring::{latency::log_latency_report, new_telemetry, server},
 };
 mod binance;
 use binance::websocket::connection::establish_and_persist;
-use log::{debug, info, warn};
+use clap::{Parser, Subcommand};
+use log::{debug, error, info, warn};
+use rust_decimal::Decimal;
 use tokio::sync::mpsc;
 mod model;
+mod monitoring;
+#[cfg(test)]
+mod test_support;
 mod utils;
 pub const MIN_TICKS_FOR_SIGNAL: i32 = 30;
+/// Targets, in ticks, the classifier labels as up or down rather than flat.
+pub const CLASS_UP_TICKS: f32 = 2.0;
+pub const CLASS_DOWN_TICKS: f32 = 2.0;
+/// Probability of up or down a classifier needs before trading on it.
+pub const MIN_CLASS_PROBABILITY: f32 = 0.5;
+/// Prediction of a triple-barrier or meta label a regressor trades on, halfway to a take-profit
+/// label of 1.
+pub const MIN_BARRIER_SIGNAL: f32 = 0.5;
 pub const ROLLING_WINDOW: usize = 1000;
+/// Windows of the rolling trade size, price and book ratio features.
+pub const NOTIONAL_WINDOW: Window = Window::Time(60_000);
+pub const PRICE_WINDOW: Window = Window::Time(60_000);
+pub const BOOK_RATIO_WINDOW: Window = Window::Time(10_000);
+/// How far ahead the target looks, and how long positions are held, unless `LABEL_HORIZON` is set.
+pub const TARGET_HORIZON: Window = Window::Time(60_000);
+/// Barriers of the triple-barrier and meta labels, in ticks from the entry price.
+pub const TAKE_PROFIT_TICKS: i64 = 5;
+pub const STOP_TICKS: i64 = 5;
+/// Top of book imbalance the primary signal of meta labels trades on, 0.3.
+pub const META_IMBALANCE_THRESHOLD: Decimal = Decimal::from_parts(3, 0, 0, false, 1);
+/// Walk-forward folds a new model is scored on before it can replace the current one.
+pub const WALK_FORWARD_FOLDS: usize = 4;
+/// Gap between the training and test rows of each fold.
+pub const EMBARGO: Window = Window::Time(10_000);
+/// Tail quantile the `winsorize` preprocessing step limits features to, on each side.
+pub const WINSORIZE_QUANTILE: f32 = 0.01;
+/// Bound of the `clip` preprocessing step, in scaled units.
+pub const CLIP_LIMIT: f32 = 5.0;
+/// Bins of the training distribution saved with each model, and the live feature vectors it is
+/// compared with every `DRIFT_CHECK_INTERVAL` predictions.
+pub const DRIFT_BINS: usize = 10;
+pub const DRIFT_WINDOW: usize = 1000;
+pub const DRIFT_CHECK_INTERVAL: usize = 100;
+/// PSI and KS a feature warns at, and halts trading at with `DRIFT_ACTION=halt`.
+pub const PSI_WARN_THRESHOLD: f32 = 0.1;
+pub const KS_WARN_THRESHOLD: f32 = 0.1;
+pub const PSI_HALT_THRESHOLD: f32 = 0.25;
+pub const KS_HALT_THRESHOLD: f32 = 0.2;
 pub const TRAINING_INTERVAL: u64 = 60 * 10;
+pub const LATENCY_REPORT_INTERVAL: u64 = 60;
+/// Address of the HTTP API unless `HTTP_ADDR` is set, reachable from this host only.
+pub const HTTP_ADDR: &str = "127.0.0.1:9898";
+pub const DATA_FRESHNESS_MS: i64 = 10_000;
+/// How long a REST request, such as a depth snapshot, may take before it is abandoned.
+pub const REST_TIMEOUT_MS: u64 = 5_000;
+pub const MAX_OBSERVATIONS: usize = 2_000_000;
+pub const OBSERVATION_MAX_AGE_MS: i64 = 24 * 60 * 60 * 1000;
+/// Default directory of the model registry, overridden with `MODEL_REGISTRY_DIR`.
+pub const MODEL_REGISTRY_DIR: &str = "models";
+/// Seconds between exports of the training data, and their default directory, overridden with
+/// `EXPORT_DIR`.
+pub const EXPORT_INTERVAL: u64 = 60 * 60;
+pub const EXPORT_DIR: &str = "exports";
 mod log_config;
 
+#[derive(Parser)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Trade live, the default.
+    Run,
+    /// Search gbdt hyperparameters on recorded observations.
+    Search(SearchArgs),
+    /// List, inspect, promote or roll back models in the registry.
+    Models {
+        #[command(subcommand)]
+        command: ModelsCommand,
+    },
+    /// Print the feature importance and tree report of a registered model.
+    InspectModel(InspectModelArgs),
+    /// Write recorded observations with their features, targets and weights to CSV or Parquet.
+    Export(ExportArgs),
+    /// Train a model on recorded observations or exports and add it to the registry.
+    Train(TrainArgs),
+}
+
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
     log_config::configure_log(log::LevelFilter::Info);
+    match cli.command.unwrap_or(Command::Run) {
+        Command::Run => run().await,
+        Command::Search(args) => {
+            if let Err(e) = search(&args) {
+                error!("Search failed: {:?}", e);
+            }
+        }
+        Command::Models { command } => {
+            if let Err(e) = run_models_command(&ModelRegistry::from_env(), &command) {
+                error!("{}", e);
+            }
+        }
+        Command::InspectModel(args) => {
+            if let Err(e) = inspect_model(&ModelRegistry::from_env(), &args) {
+                error!("{}", e);
+            }
+        }
+        Command::Export(args) => {
+            if let Err(e) = export(&args) {
+                error!("Export failed: {:?}", e);
+            }
+        }
+        Command::Train(args) => {
+            if let Err(e) = train(&args) {
+                error!("Training failed: {:?}", e);
+            }
+        }
+    }
+}
+
+async fn run() {
     info!("Starting program");
     let symbol = "BTCUSDT";
-    let exchange_info = binance::rest::get_exchange_info().await.unwrap();
-    let dataframe_rwl = new_dataframe_rwl();
+    let endpoints = Endpoints::from_env();
+    let exchange_info = binance::rest::get_exchange_info(&endpoints).await.unwrap();
+    let dataframe_rwl = new_dataframe_rwl(Retention {
+        max_len: Some(MAX_OBSERVATIONS),
+        max_age_ms: Some(OBSERVATION_MAX_AGE_MS),
+        spill_path: std::env::var_os("OBSERVATIONS_SPILL_PATH").map(Into::into),
+    });
     let orderbooks_rwl = new_orderbooks_rwl();
     //this order receive variable can be read in another thread to send orders.
     let (order_send, _order_receive): (mpsc::Sender<String>, mpsc::Receiver<String>) =
         mpsc::channel(10);
-    let model_mutex = new_model_data();
+    let kind = match ModelKind::from_env() {
+        Ok(kind) => kind,
+        Err(e) => {
+            error!("Could not create the model: {}", e);
+            return;
+        }
+    };
+    let labeler = match Labeler::from_env() {
+        Ok(labeler) => labeler,
+        Err(e) => {
+            error!("Could not create the model: {}", e);
+            return;
+        }
+    };
+    let gbdt_params = match GbdtParams::from_file_or_default() {
+        Ok(gbdt_params) => gbdt_params,
+        Err(e) => {
+            error!("Could not read the gbdt parameters: {}", e);
+            return;
+        }
+    };
+    let model_swap = match new_model_data(kind, labeler, gbdt_params, ModelRegistry::from_env()) {
+        Ok(model_swap) => model_swap,
+        Err(e) => {
+            error!("Could not create the model: {}", e);
+            return;
+        }
+    };
+    let telemetry = new_telemetry(symbol);
     let notify = std::sync::Arc::new(tokio::sync::Notify::new());
+    let export_trigger = std::sync::Arc::new(tokio::sync::Notify::new());
     match exchange_info
         .symbols
         .into_iter()
@@ -40,17 +204,29 @@ async fn main() {
                 _ = tokio::signal::ctrl_c() => {
                     warn!("Ctrl-C received, exiting");
                 },
-                _ = tokio::spawn(establish_and_persist(orderbooks_rwl.clone(),market.clone(),notify.clone(),dataframe_rwl.clone())) => {
+                _ = tokio::spawn(establish_and_persist(orderbooks_rwl.clone(),market.clone(),notify.clone(),dataframe_rwl.clone(),telemetry.clone(),endpoints)) => {
                     warn!("Websocket connection closed");
                 }
-                _ = tokio::spawn(make_predictions(dataframe_rwl.clone(),market.clone(),model_mutex.clone(),notify,order_send)) => {
+                _ = tokio::spawn(make_predictions(dataframe_rwl.clone(),market.clone(),model_swap.clone(),notify,order_send,DriftAction::from_env(),telemetry.clone())) => {
                     info!("Exiting prediction thread");
                 }
-                _ = tokio::spawn(manage_model(dataframe_rwl.clone(),market,model_mutex.clone())) => {
+                _ = tokio::spawn(log_latency_report(telemetry.latency.clone(),LATENCY_REPORT_INTERVAL)) => {
+                    warn!("Latency report thread closed");
+                }
+                _ = tokio::spawn(export_periodically(dataframe_rwl.clone(),market.clone(),model_swap.clone(),export_trigger.clone())) => {
+                    warn!("Export thread closed");
+                }
+                _ = tokio::spawn(manage_model(dataframe_rwl.clone(),market,model_swap.clone(),SampleWeighting::from_env(),telemetry.clone())) => {
                     warn!("Model thread closed");
                 }
+                _ = tokio::spawn(server::serve(server::addr_from_env(),telemetry.clone(),orderbooks_rwl.clone(),export_trigger)) => {
+                    warn!("HTTP server closed");
+                }
 
             }
+            // whichever task ended the run, keep the observations still in memory
+            let mut df = dataframe_rwl.write().await;
+            tokio::task::block_in_place(|| df.spill_retained());
         }
         None => {
             warn!("Market {} not found", symbol);
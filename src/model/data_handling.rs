@@ -1,14 +1,24 @@
-use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
-use rust_decimal::{prelude::ToPrimitive, Decimal, MathematicalOps};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
 use crate::{
     binance::models::{orderbook::BookFeatures, trades::TradeFeatures},
-    ROLLING_WINDOW,
+    BOOK_RATIO_WINDOW, NOTIONAL_WINDOW, PRICE_WINDOW,
+};
+
+use super::{
+    feature_registry::{checked_features, FeatureError},
+    labeling::Labeler,
+    order_flow::OrderFlowFeatures,
+    rolling::RollingWindow,
+    store::{ObservationStore, Retention, Snapshot},
+    time_features::MoveClock,
+    trade_flow::TradeFlow,
 };
 pub type Dfrwl = Arc<RwLock<FeatureDataFrame>>;
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Observation {
     pub timestamp: i64,
     pub price: Decimal,
@@ -21,6 +31,41 @@ pub struct Observation {
     pub bids_asks_ratio: Decimal,
     pub bid_notional: Decimal,
     pub ask_notional: Decimal,
+    /// Not a model input, forward returns are measured on it.
+    pub mid_price: Decimal,
+    pub spread_ticks: Decimal,
+    pub microprice_ticks_from_mid: Decimal,
+    pub weighted_mid_ticks_from_mid: Decimal,
+    pub imbalance_1: Decimal,
+    pub imbalance_5: Decimal,
+    pub imbalance_10: Decimal,
+    pub imbalance_20: Decimal,
+    pub bid_depth_near_mid: Decimal,
+    pub ask_depth_near_mid: Decimal,
+    pub bid_slope: Decimal,
+    pub ask_slope: Decimal,
+    pub ticks_to_largest_bid: Decimal,
+    pub ticks_to_largest_ask: Decimal,
+    pub ofi_1: Decimal,
+    pub ofi_multi: Decimal,
+    /// The windowed order flow, trade flow and move clock features are `None` until their window
+    /// is full.
+    pub ofi_rolling: Option<Decimal>,
+    pub bid_depletion_rate: Option<Decimal>,
+    pub bid_replenishment_rate: Option<Decimal>,
+    pub ask_depletion_rate: Option<Decimal>,
+    pub ask_replenishment_rate: Option<Decimal>,
+    pub vpin: Option<Decimal>,
+    pub trade_rate: Option<Decimal>,
+    pub inter_arrival_mean_ms: Option<Decimal>,
+    pub inter_arrival_std_ms: Option<Decimal>,
+    pub buy_share_1s: Option<Decimal>,
+    pub buy_share_10s: Option<Decimal>,
+    pub buy_share_60s: Option<Decimal>,
+    pub large_trade_90: Option<Decimal>,
+    pub large_trade_99: Option<Decimal>,
+    pub kyle_lambda: Option<Decimal>,
+    pub seconds_since_large_move: Option<Decimal>,
     pub rolling_qty: Option<Decimal>,
     pub mean_qty: Option<Decimal>,
     pub qty_std: Option<Decimal>,
@@ -44,6 +89,38 @@ impl Observation {
             bids_asks_ratio: book_features.bids_asks_ratio,
             bid_notional: book_features.bid_notional,
             ask_notional: book_features.ask_notional,
+            mid_price: book_features.mid_price,
+            spread_ticks: book_features.spread_ticks,
+            microprice_ticks_from_mid: book_features.microprice_ticks_from_mid,
+            weighted_mid_ticks_from_mid: book_features.weighted_mid_ticks_from_mid,
+            imbalance_1: book_features.imbalance_1,
+            imbalance_5: book_features.imbalance_5,
+            imbalance_10: book_features.imbalance_10,
+            imbalance_20: book_features.imbalance_20,
+            bid_depth_near_mid: book_features.bid_depth_near_mid,
+            ask_depth_near_mid: book_features.ask_depth_near_mid,
+            bid_slope: book_features.bid_slope,
+            ask_slope: book_features.ask_slope,
+            ticks_to_largest_bid: book_features.ticks_to_largest_bid,
+            ticks_to_largest_ask: book_features.ticks_to_largest_ask,
+            ofi_1: Decimal::ZERO,
+            ofi_multi: Decimal::ZERO,
+            ofi_rolling: None,
+            bid_depletion_rate: None,
+            bid_replenishment_rate: None,
+            ask_depletion_rate: None,
+            ask_replenishment_rate: None,
+            vpin: None,
+            trade_rate: None,
+            inter_arrival_mean_ms: None,
+            inter_arrival_std_ms: None,
+            buy_share_1s: None,
+            buy_share_10s: None,
+            buy_share_60s: None,
+            large_trade_90: None,
+            large_trade_99: None,
+            kyle_lambda: None,
+            seconds_since_large_move: None,
             rolling_qty: None,
             mean_qty: None,
             qty_std: None,
@@ -54,9 +131,41 @@ impl Observation {
             target: None,
         }
     }
-    ///Returns true if the `Observation` has rolling features.
+    /// Sets the order flow accumulated since the previous observation.
+    pub fn with_order_flow(mut self, order_flow: OrderFlowFeatures) -> Self {
+        self.ofi_1 = order_flow.ofi_1;
+        self.ofi_multi = order_flow.ofi_multi;
+        self.ofi_rolling = order_flow.ofi_rolling;
+        self.bid_depletion_rate = order_flow.bid_depletion_rate;
+        self.bid_replenishment_rate = order_flow.bid_replenishment_rate;
+        self.ask_depletion_rate = order_flow.ask_depletion_rate;
+        self.ask_replenishment_rate = order_flow.ask_replenishment_rate;
+        self
+    }
+    ///Returns true if the `Observation` has rolling features, every windowed feature once its
+    /// window is full.
     pub fn has_rolling_features(&self) -> bool {
-        self.rolling_qty.is_some()
+        [
+            self.ofi_rolling,
+            self.bid_depletion_rate,
+            self.bid_replenishment_rate,
+            self.ask_depletion_rate,
+            self.ask_replenishment_rate,
+            self.vpin,
+            self.trade_rate,
+            self.inter_arrival_mean_ms,
+            self.inter_arrival_std_ms,
+            self.buy_share_1s,
+            self.buy_share_10s,
+            self.buy_share_60s,
+            self.large_trade_90,
+            self.large_trade_99,
+            self.kyle_lambda,
+            self.seconds_since_large_move,
+        ]
+        .iter()
+        .all(Option::is_some)
+            && self.rolling_qty.is_some()
             && self.mean_qty.is_some()
             && self.qty_std.is_some()
             && self.mean_price.is_some()
@@ -67,120 +176,143 @@ impl Observation {
     pub fn has_target(&self) -> bool {
         self.target.is_some()
     }
-    pub fn to_training_data(&self) -> [f32; 18] {
-        [
-            self.timestamp as f32,
-            self.price.to_f32().unwrap(),
-            self.net_qty.to_f32().unwrap(),
-            self.notional.to_f32().unwrap(),
-            self.bid_total.to_f32().unwrap(),
-            self.num_ticks_from_best_bid.to_f32().unwrap(),
-            self.ask_total.to_f32().unwrap(),
-            self.num_ticks_from_best_ask.to_f32().unwrap(),
-            self.bids_asks_ratio.to_f32().unwrap(),
-            self.bid_notional.to_f32().unwrap(),
-            self.ask_notional.to_f32().unwrap(),
-            self.rolling_qty.unwrap().to_f32().unwrap(),
-            self.mean_qty.unwrap().to_f32().unwrap(),
-            self.qty_std.unwrap().to_f32().unwrap(),
-            self.mean_price.unwrap().to_f32().unwrap(),
-            self.price_std.unwrap().to_f32().unwrap(),
-            self.book_ratio_rolling_mean.unwrap().to_f32().unwrap(),
-            self.rolling_qty_abs.unwrap().to_f32().unwrap(),
-        ]
+    /// The model inputs, in [`FEATURES`](super::feature_registry::FEATURES) order, or the first
+    /// feature that is undefined or not finite.
+    pub fn to_training_data(&self) -> Result<Vec<f32>, FeatureError> {
+        checked_features(self)
     }
 }
 
-pub fn new_dataframe_rwl() -> Dfrwl {
-    let df = FeatureDataFrame::new_empty();
+pub fn new_dataframe_rwl(retention: Retention) -> Dfrwl {
+    let df = FeatureDataFrame::new_empty(retention);
     Arc::new(RwLock::new(df))
 }
 
+/// Rolling accumulators over each feature's window, and the trade flow and price moves before
+/// each observation.
 #[derive(Debug, Clone)]
+struct RollingFeatures {
+    notional: RollingWindow,
+    notional_abs: RollingWindow,
+    price: RollingWindow,
+    bids_asks_ratio: RollingWindow,
+    trade_flow: TradeFlow,
+    move_clock: MoveClock,
+}
+impl RollingFeatures {
+    fn new() -> Self {
+        Self {
+            notional: RollingWindow::over(NOTIONAL_WINDOW),
+            notional_abs: RollingWindow::over(NOTIONAL_WINDOW),
+            price: RollingWindow::over(PRICE_WINDOW),
+            bids_asks_ratio: RollingWindow::over(BOOK_RATIO_WINDOW),
+            trade_flow: TradeFlow::default(),
+            move_clock: MoveClock::default(),
+        }
+    }
+    /// Sets the trade flow and time features of `observation`, and each rolling feature whose
+    /// window is full.
+    fn fill(&self, observation: &mut Observation) {
+        self.trade_flow.fill(observation);
+        self.move_clock.fill(observation);
+        if self.notional.is_full() {
+            observation.rolling_qty = Some(self.notional.sum());
+            observation.mean_qty = self.notional.mean();
+            observation.qty_std = self.notional.std();
+            observation.rolling_qty_abs = Some(self.notional_abs.sum());
+        }
+        if self.price.is_full() {
+            observation.mean_price = self.price.mean();
+            observation.price_std = self.price.std();
+        }
+        if self.bids_asks_ratio.is_full() {
+            observation.book_ratio_rolling_mean = self.bids_asks_ratio.mean();
+        }
+    }
+    fn push(&mut self, observation: &Observation) {
+        let timestamp = observation.timestamp;
+        self.notional.push_at(timestamp, observation.notional);
+        self.notional_abs
+            .push_at(timestamp, observation.notional.abs());
+        self.price.push_at(timestamp, observation.price);
+        self.bids_asks_ratio
+            .push_at(timestamp, observation.bids_asks_ratio);
+        self.trade_flow.push(observation);
+        self.move_clock.push(observation);
+    }
+}
+
+/// The live observations, bounded by a [`Retention`], with their rolling features computed as
+/// they arrive.
+#[derive(Debug)]
 pub struct FeatureDataFrame {
-    pub data: Vec<Observation>,
+    store: ObservationStore,
+    rolling: RollingFeatures,
 }
 impl FeatureDataFrame {
-    pub fn new_empty() -> Self {
+    pub fn new_empty(retention: Retention) -> Self {
+        Self {
+            store: ObservationStore::new(retention),
+            rolling: RollingFeatures::new(),
+        }
+    }
+    /// Appends an observation, computing its rolling features over the previous observations.
+    pub fn push(&mut self, mut observation: Observation) {
+        self.rolling.fill(&mut observation);
+        self.rolling.push(&observation);
+        self.store.push(observation);
+    }
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+    pub fn pushed(&self) -> usize {
+        self.store.pushed()
+    }
+    pub fn last(&self) -> Option<&Observation> {
+        self.store.last()
+    }
+    pub fn snapshot(&self) -> Snapshot {
+        self.store.snapshot()
+    }
+    /// Writes the observations still in memory to the spill file, if any, before exiting.
+    pub fn spill_retained(&mut self) {
+        self.store.spill_retained();
+    }
+}
+
+/// An owned copy of observations to build training sets from, detached from the live store.
+#[derive(Debug, Clone, Default)]
+pub struct TrainingFrame {
+    pub data: Vec<Observation>,
+}
+impl From<Snapshot> for TrainingFrame {
+    fn from(snapshot: Snapshot) -> Self {
         Self {
-            data: Vec::with_capacity(10000000),
+            data: snapshot.to_vec(),
         }
     }
+}
+impl TrainingFrame {
+    /// Computes the rolling features of any observations that don't have them, in a single pass.
     pub fn calculate_rolling_features(&mut self) {
-        if self.data.is_empty() || self.data.len() < ROLLING_WINDOW + 2 {
-            return;
-        };
-        let max_index = self.data.len() - 1;
-        for i in (ROLLING_WINDOW..max_index).rev() {
-            if self.data[i].has_rolling_features() {
-                continue;
+        let mut rolling = RollingFeatures::new();
+        for observation in self.data.iter_mut() {
+            if !observation.has_rolling_features() {
+                rolling.fill(observation);
             }
-            let net_qty_rolling = self.data[i - ROLLING_WINDOW..i]
-                .par_iter()
-                .map(|x| x.notional)
-                .collect::<Vec<_>>();
-            let rolling_qty = net_qty_rolling.par_iter().sum::<Decimal>();
-            let mean_qty = rolling_qty / Decimal::from(ROLLING_WINDOW);
-            let qty_std = Decimal::sqrt(
-                &(net_qty_rolling
-                    .par_iter()
-                    .map(|x| (x - mean_qty).powi(2))
-                    .sum::<Decimal>()
-                    / Decimal::from(ROLLING_WINDOW)),
-            );
-            let last_price_rolling = self.data[i - ROLLING_WINDOW..i]
-                .par_iter()
-                .map(|x| x.price)
-                .collect::<Vec<_>>();
-            let mean_price =
-                last_price_rolling.par_iter().sum::<Decimal>() / Decimal::from(ROLLING_WINDOW);
-            let price_std = Decimal::sqrt(
-                &(last_price_rolling
-                    .par_iter()
-                    .map(|x| (x - mean_price).powi(2))
-                    .sum::<Decimal>()
-                    / Decimal::from(ROLLING_WINDOW)),
-            );
-            let book_ratio_rolling_mean = self.data[i - ROLLING_WINDOW..i]
-                .par_iter()
-                .map(|x| x.bids_asks_ratio)
-                .collect::<Vec<_>>()
-                .par_iter()
-                .sum::<Decimal>()
-                / Decimal::from(ROLLING_WINDOW);
-            let rolling_qty_abs = self.data[i - ROLLING_WINDOW..i]
-                .par_iter()
-                .map(|x| x.notional.abs())
-                .collect::<Vec<_>>()
-                .par_iter()
-                .sum::<Decimal>();
-            self.data[i].rolling_qty = Some(rolling_qty);
-            self.data[i].mean_qty = Some(mean_qty);
-            self.data[i].qty_std = qty_std;
-            self.data[i].mean_price = Some(mean_price);
-            self.data[i].price_std = price_std;
-            self.data[i].book_ratio_rolling_mean = Some(book_ratio_rolling_mean);
-            self.data[i].rolling_qty_abs = Some(rolling_qty_abs);
+            rolling.push(observation);
         }
         //self.drop_na_without_target();
     }
-    pub fn add_target_value(&mut self, tick_size: Decimal) {
-        if self.data.is_empty() || self.data.len() < ROLLING_WINDOW {
-            return;
-        };
-        for i in ROLLING_WINDOW..self.data.len() - 1 {
-            let start_of_period = &self.data[i - ROLLING_WINDOW].price;
-            let price_rp = self.data[i - ROLLING_WINDOW..i]
-                .par_iter()
-                .map(|x| x.price)
-                .collect::<Vec<_>>();
-            let highest_price = price_rp.par_iter().max().unwrap();
-            let lowest_price = price_rp.par_iter().min().unwrap();
-            let distance_to_high = (highest_price - start_of_period) / tick_size;
-            let distance_to_low = (start_of_period - lowest_price) / tick_size;
-            let diff = distance_to_high - distance_to_low;
-            self.data[i - ROLLING_WINDOW].target = Some(diff);
-        }
+    /// Computes the rolling features and targets, keeping only the rows a model can be trained on.
+    pub fn prepare(&mut self, labeler: &Labeler, tick_size: Decimal) {
+        self.calculate_rolling_features();
+        self.drop_na_without_target();
+        self.add_target_value(labeler, tick_size);
+    }
+    /// Sets the target of each observation with `labeler`, and drops observations without one.
+    pub fn add_target_value(&mut self, labeler: &Labeler, tick_size: Decimal) {
+        labeler.label(&mut self.data, tick_size);
         self.drop_na_with_target();
     }
     pub fn drop_na_without_target(&mut self) {
@@ -193,3 +325,116 @@ impl FeatureDataFrame {
             .retain(|x| x.has_rolling_features() && x.has_target());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::MathematicalOps;
+
+    use super::*;
+    use crate::{
+        model::rolling::Window,
+        test_support::{dec, observations},
+        ROLLING_WINDOW, TARGET_HORIZON,
+    };
+
+    fn mean_and_std(values: &[Decimal]) -> (Decimal, Decimal) {
+        let n = Decimal::from(values.len());
+        let mean = values.iter().sum::<Decimal>() / n;
+        let variance = values.iter().map(|x| (x - mean).powi(2)).sum::<Decimal>() / n;
+        (mean, variance.sqrt().unwrap())
+    }
+
+    fn assert_close(left: Option<Decimal>, right: Decimal) {
+        assert!((left.unwrap() - right).abs() < dec("0.000000000001"));
+    }
+
+    /// The observations in `window` before row `i`.
+    fn window_before(rows: &[Observation], i: usize, window: Window) -> &[Observation] {
+        let latest = rows[i - 1].timestamp;
+        let start = (0..i)
+            .find(|j| window.contains(i - 1 - j, latest - rows[*j].timestamp))
+            .unwrap();
+        &rows[start..i]
+    }
+
+    #[test]
+    fn pushed_observations_get_features_of_the_previous_window() {
+        let rows = observations(ROLLING_WINDOW + 300);
+        let mut df = FeatureDataFrame::new_empty(Retention::default());
+        for row in rows.iter().cloned() {
+            df.push(row);
+        }
+        let pushed = df.snapshot().to_vec();
+        assert!(!pushed[1].has_rolling_features());
+        for i in [ROLLING_WINDOW, ROLLING_WINDOW + 1, ROLLING_WINDOW + 299] {
+            let window = window_before(&rows, i, NOTIONAL_WINDOW);
+            let notional: Vec<Decimal> = window.iter().map(|x| x.notional).collect();
+            let prices: Vec<Decimal> = window_before(&rows, i, PRICE_WINDOW)
+                .iter()
+                .map(|x| x.price)
+                .collect();
+            let ratios: Vec<Decimal> = window_before(&rows, i, BOOK_RATIO_WINDOW)
+                .iter()
+                .map(|x| x.bids_asks_ratio)
+                .collect();
+            let (mean_qty, qty_std) = mean_and_std(&notional);
+            let (mean_price, price_std) = mean_and_std(&prices);
+            let row = &pushed[i];
+            assert_eq!(row.rolling_qty, Some(notional.iter().sum()));
+            assert_eq!(row.mean_qty, Some(mean_qty));
+            assert_close(row.qty_std, qty_std);
+            assert_eq!(row.mean_price, Some(mean_price));
+            assert_close(row.price_std, price_std);
+            assert_eq!(
+                row.rolling_qty_abs,
+                Some(notional.iter().map(|x| x.abs()).sum())
+            );
+            assert_close(
+                row.book_ratio_rolling_mean,
+                ratios.iter().sum::<Decimal>() / Decimal::from(ratios.len()),
+            );
+        }
+        let mut batch = TrainingFrame { data: rows };
+        batch.calculate_rolling_features();
+        for (pushed, batched) in pushed.iter().zip(batch.data.iter()) {
+            assert_eq!(pushed.rolling_qty, batched.rolling_qty);
+            assert_eq!(pushed.price_std, batched.price_std);
+        }
+    }
+
+    #[test]
+    fn target_is_the_range_skew_of_the_horizon() {
+        let tick_size = dec("0.1");
+        let rows = observations(2 * ROLLING_WINDOW + 50);
+        let mut df = TrainingFrame { data: rows.clone() };
+        df.calculate_rolling_features();
+        df.drop_na_without_target();
+        let with_features = df.data.len();
+        df.add_target_value(&Labeler::default(), tick_size);
+        assert!(!df.data.is_empty() && df.data.len() < with_features);
+        for row in df.data.iter() {
+            let start = rows
+                .iter()
+                .position(|x| x.timestamp == row.timestamp)
+                .unwrap();
+            let end = (start..rows.len())
+                .find(|j| !TARGET_HORIZON.contains(j - start, rows[*j].timestamp - row.timestamp))
+                .unwrap();
+            // the window closes before the last observation
+            assert!(end + 1 < rows.len());
+            let window = &rows[start..end];
+            let high = window.iter().map(|x| x.price).max().unwrap();
+            let low = window.iter().map(|x| x.price).min().unwrap();
+            let expected = (high - row.price) / tick_size - (row.price - low) / tick_size;
+            assert_eq!(row.target, Some(expected));
+        }
+        // the next observation's horizon doesn't close before the last one
+        let last = df.data.last().unwrap().timestamp;
+        let next = rows.iter().position(|x| x.timestamp > last).unwrap();
+        let before_last = rows.len() - 2;
+        assert!(TARGET_HORIZON.contains(
+            before_last - next,
+            rows[before_last].timestamp - rows[next].timestamp
+        ));
+    }
+}
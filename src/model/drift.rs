@@ -0,0 +1,255 @@
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{DRIFT_BINS, DRIFT_CHECK_INTERVAL, DRIFT_WINDOW};
+
+use super::feature_registry::FEATURES;
+
+/// Smallest bin share PSI is computed with, so empty bins don't divide by zero.
+const MIN_SHARE: f64 = 1e-4;
+
+/// What inference does when a feature drifts past the halt thresholds, selected with
+/// `DRIFT_ACTION`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DriftAction {
+    /// Log a warning and keep trading.
+    #[default]
+    Warn,
+    /// Stop opening positions until the drift is back under the thresholds.
+    Halt,
+}
+impl DriftAction {
+    pub fn from_env() -> Self {
+        match std::env::var("DRIFT_ACTION") {
+            Ok(action) => match action.to_lowercase().as_str() {
+                "warn" => Self::Warn,
+                "halt" => Self::Halt,
+                _ => panic!("unknown drift action {}", action),
+            },
+            Err(_) => Self::default(),
+        }
+    }
+}
+
+/// Distribution of one feature over the training rows: the share of rows in each bin between
+/// the training quantiles.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureDistribution {
+    pub name: String,
+    /// Upper bounds of every bin but the last, ascending.
+    pub edges: Vec<f32>,
+    pub shares: Vec<f32>,
+}
+impl FeatureDistribution {
+    fn fit(name: &str, values: &[f32]) -> Self {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f32::total_cmp);
+        let mut edges: Vec<f32> = (1..DRIFT_BINS)
+            .filter_map(|bin| {
+                let index = (bin * sorted.len()) / DRIFT_BINS;
+                sorted.get(index.saturating_sub(1)).copied()
+            })
+            .collect();
+        edges.dedup();
+        let mut distribution = Self {
+            name: name.to_string(),
+            shares: vec![0.0; edges.len() + 1],
+            edges,
+        };
+        let counts = distribution.counts(values);
+        distribution.shares = shares(&counts, values.len());
+        distribution
+    }
+    fn bin(&self, value: f32) -> usize {
+        self.edges.partition_point(|edge| *edge < value)
+    }
+    fn counts(&self, values: &[f32]) -> Vec<usize> {
+        let mut counts = vec![0; self.shares.len()];
+        for value in values {
+            counts[self.bin(*value)] += 1;
+        }
+        counts
+    }
+}
+
+fn shares(counts: &[usize], total: usize) -> Vec<f32> {
+    counts
+        .iter()
+        .map(|count| *count as f32 / total.max(1) as f32)
+        .collect()
+}
+
+/// Population stability index of `actual` bin shares against `expected` ones.
+fn psi(expected: &[f32], actual: &[f32]) -> f32 {
+    expected
+        .iter()
+        .zip(actual)
+        .map(|(expected, actual)| {
+            let (expected, actual) = (
+                (*expected as f64).max(MIN_SHARE),
+                (*actual as f64).max(MIN_SHARE),
+            );
+            (actual - expected) * (actual / expected).ln()
+        })
+        .sum::<f64>() as f32
+}
+
+/// Kolmogorov-Smirnov distance of two binned distributions, the largest gap between their
+/// cumulative shares at the bin edges.
+fn ks(expected: &[f32], actual: &[f32]) -> f32 {
+    let (mut expected_cdf, mut actual_cdf, mut distance) = (0.0f32, 0.0f32, 0.0f32);
+    for (expected, actual) in expected.iter().zip(actual) {
+        expected_cdf += expected;
+        actual_cdf += actual;
+        distance = distance.max((expected_cdf - actual_cdf).abs());
+    }
+    distance
+}
+
+/// Distributions of every feature over the rows a model was fitted on, saved with the model so
+/// inference can tell when live features no longer look like them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TrainingDistribution {
+    pub features: Vec<FeatureDistribution>,
+}
+impl TrainingDistribution {
+    pub fn fit(x: &[Vec<f32>]) -> Self {
+        Self {
+            features: FEATURES
+                .iter()
+                .enumerate()
+                .map(|(j, f)| {
+                    let values: Vec<f32> = x.iter().map(|row| row[j]).collect();
+                    FeatureDistribution::fit(f.name, &values)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// How far a feature's live distribution is from its training one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeatureDrift {
+    pub name: String,
+    pub psi: f32,
+    pub ks: f32,
+}
+impl FeatureDrift {
+    pub fn exceeds(&self, psi: f32, ks: f32) -> bool {
+        self.psi > psi || self.ks > ks
+    }
+}
+
+/// Bin counts of the last `DRIFT_WINDOW` live feature vectors, compared with the training
+/// distribution every `DRIFT_CHECK_INTERVAL` rows.
+pub struct DriftMonitor {
+    /// Version of the served model the distribution belongs to.
+    pub model_version: u64,
+    distribution: TrainingDistribution,
+    window: VecDeque<Vec<usize>>,
+    counts: Vec<Vec<usize>>,
+    /// Rows pushed since a check was last due.
+    since_check: usize,
+}
+impl DriftMonitor {
+    pub fn new(distribution: TrainingDistribution, model_version: u64) -> Self {
+        let counts = distribution
+            .features
+            .iter()
+            .map(|feature| vec![0; feature.shares.len()])
+            .collect();
+        Self {
+            model_version,
+            distribution,
+            window: VecDeque::with_capacity(DRIFT_WINDOW),
+            counts,
+            since_check: 0,
+        }
+    }
+    /// Adds a live feature vector, returning the drift of every feature when a check is due and
+    /// the window is full.
+    pub fn push(&mut self, row: &[f32]) -> Option<Vec<FeatureDrift>> {
+        let bins: Vec<usize> = self
+            .distribution
+            .features
+            .iter()
+            .zip(row)
+            .map(|(feature, value)| feature.bin(*value))
+            .collect();
+        for (counts, bin) in self.counts.iter_mut().zip(&bins) {
+            counts[*bin] += 1;
+        }
+        self.window.push_back(bins);
+        if self.window.len() > DRIFT_WINDOW {
+            let evicted = self.window.pop_front().unwrap();
+            for (counts, bin) in self.counts.iter_mut().zip(evicted) {
+                counts[bin] -= 1;
+            }
+        }
+        self.since_check += 1;
+        if self.since_check < DRIFT_CHECK_INTERVAL {
+            return None;
+        }
+        self.since_check = 0;
+        (self.window.len() == DRIFT_WINDOW).then(|| self.drift())
+    }
+    pub fn drift(&self) -> Vec<FeatureDrift> {
+        self.distribution
+            .features
+            .iter()
+            .zip(&self.counts)
+            .map(|(feature, counts)| {
+                let actual = shares(counts, self.window.len());
+                FeatureDrift {
+                    name: feature.name.clone(),
+                    psi: psi(&feature.shares, &actual),
+                    ks: ks(&feature.shares, &actual),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::feature_registry::feature_size;
+
+    fn rows(offset: f32) -> Vec<Vec<f32>> {
+        (0..DRIFT_WINDOW)
+            .map(|i| vec![(i % 100) as f32 + offset; feature_size()])
+            .collect()
+    }
+
+    #[test]
+    fn flags_features_that_moved_away_from_training() {
+        let distribution = TrainingDistribution::fit(&rows(0.0));
+        let feature = &distribution.features[0];
+        assert_eq!(feature.edges.len(), DRIFT_BINS - 1);
+        assert!((feature.shares.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        // a constant feature keeps a single edge
+        let constant = FeatureDistribution::fit("constant", &[1.0; 10]);
+        assert_eq!(constant.edges, vec![1.0]);
+        assert_eq!(constant.shares, vec![1.0, 0.0]);
+
+        let mut monitor = DriftMonitor::new(distribution.clone(), 0);
+        let mut checks = Vec::new();
+        for row in rows(0.0) {
+            checks.extend(monitor.push(&row));
+        }
+        assert_eq!(checks.len(), 1);
+        assert!(checks[0]
+            .iter()
+            .all(|drift| drift.psi < 1e-6 && drift.ks < 1e-6));
+
+        for row in rows(50.0) {
+            checks.extend(monitor.push(&row));
+        }
+        let shifted = checks.last().unwrap();
+        assert_eq!(checks.len(), 1 + DRIFT_WINDOW / DRIFT_CHECK_INTERVAL);
+        assert!((shifted[0].ks - 0.5).abs() < 1e-5);
+        assert!(shifted[0].exceeds(0.25, 0.2));
+        assert!(!checks[0][0].exceeds(0.25, 0.2));
+    }
+}
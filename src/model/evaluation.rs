@@ -0,0 +1,305 @@
+use std::{error::Error, ops::Range};
+
+use log::debug;
+use rust_decimal::prelude::ToPrimitive;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    data_handling::Observation, feature_registry::FeatureError, predictor::Predictor,
+    rolling::Window,
+};
+
+/// Predictions smaller than this, in ticks, aren't traded in the simulated PnL.
+const MIN_SIGNAL_TICKS: f32 = 1.0;
+
+/// Rows a model is fitted on and the later rows it is scored on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fold {
+    pub train: Range<usize>,
+    pub test: Range<usize>,
+}
+
+/// Expanding-window walk-forward splits. Training rows whose target `horizon` reaches into the
+/// test rows are purged, and test rows within `embargo` of the end of training are skipped.
+#[derive(Debug, Clone, Copy)]
+pub struct WalkForward {
+    pub folds: usize,
+    pub horizon: Window,
+    pub embargo: Window,
+}
+impl WalkForward {
+    /// Splits rows with these timestamps into folds, skipping folds left without rows.
+    pub fn folds(&self, timestamps: &[i64]) -> Vec<Fold> {
+        let block = timestamps.len() / (self.folds + 1);
+        if block == 0 {
+            return Vec::new();
+        }
+        (1..=self.folds)
+            .filter_map(|k| {
+                let boundary = k * block;
+                let end = if k == self.folds {
+                    timestamps.len()
+                } else {
+                    boundary + block
+                };
+                let train_end = (0..boundary)
+                    .find(|i| {
+                        self.horizon
+                            .contains(boundary - i, timestamps[boundary] - timestamps[*i])
+                    })
+                    .unwrap_or(boundary);
+                let test_start = (boundary..end)
+                    .find(|j| {
+                        !self
+                            .embargo
+                            .contains(j - boundary, timestamps[*j] - timestamps[boundary])
+                    })
+                    .unwrap_or(end);
+                let fold = Fold {
+                    train: 0..train_end,
+                    test: test_start..end,
+                };
+                (!fold.train.is_empty() && !fold.test.is_empty()).then_some(fold)
+            })
+            .collect()
+    }
+    /// Index of the first row a model trained on the rows up to `last_trained` can be scored on
+    /// out-of-sample: past the target `horizon` of its last training row and then the `embargo`,
+    /// as between the training and test rows of a fold.
+    pub fn first_unseen(&self, timestamps: &[i64], last_trained: i64) -> usize {
+        let seen = timestamps.partition_point(|timestamp| *timestamp <= last_trained);
+        let boundary = (seen..timestamps.len())
+            .find(|j| {
+                !self
+                    .horizon
+                    .contains(j + 1 - seen, timestamps[*j] - last_trained)
+            })
+            .unwrap_or(timestamps.len());
+        (boundary..timestamps.len())
+            .find(|j| {
+                !self
+                    .embargo
+                    .contains(j - boundary, timestamps[*j] - timestamps[boundary])
+            })
+            .unwrap_or(timestamps.len())
+    }
+}
+
+/// Feature rows and targets of observations that have both, with their sample weights and the
+/// timestamps they are split into folds on.
+#[derive(Debug, Clone, Default)]
+pub struct Dataset {
+    pub x: Vec<Vec<f32>>,
+    pub y: Vec<f32>,
+    /// One for every row until weighted with a `SampleWeighting`.
+    pub w: Vec<f32>,
+    pub timestamps: Vec<i64>,
+}
+impl Dataset {
+    /// The rows of labelled observations, or the first feature one of them is missing.
+    pub fn from_rows(rows: &[Observation]) -> Result<Self, FeatureError> {
+        Ok(Self {
+            x: rows
+                .iter()
+                .map(|row| row.to_training_data())
+                .collect::<Result<_, _>>()?,
+            y: rows
+                .iter()
+                .map(|row| row.target.unwrap().to_f32().unwrap())
+                .collect(),
+            w: vec![1.0; rows.len()],
+            timestamps: rows.iter().map(|row| row.timestamp).collect(),
+        })
+    }
+    pub fn len(&self) -> usize {
+        self.y.len()
+    }
+    pub fn slice(&self, rows: Range<usize>) -> Self {
+        Self {
+            x: self.x[rows.clone()].to_vec(),
+            y: self.y[rows.clone()].to_vec(),
+            w: self.w[rows.clone()].to_vec(),
+            timestamps: self.timestamps[rows].to_vec(),
+        }
+    }
+    /// The latest `fraction` of the rows.
+    pub fn tail(&self, fraction: f64) -> Self {
+        let start = self.len() - (self.len() as f64 * fraction).round() as usize;
+        self.slice(start..self.len())
+    }
+}
+
+/// Fits a fresh model from `new_model` on the training rows of each fold and scores it on the
+/// test rows.
+pub fn score_folds(
+    dataset: &Dataset,
+    folds: &[Fold],
+    new_model: impl Fn() -> Result<Box<dyn Predictor>, Box<dyn Error>>,
+) -> Result<Vec<FoldMetrics>, Box<dyn Error>> {
+    let predictions = predict_folds(dataset, folds, new_model)?;
+    Ok(fold_metrics(dataset, folds, &predictions))
+}
+
+/// Fits a fresh model from `new_model` on the training rows of each fold and predicts its test
+/// rows.
+pub fn predict_folds(
+    dataset: &Dataset,
+    folds: &[Fold],
+    new_model: impl Fn() -> Result<Box<dyn Predictor>, Box<dyn Error>>,
+) -> Result<Vec<Vec<f32>>, Box<dyn Error>> {
+    folds
+        .iter()
+        .map(|fold| {
+            let mut model = new_model()?;
+            model.fit(
+                &dataset.x[fold.train.clone()],
+                &dataset.y[fold.train.clone()],
+                &dataset.w[fold.train.clone()],
+            );
+            Ok(model.predict(&dataset.x[fold.test.clone()]))
+        })
+        .collect()
+}
+
+/// Scores of the predictions of each fold's test rows.
+pub fn fold_metrics(
+    dataset: &Dataset,
+    folds: &[Fold],
+    predictions: &[Vec<f32>],
+) -> Vec<FoldMetrics> {
+    folds
+        .iter()
+        .zip(predictions)
+        .enumerate()
+        .map(|(i, (fold, predictions))| {
+            let metrics = FoldMetrics::compute(predictions, &dataset.y[fold.test.clone()]);
+            debug!(
+                "Fold {}: {} train, {} test, {:?}",
+                i,
+                fold.train.len(),
+                fold.test.len(),
+                metrics
+            );
+            metrics
+        })
+        .collect()
+}
+
+/// Out-of-sample scores of predictions against targets, both in ticks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct FoldMetrics {
+    pub mae: f32,
+    /// Share of predictions on the same side of zero as the target.
+    pub directional_accuracy: f32,
+    /// Information coefficient, the correlation of predictions and targets.
+    pub ic: f32,
+    /// Target ticks captured by trading the sign of each large enough prediction.
+    pub pnl: f32,
+}
+impl FoldMetrics {
+    pub fn compute(predictions: &[f32], targets: &[f32]) -> Self {
+        let n = predictions.len() as f32;
+        let mut metrics = Self::default();
+        for (prediction, target) in predictions.iter().zip(targets) {
+            metrics.mae += (prediction - target).abs() / n;
+            if prediction * target >= 0.0 {
+                metrics.directional_accuracy += 1.0 / n;
+            }
+            if prediction.abs() >= MIN_SIGNAL_TICKS {
+                metrics.pnl += prediction.signum() * target;
+            }
+        }
+        metrics.ic = correlation(predictions, targets);
+        metrics
+    }
+    pub fn mean(folds: &[FoldMetrics]) -> Self {
+        let n = folds.len() as f32;
+        folds.iter().fold(Self::default(), |mean, fold| Self {
+            mae: mean.mae + fold.mae / n,
+            directional_accuracy: mean.directional_accuracy + fold.directional_accuracy / n,
+            ic: mean.ic + fold.ic / n,
+            pnl: mean.pnl + fold.pnl / n,
+        })
+    }
+    /// True if these scores are a strict improvement on the `incumbent`'s: a lower MAE without
+    /// giving up PnL.
+    pub fn beats(&self, incumbent: &FoldMetrics) -> bool {
+        self.mae < incumbent.mae && self.pnl >= incumbent.pnl
+    }
+}
+
+/// Pearson correlation, zero when either side is constant.
+fn correlation(x: &[f32], y: &[f32]) -> f32 {
+    let n = x.len() as f64;
+    let mean_x = x.iter().map(|v| *v as f64).sum::<f64>() / n;
+    let mean_y = y.iter().map(|v| *v as f64).sum::<f64>() / n;
+    let (mut covariance, mut variance_x, mut variance_y) = (0.0, 0.0, 0.0);
+    for (a, b) in x.iter().zip(y) {
+        let (dx, dy) = (*a as f64 - mean_x, *b as f64 - mean_y);
+        covariance += dx * dy;
+        variance_x += dx * dx;
+        variance_y += dy * dy;
+    }
+    if variance_x == 0.0 || variance_y == 0.0 {
+        return 0.0;
+    }
+    (covariance / (variance_x * variance_y).sqrt()) as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn folds_purge_the_horizon_and_embargo_the_test_start() {
+        let timestamps: Vec<i64> = (0..100).map(|i| i * 1000).collect();
+        let walk_forward = WalkForward {
+            folds: 3,
+            horizon: Window::Time(5_000),
+            embargo: Window::Count(2),
+        };
+        assert_eq!(
+            walk_forward.folds(&timestamps),
+            vec![
+                Fold {
+                    train: 0..21,
+                    test: 27..50
+                },
+                Fold {
+                    train: 0..46,
+                    test: 52..75
+                },
+                Fold {
+                    train: 0..71,
+                    test: 77..100
+                },
+            ]
+        );
+        let short = WalkForward {
+            horizon: Window::Count(30),
+            ..walk_forward
+        };
+        // the first fold's training rows all reach into its test rows
+        assert_eq!(short.folds(&timestamps).len(), 2);
+        // a model trained through row 20 shares labels with rows up to 24, then rows 25 and 26
+        // are embargoed
+        assert_eq!(walk_forward.first_unseen(&timestamps, 20_000), 27);
+        assert_eq!(short.first_unseen(&timestamps, 20_000), 52);
+        assert_eq!(walk_forward.first_unseen(&timestamps, 99_000), 100);
+    }
+
+    #[test]
+    fn scores_predictions_out_of_sample() {
+        let metrics = FoldMetrics::compute(&[2.0, -1.0, 0.5, -3.0], &[4.0, 1.0, -1.0, -2.0]);
+        assert_eq!(metrics.mae, (2.0 + 2.0 + 1.5 + 1.0) / 4.0);
+        assert_eq!(metrics.directional_accuracy, 0.5);
+        // the 0.5 prediction isn't traded
+        assert_eq!(metrics.pnl, 4.0 - 1.0 + 2.0);
+        assert!(metrics.ic > 0.0);
+        let perfect = FoldMetrics::compute(&[4.0, 1.0, -1.0, -2.0], &[4.0, 1.0, -1.0, -2.0]);
+        assert!((perfect.ic - 1.0).abs() < 1e-6);
+        assert!(perfect.beats(&metrics));
+        assert!(!metrics.beats(&perfect));
+        assert!(!metrics.beats(&metrics));
+    }
+}
@@ -0,0 +1,506 @@
+use std::{
+    error::Error,
+    fs,
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::Utc;
+use clap::{Args, ValueEnum};
+use log::{error, info};
+use parquet::{
+    data_type::{DoubleType, FloatType, Int64Type},
+    file::{
+        properties::WriterProperties,
+        reader::{FileReader, SerializedFileReader},
+        writer::SerializedFileWriter,
+    },
+    record::RowAccessor,
+    schema::parser::parse_message_type,
+};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+
+use crate::{
+    binance::models::{fapi_exchange_info::Symbol, model_config::ModelSwap},
+    EXPORT_DIR, EXPORT_INTERVAL,
+};
+
+use super::{
+    data_handling::{Dfrwl, Observation, TrainingFrame},
+    evaluation::Dataset,
+    feature_registry::{exact_features, FeatureError, FeatureSchema, FEATURES},
+    labeling::Labeler,
+    predictor::ModelKind,
+    store::read_spills,
+    weighting::SampleWeighting,
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    #[default]
+    Parquet,
+}
+impl ExportFormat {
+    /// The format named by `EXPORT_FORMAT`, Parquet by default.
+    pub fn from_env() -> Self {
+        match std::env::var("EXPORT_FORMAT") {
+            Ok(format) => Self::from_str(&format, true).unwrap(),
+            Err(_) => Self::default(),
+        }
+    }
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Parquet => "parquet",
+        }
+    }
+    /// The format of an exported file, from its extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(Self::Csv),
+            "parquet" => Some(Self::Parquet),
+            _ => None,
+        }
+    }
+}
+
+/// Column names of an export: the timestamp, every feature in column order, the target and the
+/// sample weight.
+fn columns() -> Vec<&'static str> {
+    let mut columns = vec!["timestamp"];
+    columns.extend(FEATURES.iter().map(|feature| feature.name));
+    columns.extend(["target", "weight"]);
+    columns
+}
+
+/// How the rows of an export were labelled and which features they hold, saved beside it as
+/// `<export>.meta.json`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportMetadata {
+    pub labeler: Labeler,
+    pub schema_hash: String,
+}
+impl ExportMetadata {
+    pub fn path_for(path: &Path) -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(".meta.json");
+        PathBuf::from(name)
+    }
+    /// Fails unless the export at `path` was labelled by `labeler` on the current features.
+    fn check(path: &Path, labeler: &Labeler) -> Result<(), Box<dyn Error>> {
+        let metadata_path = Self::path_for(path);
+        let metadata: Self = serde_json::from_str(
+            &fs::read_to_string(&metadata_path)
+                .map_err(|e| format!("can't read {:?}: {}", metadata_path, e))?,
+        )?;
+        if metadata.labeler != *labeler {
+            return Err(format!(
+                "{:?} was labelled by {:?}, not {:?}",
+                path, metadata.labeler, labeler
+            )
+            .into());
+        }
+        let current = FeatureSchema::current().hash;
+        if metadata.schema_hash != current {
+            return Err(format!(
+                "feature schema {} of {:?} doesn't match the current schema {}",
+                metadata.schema_hash, path, current
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+/// The rows of an export, with features and targets at the precision of the observations rather
+/// than the `f32` the models train on.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExportRows {
+    pub timestamps: Vec<i64>,
+    pub features: Vec<Vec<f64>>,
+    pub targets: Vec<f64>,
+    pub weights: Vec<f32>,
+}
+impl ExportRows {
+    /// The labelled `rows` weighted by `weights`, or the first feature one of them is missing.
+    fn from_observations(rows: &[Observation], weights: Vec<f32>) -> Result<Self, FeatureError> {
+        Ok(Self {
+            timestamps: rows.iter().map(|row| row.timestamp).collect(),
+            features: rows.iter().map(exact_features).collect::<Result<_, _>>()?,
+            targets: rows
+                .iter()
+                .map(|row| row.target.unwrap().to_f64().unwrap())
+                .collect(),
+            weights,
+        })
+    }
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+}
+impl From<&Dataset> for ExportRows {
+    fn from(dataset: &Dataset) -> Self {
+        Self {
+            timestamps: dataset.timestamps.clone(),
+            features: dataset
+                .x
+                .iter()
+                .map(|row| row.iter().map(|&value| value as f64).collect())
+                .collect(),
+            targets: dataset.y.iter().map(|&value| value as f64).collect(),
+            weights: dataset.w.clone(),
+        }
+    }
+}
+
+/// Writes `rows`, labelled by `labeler`, to `path` in `format` and their [`ExportMetadata`]
+/// beside them.
+pub fn write_export(
+    rows: &ExportRows,
+    labeler: &Labeler,
+    path: &Path,
+    format: ExportFormat,
+) -> Result<(), Box<dyn Error>> {
+    match format {
+        ExportFormat::Csv => write_csv(rows, path)?,
+        ExportFormat::Parquet => write_parquet(rows, path)?,
+    }
+    let metadata = ExportMetadata {
+        labeler: *labeler,
+        schema_hash: FeatureSchema::current().hash,
+    };
+    fs::write(
+        ExportMetadata::path_for(path),
+        serde_json::to_string_pretty(&metadata)?,
+    )?;
+    Ok(())
+}
+
+fn write_csv(rows: &ExportRows, path: &Path) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    writeln!(writer, "{}", columns().join(","))?;
+    for i in 0..rows.len() {
+        write!(writer, "{}", rows.timestamps[i])?;
+        for value in &rows.features[i] {
+            write!(writer, ",{}", value)?;
+        }
+        writeln!(writer, ",{},{}", rows.targets[i], rows.weights[i])?;
+    }
+    writer.flush()
+}
+
+/// A single row group with an `INT64` UTC millisecond timestamp, a `DOUBLE` column per feature and
+/// the target, and a `FLOAT` weight.
+fn write_parquet(rows: &ExportRows, path: &Path) -> Result<(), Box<dyn Error>> {
+    let mut message = String::from("message observations {\n");
+    message.push_str("  REQUIRED INT64 timestamp (TIMESTAMP(MILLIS,true));\n");
+    for column in &columns()[1..=FEATURES.len() + 1] {
+        message.push_str(&format!("  REQUIRED DOUBLE {};\n", column));
+    }
+    message.push_str("  REQUIRED FLOAT weight;\n}");
+    let schema = Arc::new(parse_message_type(&message)?);
+    let properties = Arc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(fs::File::create(path)?, schema, properties)?;
+    let mut row_group = writer.next_row_group()?;
+    let mut index = 0;
+    while let Some(mut column) = row_group.next_column()? {
+        match index {
+            0 => {
+                column
+                    .typed::<Int64Type>()
+                    .write_batch(&rows.timestamps, None, None)?;
+            }
+            feature if feature <= FEATURES.len() => {
+                let values: Vec<f64> = rows.features.iter().map(|row| row[feature - 1]).collect();
+                column
+                    .typed::<DoubleType>()
+                    .write_batch(&values, None, None)?;
+            }
+            target if target == FEATURES.len() + 1 => {
+                column
+                    .typed::<DoubleType>()
+                    .write_batch(&rows.targets, None, None)?;
+            }
+            _ => {
+                column
+                    .typed::<FloatType>()
+                    .write_batch(&rows.weights, None, None)?;
+            }
+        }
+        column.close()?;
+        index += 1;
+    }
+    row_group.close()?;
+    writer.close()?;
+    Ok(())
+}
+
+/// Reads back a dataset exported in `format`, failing unless it was labelled by `labeler` on the
+/// current features.
+pub fn read_dataset(
+    path: &Path,
+    labeler: &Labeler,
+    format: ExportFormat,
+) -> Result<Dataset, Box<dyn Error>> {
+    ExportMetadata::check(path, labeler)?;
+    match format {
+        ExportFormat::Csv => read_csv(path),
+        ExportFormat::Parquet => read_parquet(path),
+    }
+}
+
+fn check_columns(path: &Path, names: &[&str]) -> Result<(), Box<dyn Error>> {
+    if names != columns() {
+        return Err(format!(
+            "the columns of {:?} aren't those of the current features",
+            path
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn read_csv(path: &Path) -> Result<Dataset, Box<dyn Error>> {
+    let mut lines = BufReader::new(fs::File::open(path)?).lines();
+    let header = lines.next().ok_or(format!("{:?} is empty", path))??;
+    check_columns(path, &header.split(',').collect::<Vec<&str>>())?;
+    let mut dataset = Dataset::default();
+    for line in lines {
+        let line = line?;
+        let mut values = line.split(',');
+        dataset
+            .timestamps
+            .push(values.next().unwrap_or_default().parse()?);
+        let values = values.collect::<Vec<&str>>();
+        if values.len() != FEATURES.len() + 2 {
+            return Err(format!("row {} of {:?} is short", dataset.len() + 1, path).into());
+        }
+        dataset.x.push(
+            values[..FEATURES.len()]
+                .iter()
+                .map(|value| value.parse::<f64>().map(|value| value as f32))
+                .collect::<Result<_, _>>()?,
+        );
+        dataset
+            .y
+            .push(values[FEATURES.len()].parse::<f64>()? as f32);
+        dataset.w.push(values[FEATURES.len() + 1].parse()?);
+    }
+    Ok(dataset)
+}
+
+fn read_parquet(path: &Path) -> Result<Dataset, Box<dyn Error>> {
+    let reader = SerializedFileReader::new(fs::File::open(path)?)?;
+    let schema = reader.metadata().file_metadata().schema_descr_ptr();
+    let names: Vec<&str> = schema
+        .columns()
+        .iter()
+        .map(|column| column.name())
+        .collect();
+    check_columns(path, &names)?;
+    let mut dataset = Dataset::default();
+    for row in reader.get_row_iter(None)? {
+        let row = row?;
+        dataset.timestamps.push(row.get_timestamp_millis(0)?);
+        dataset.x.push(
+            (1..=FEATURES.len())
+                .map(|i| row.get_double(i).map(|value| value as f32))
+                .collect::<Result<Vec<f32>, _>>()?,
+        );
+        dataset.y.push(row.get_double(FEATURES.len() + 1)? as f32);
+        dataset.w.push(row.get_float(FEATURES.len() + 2)?);
+    }
+    Ok(dataset)
+}
+
+/// The rows a model of `kind` labelled by `labeler` would be trained on, with their weights.
+fn training_rows(
+    mut frame: TrainingFrame,
+    kind: ModelKind,
+    labeler: &Labeler,
+    tick_size: Decimal,
+) -> Result<ExportRows, FeatureError> {
+    frame.prepare(labeler, tick_size);
+    let dataset = Dataset::from_rows(&frame.data)?;
+    let weights = SampleWeighting::from_env().weights(
+        &dataset.timestamps,
+        &dataset.y,
+        labeler.horizon(),
+        kind.class_thresholds(labeler).as_ref(),
+    );
+    ExportRows::from_observations(&frame.data, weights)
+}
+
+/// Options of the `export` command.
+#[derive(Debug, Clone, Args)]
+pub struct ExportArgs {
+    /// Observations recorded as JSON lines, such as `OBSERVATIONS_SPILL_PATH` files.
+    #[arg(long = "data", required = true)]
+    pub data: Vec<PathBuf>,
+    /// File written, in the format of its extension unless `--format` is given.
+    #[arg(long)]
+    pub output: PathBuf,
+    #[arg(long, value_enum)]
+    pub format: Option<ExportFormat>,
+    #[arg(long, default_value = "0.10")]
+    pub tick_size: Decimal,
+}
+
+/// Exports recorded observations with their rolling features, targets and weights, labelled as
+/// `LABELER` and `MODEL_KIND` would train on them.
+pub fn export(args: &ExportArgs) -> Result<(), Box<dyn Error>> {
+    let format = args
+        .format
+        .or(ExportFormat::from_path(&args.output))
+        .unwrap_or_default();
+    let frame = TrainingFrame {
+        data: read_spills(&args.data)?,
+    };
+    let labeler = Labeler::from_env()?;
+    let rows = training_rows(frame, ModelKind::from_env()?, &labeler, args.tick_size)?;
+    write_export(&rows, &labeler, &args.output, format)?;
+    info!("Exported {} rows to {:?}", rows.len(), args.output);
+    Ok(())
+}
+
+/// Exports the live observations as the served model would be trained on them to `EXPORT_DIR`,
+/// every `EXPORT_INTERVAL` seconds and whenever `trigger` is notified.
+pub async fn export_periodically(
+    dataframe_rwl: Dfrwl,
+    market: Symbol,
+    model_swap: ModelSwap,
+    trigger: Arc<Notify>,
+) {
+    let dir = PathBuf::from(std::env::var("EXPORT_DIR").unwrap_or(EXPORT_DIR.to_string()));
+    let format = ExportFormat::from_env();
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(EXPORT_INTERVAL));
+    interval.tick().await;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {},
+            _ = trigger.notified() => {},
+        }
+        let tick_size = market.get_tick_size().unwrap();
+        let frame = TrainingFrame::from(dataframe_rwl.read().await.snapshot());
+        let served = model_swap.load_full();
+        let path = dir.join(format!(
+            "observations-{}.{}",
+            Utc::now().format("%Y%m%dT%H%M%S"),
+            format.extension()
+        ));
+        let dir = dir.clone();
+        let exported = tokio::task::spawn_blocking(move || {
+            let rows = training_rows(frame, served.kind, &served.labeler, tick_size)
+                .map_err(|e| e.to_string())?;
+            fs::create_dir_all(dir)
+                .map_err(|e| e.to_string())
+                .and_then(|_| {
+                    write_export(&rows, &served.labeler, &path, format).map_err(|e| e.to_string())
+                })
+                .map(|_| (rows.len(), path))
+        })
+        .await
+        .unwrap();
+        match exported {
+            Ok((rows, path)) => info!("Exported {} rows to {:?}", rows, path),
+            Err(e) => error!("Export failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_same(read: &Dataset, written: &Dataset) {
+        assert_eq!(read.timestamps, written.timestamps);
+        assert_eq!(read.x, written.x);
+        assert_eq!(read.y, written.y);
+        assert_eq!(read.w, written.w);
+    }
+
+    #[test]
+    fn writes_and_reads_back_named_columns_in_both_formats() {
+        let dataset = Dataset {
+            x: vec![vec![1.5; FEATURES.len()], vec![-2.0; FEATURES.len()]],
+            y: vec![3.0, -1.0],
+            w: vec![1.0, 0.5],
+            timestamps: vec![1_700_000_000_000, 1_700_000_000_100],
+        };
+        let rows = ExportRows::from(&dataset);
+        let path = |extension: &str| {
+            std::env::temp_dir().join(format!("export-{}.{}", std::process::id(), extension))
+        };
+
+        let labeler = Labeler::default();
+        let csv = path("csv");
+        write_export(&rows, &labeler, &csv, ExportFormat::Csv).unwrap();
+        let text = fs::read_to_string(&csv).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("timestamp,price,"));
+        assert!(lines[2].starts_with("1700000000100,-2,"));
+        assert!(lines[2].ends_with(",-1,0.5"));
+        assert_same(
+            &read_dataset(&csv, &labeler, ExportFormat::Csv).unwrap(),
+            &dataset,
+        );
+        // rows labelled otherwise, or on other features, aren't read back
+        let forward_return = Labeler::ForwardReturn {
+            horizon: labeler.horizon(),
+        };
+        assert!(read_dataset(&csv, &forward_return, ExportFormat::Csv).is_err());
+        let metadata_path = ExportMetadata::path_for(&csv);
+        let metadata = fs::read_to_string(&metadata_path).unwrap();
+        let hash = FeatureSchema::current().hash;
+        fs::write(&metadata_path, metadata.replace(&hash, "0000000000000000")).unwrap();
+        assert!(read_dataset(&csv, &labeler, ExportFormat::Csv).is_err());
+        fs::remove_file(&metadata_path).unwrap();
+        assert!(read_dataset(&csv, &labeler, ExportFormat::Csv).is_err());
+        fs::remove_file(&csv).unwrap();
+
+        let parquet = path("parquet");
+        assert_eq!(
+            ExportFormat::from_path(&parquet),
+            Some(ExportFormat::Parquet)
+        );
+        write_export(&rows, &labeler, &parquet, ExportFormat::Parquet).unwrap();
+        let reader = SerializedFileReader::new(fs::File::open(&parquet).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 2);
+        assert_same(
+            &read_dataset(&parquet, &labeler, ExportFormat::Parquet).unwrap(),
+            &dataset,
+        );
+        fs::remove_file(&parquet).unwrap();
+        fs::remove_file(ExportMetadata::path_for(&parquet)).unwrap();
+
+        // prices beyond the precision of an f32 are written whole
+        let mut precise = rows.clone();
+        precise.features[0][0] = 30_000.12;
+        let parquet = path("precise.parquet");
+        write_export(&precise, &labeler, &parquet, ExportFormat::Parquet).unwrap();
+        let reader = SerializedFileReader::new(fs::File::open(&parquet).unwrap()).unwrap();
+        let row = reader.get_row_iter(None).unwrap().next().unwrap().unwrap();
+        assert_eq!(row.get_double(1).unwrap(), 30_000.12);
+        let csv = path("precise.csv");
+        write_export(&precise, &labeler, &csv, ExportFormat::Csv).unwrap();
+        assert!(fs::read_to_string(&csv)
+            .unwrap()
+            .contains("\n1700000000000,30000.12,"));
+        for path in [parquet, csv] {
+            fs::remove_file(ExportMetadata::path_for(&path)).unwrap();
+            fs::remove_file(path).unwrap();
+        }
+
+        let renamed = path("renamed.csv");
+        write_export(&rows, &labeler, &renamed, ExportFormat::Csv).unwrap();
+        fs::write(&renamed, text.replacen("price", "last_price", 1)).unwrap();
+        assert!(read_dataset(&renamed, &labeler, ExportFormat::Csv).is_err());
+        fs::remove_file(&renamed).unwrap();
+        fs::remove_file(ExportMetadata::path_for(&renamed)).unwrap();
+    }
+}
@@ -0,0 +1,516 @@
+use std::{error::Error, fmt};
+
+use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::{Deserialize, Serialize};
+
+use crate::{BOOK_RATIO_WINDOW, NOTIONAL_WINDOW, PRICE_WINDOW};
+
+use super::{
+    data_handling::Observation,
+    order_flow::ORDER_FLOW_WINDOW,
+    rolling::Window,
+    time_features::{day_of_week_angle, seconds_to_funding, time_of_day_angle},
+    trade_flow::{BUY_SHARE_WINDOWS_MS, TRADE_FLOW_WINDOW, VPIN_VOLUME_TRADES},
+};
+
+/// A model input: its name, how it's computed from an [`Observation`] and the window of previous
+/// observations it needs before it's defined.
+pub struct FeatureDef {
+    pub name: &'static str,
+    pub lookback: Window,
+    pub compute: fn(&Observation) -> Option<f64>,
+}
+
+fn to_f64(value: Decimal) -> Option<f64> {
+    value.to_f64()
+}
+
+/// Lookback of the rolling order flow features. Their window counts book updates rather than
+/// observations, and is declared as as many observations.
+const ORDER_FLOW_LOOKBACK: Window = Window::Count(ORDER_FLOW_WINDOW);
+/// Lookback of VPIN, the trades its buckets are sized from, then about as many again to fill the
+/// buckets.
+const VPIN_LOOKBACK: Window = Window::Count(2 * VPIN_VOLUME_TRADES);
+/// Lookback of the features of the gaps and price changes between trades, one more trade than
+/// the gaps they are measured over.
+const TRADE_GAPS_LOOKBACK: Window = Window::Count(TRADE_FLOW_WINDOW + 1);
+
+/// Every feature the models are trained on, in column order.
+pub static FEATURES: &[FeatureDef] = &[
+    FeatureDef {
+        name: "price",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.price),
+    },
+    FeatureDef {
+        name: "net_qty",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.net_qty),
+    },
+    FeatureDef {
+        name: "notional",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.notional),
+    },
+    FeatureDef {
+        name: "bid_total",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.bid_total),
+    },
+    FeatureDef {
+        name: "num_ticks_from_best_bid",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.num_ticks_from_best_bid),
+    },
+    FeatureDef {
+        name: "ask_total",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.ask_total),
+    },
+    FeatureDef {
+        name: "num_ticks_from_best_ask",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.num_ticks_from_best_ask),
+    },
+    FeatureDef {
+        name: "bids_asks_ratio",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.bids_asks_ratio),
+    },
+    FeatureDef {
+        name: "bid_notional",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.bid_notional),
+    },
+    FeatureDef {
+        name: "ask_notional",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.ask_notional),
+    },
+    FeatureDef {
+        name: "spread_ticks",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.spread_ticks),
+    },
+    FeatureDef {
+        name: "microprice_ticks_from_mid",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.microprice_ticks_from_mid),
+    },
+    FeatureDef {
+        name: "weighted_mid_ticks_from_mid",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.weighted_mid_ticks_from_mid),
+    },
+    FeatureDef {
+        name: "imbalance_1",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.imbalance_1),
+    },
+    FeatureDef {
+        name: "imbalance_5",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.imbalance_5),
+    },
+    FeatureDef {
+        name: "imbalance_10",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.imbalance_10),
+    },
+    FeatureDef {
+        name: "imbalance_20",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.imbalance_20),
+    },
+    FeatureDef {
+        name: "bid_depth_near_mid",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.bid_depth_near_mid),
+    },
+    FeatureDef {
+        name: "ask_depth_near_mid",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.ask_depth_near_mid),
+    },
+    FeatureDef {
+        name: "bid_slope",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.bid_slope),
+    },
+    FeatureDef {
+        name: "ask_slope",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.ask_slope),
+    },
+    FeatureDef {
+        name: "ticks_to_largest_bid",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.ticks_to_largest_bid),
+    },
+    FeatureDef {
+        name: "ticks_to_largest_ask",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.ticks_to_largest_ask),
+    },
+    FeatureDef {
+        name: "ofi_1",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.ofi_1),
+    },
+    FeatureDef {
+        name: "ofi_multi",
+        lookback: Window::Count(0),
+        compute: |o| to_f64(o.ofi_multi),
+    },
+    FeatureDef {
+        name: "ofi_rolling",
+        lookback: ORDER_FLOW_LOOKBACK,
+        compute: |o| o.ofi_rolling.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "bid_depletion_rate",
+        lookback: ORDER_FLOW_LOOKBACK,
+        compute: |o| o.bid_depletion_rate.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "bid_replenishment_rate",
+        lookback: ORDER_FLOW_LOOKBACK,
+        compute: |o| o.bid_replenishment_rate.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "ask_depletion_rate",
+        lookback: ORDER_FLOW_LOOKBACK,
+        compute: |o| o.ask_depletion_rate.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "ask_replenishment_rate",
+        lookback: ORDER_FLOW_LOOKBACK,
+        compute: |o| o.ask_replenishment_rate.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "vpin",
+        lookback: VPIN_LOOKBACK,
+        compute: |o| o.vpin.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "trade_rate",
+        lookback: TRADE_GAPS_LOOKBACK,
+        compute: |o| o.trade_rate.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "inter_arrival_mean_ms",
+        lookback: TRADE_GAPS_LOOKBACK,
+        compute: |o| o.inter_arrival_mean_ms.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "inter_arrival_std_ms",
+        lookback: TRADE_GAPS_LOOKBACK,
+        compute: |o| o.inter_arrival_std_ms.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "buy_share_1s",
+        lookback: Window::Time(BUY_SHARE_WINDOWS_MS[0]),
+        compute: |o| o.buy_share_1s.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "buy_share_10s",
+        lookback: Window::Time(BUY_SHARE_WINDOWS_MS[1]),
+        compute: |o| o.buy_share_10s.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "buy_share_60s",
+        lookback: Window::Time(BUY_SHARE_WINDOWS_MS[2]),
+        compute: |o| o.buy_share_60s.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "large_trade_90",
+        lookback: Window::Count(TRADE_FLOW_WINDOW),
+        compute: |o| o.large_trade_90.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "large_trade_99",
+        lookback: Window::Count(TRADE_FLOW_WINDOW),
+        compute: |o| o.large_trade_99.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "kyle_lambda",
+        lookback: TRADE_GAPS_LOOKBACK,
+        compute: |o| o.kyle_lambda.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "time_of_day_sin",
+        lookback: Window::Count(0),
+        compute: |o| Some(time_of_day_angle(o.timestamp).sin()),
+    },
+    FeatureDef {
+        name: "time_of_day_cos",
+        lookback: Window::Count(0),
+        compute: |o| Some(time_of_day_angle(o.timestamp).cos()),
+    },
+    FeatureDef {
+        name: "day_of_week_sin",
+        lookback: Window::Count(0),
+        compute: |o| day_of_week_angle(o.timestamp).map(f64::sin),
+    },
+    FeatureDef {
+        name: "day_of_week_cos",
+        lookback: Window::Count(0),
+        compute: |o| day_of_week_angle(o.timestamp).map(f64::cos),
+    },
+    FeatureDef {
+        name: "seconds_to_funding",
+        lookback: Window::Count(0),
+        compute: |o| Some(seconds_to_funding(o.timestamp)),
+    },
+    FeatureDef {
+        name: "seconds_since_large_move",
+        lookback: Window::Count(1),
+        compute: |o| o.seconds_since_large_move.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "rolling_qty",
+        lookback: NOTIONAL_WINDOW,
+        compute: |o| o.rolling_qty.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "mean_qty",
+        lookback: NOTIONAL_WINDOW,
+        compute: |o| o.mean_qty.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "qty_std",
+        lookback: NOTIONAL_WINDOW,
+        compute: |o| o.qty_std.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "mean_price",
+        lookback: PRICE_WINDOW,
+        compute: |o| o.mean_price.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "price_std",
+        lookback: PRICE_WINDOW,
+        compute: |o| o.price_std.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "book_ratio_rolling_mean",
+        lookback: BOOK_RATIO_WINDOW,
+        compute: |o| o.book_ratio_rolling_mean.and_then(to_f64),
+    },
+    FeatureDef {
+        name: "rolling_qty_abs",
+        lookback: NOTIONAL_WINDOW,
+        compute: |o| o.rolling_qty_abs.and_then(to_f64),
+    },
+];
+
+pub fn feature_size() -> usize {
+    FEATURES.len()
+}
+
+/// Observations needed before every count-window feature is defined, the trade flow window.
+/// Time-window features also need their window of history, see
+/// [`Observation::has_rolling_features`].
+pub fn max_lookback() -> usize {
+    FEATURES
+        .iter()
+        .filter_map(|f| match f.lookback {
+            Window::Count(count) => Some(count),
+            Window::Time(_) => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Why an observation's features can't be fed to a model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureError {
+    /// Its lookback isn't filled yet, or a value didn't convert to `f64`.
+    Undefined(&'static str),
+    /// It came out NaN or infinite.
+    NonFinite(&'static str),
+}
+impl FeatureError {
+    pub fn feature(&self) -> &'static str {
+        match self {
+            Self::Undefined(name) | Self::NonFinite(name) => name,
+        }
+    }
+}
+impl fmt::Display for FeatureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Undefined(name) => write!(f, "feature {} is undefined", name),
+            Self::NonFinite(name) => write!(f, "feature {} is not finite", name),
+        }
+    }
+}
+impl Error for FeatureError {}
+
+/// The feature vector of `observation` as the models take it, or the first feature that is
+/// undefined or not finite.
+pub fn checked_features(observation: &Observation) -> Result<Vec<f32>, FeatureError> {
+    Ok(exact_features(observation)?
+        .into_iter()
+        .map(|value| value as f32)
+        .collect())
+}
+
+/// The features of `observation` at full precision, for exports, see [`checked_features`].
+pub fn exact_features(observation: &Observation) -> Result<Vec<f64>, FeatureError> {
+    FEATURES
+        .iter()
+        .map(|f| checked(f.name, (f.compute)(observation)))
+        .collect()
+}
+
+fn checked(name: &'static str, value: Option<f64>) -> Result<f64, FeatureError> {
+    match value {
+        Some(value) if value.is_finite() => Ok(value),
+        Some(_) => Err(FeatureError::NonFinite(name)),
+        None => Err(FeatureError::Undefined(name)),
+    }
+}
+
+/// The names and lookbacks of the features a model was trained on, saved beside the model so it
+/// is never fed columns it wasn't trained on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeatureSchema {
+    pub names: Vec<String>,
+    pub lookbacks: Vec<Window>,
+    /// FNV-1a hash of the names and lookbacks, as hex.
+    pub hash: String,
+}
+impl FeatureSchema {
+    pub fn current() -> Self {
+        Self::new(
+            FEATURES.iter().map(|f| f.name.to_string()).collect(),
+            FEATURES.iter().map(|f| f.lookback).collect(),
+        )
+    }
+    pub fn new(names: Vec<String>, lookbacks: Vec<Window>) -> Self {
+        let mut hash: u64 = 0xcbf29ce484222325;
+        for (name, lookback) in names.iter().zip(lookbacks.iter()) {
+            for byte in format!("{}:{}\n", name, lookback).bytes() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100000001b3);
+            }
+        }
+        Self {
+            names,
+            lookbacks,
+            hash: format!("{:016x}", hash),
+        }
+    }
+    /// The schema file saved beside the model at `model_path`.
+    pub fn path_for(model_path: &str) -> String {
+        format!("{}.schema.json", model_path)
+    }
+    pub fn save(&self, model_path: &str) -> Result<(), Box<dyn Error>> {
+        std::fs::write(
+            Self::path_for(model_path),
+            serde_json::to_string_pretty(self)?,
+        )?;
+        Ok(())
+    }
+    pub fn load(model_path: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(
+            Self::path_for(model_path),
+        )?)?)
+    }
+    /// Fails unless the schema saved beside `model_path` is this one.
+    pub fn check_saved(&self, model_path: &str) -> Result<(), Box<dyn Error>> {
+        let saved = Self::load(model_path)?;
+        if saved.hash != self.hash {
+            return Err(format!(
+                "feature schema {} of {} doesn't match the current schema {}",
+                saved.hash, model_path, self.hash
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{test_support::observations, ROLLING_WINDOW};
+
+    #[test]
+    fn computes_every_feature_once_the_lookback_is_filled() {
+        let names: std::collections::HashSet<_> = FEATURES.iter().map(|f| f.name).collect();
+        assert_eq!(names.len(), feature_size());
+        let raw = observations(1).remove(0);
+        assert!(matches!(
+            checked_features(&raw),
+            Err(FeatureError::Undefined(_))
+        ));
+        let mut df = crate::model::data_handling::TrainingFrame {
+            data: observations(max_lookback() + 2 * ROLLING_WINDOW),
+        };
+        df.calculate_rolling_features();
+        let features = checked_features(df.data.last().unwrap()).unwrap();
+        assert_eq!(features.len(), feature_size());
+        // a timestamp past the range of dates has no day of the week
+        let mut far = df.data.last().unwrap().clone();
+        far.timestamp = i64::MAX;
+        assert_eq!(
+            checked_features(&far),
+            Err(FeatureError::Undefined("day_of_week_sin"))
+        );
+        // the windowed order flow and trade flow features declare their windows
+        let lookback = |name| FEATURES.iter().find(|f| f.name == name).unwrap().lookback;
+        assert_eq!(max_lookback(), 2 * VPIN_VOLUME_TRADES);
+        assert_eq!(lookback("ofi_rolling"), Window::Count(ORDER_FLOW_WINDOW));
+        assert_eq!(lookback("vpin"), Window::Count(2 * VPIN_VOLUME_TRADES));
+        assert_eq!(lookback("buy_share_60s"), Window::Time(60_000));
+        assert_eq!(
+            checked("qty_std", Some(f64::NAN)),
+            Err(FeatureError::NonFinite("qty_std"))
+        );
+        assert_eq!(
+            checked("qty_std", Some(f64::INFINITY)),
+            Err(FeatureError::NonFinite("qty_std"))
+        );
+    }
+
+    #[test]
+    fn refuses_a_mismatched_schema() {
+        let model_path = std::env::temp_dir()
+            .join(format!("schema-{}.model", std::process::id()))
+            .to_str()
+            .unwrap()
+            .to_string();
+        let current = FeatureSchema::current();
+        current.save(&model_path).unwrap();
+        assert!(current.check_saved(&model_path).is_ok());
+        assert_eq!(FeatureSchema::load(&model_path).unwrap(), current);
+
+        // renaming, reordering or rewindowing a feature changes the hash
+        let mut renamed = current.names.clone();
+        renamed[0].push_str("_v2");
+        let mut reordered = current.names.clone();
+        reordered.swap(0, 1);
+        let mut rewindowed = current.lookbacks.clone();
+        rewindowed[0] = Window::Time(1);
+        let changed = [
+            FeatureSchema::new(renamed, current.lookbacks.clone()),
+            FeatureSchema::new(reordered, current.lookbacks.clone()),
+            FeatureSchema::new(current.names.clone(), rewindowed),
+            FeatureSchema::new(current.names[1..].to_vec(), current.lookbacks[1..].to_vec()),
+        ];
+        for schema in &changed {
+            assert_ne!(schema.hash, current.hash);
+            let error = schema.check_saved(&model_path).unwrap_err();
+            assert!(error.to_string().contains(&current.hash));
+        }
+        assert_eq!(
+            FeatureSchema::new(current.names.clone(), current.lookbacks.clone()).hash,
+            current.hash
+        );
+
+        std::fs::remove_file(FeatureSchema::path_for(&model_path)).unwrap();
+        assert!(current.check_saved(&model_path).is_err());
+    }
+}
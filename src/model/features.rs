@@ -1,94 +1,622 @@
-use gbdt::decision_tree::{Data, DataVec, PredVec};
-use log::info;
-use rust_decimal::prelude::ToPrimitive;
+use std::{error::Error, sync::Arc, time::Instant};
+
+use chrono::Utc;
+use log::{error, info, warn};
+use rust_decimal::Decimal;
 
 use crate::{
-    binance::models::{fapi_exchange_info::Symbol, model_config::ModelMutex},
-    TRAINING_INTERVAL,
+    binance::models::{
+        fapi_exchange_info::Symbol,
+        model_config::{load_model_data, ModelData, ModelSwap},
+    },
+    monitoring::Telemetry,
+    EMBARGO, TRAINING_INTERVAL, WALK_FORWARD_FOLDS,
 };
 
-use super::data_handling::Dfrwl;
+use super::{
+    data_handling::{Dfrwl, TrainingFrame},
+    drift::TrainingDistribution,
+    evaluation::{fold_metrics, predict_folds, Dataset, Fold, FoldMetrics, WalkForward},
+    feature_registry::feature_size,
+    introspection::ModelReport,
+    predictor::Predictor,
+    registry::TrainingWindow,
+    store::Snapshot,
+    weighting::{effective_rows, SampleWeighting},
+};
 
-pub async fn manage_model(dataframe_rwl: Dfrwl, market: Symbol, model_mutex: ModelMutex) {
+/// Retrains the served model every `TRAINING_INTERVAL`. A version promoted in the registry by
+/// hand, with `models promote` or `models rollback`, is served instead of retraining that time.
+pub async fn manage_model(
+    dataframe_rwl: Dfrwl,
+    market: Symbol,
+    model_swap: ModelSwap,
+    weighting: SampleWeighting,
+    telemetry: Telemetry,
+) {
+    publish(
+        &model_swap,
+        ModelData::clone(&model_swap.load()),
+        &telemetry,
+    )
+    .await;
     let mut interval = tokio::time::interval(std::time::Duration::from_secs(TRAINING_INTERVAL));
     interval.tick().await;
     loop {
         interval.tick().await;
-        info!("Training model...");
-        let tick_size = market.get_tick_size().unwrap();
-        let mut features = dataframe_rwl.read().await.clone();
-        features.calculate_rolling_features();
-        features.drop_na_without_target();
-        features.add_target_value(tick_size);
-        let mut train = features.data.clone();
-        // the first half of the train
-        let test = train.split_off(train.len() / 2);
-        if train.is_empty() {
-            info!("No training data");
+        if follow_promoted(&model_swap, &telemetry).await {
             continue;
         }
-        let mut train_dv: DataVec = DataVec::from_iter(train.into_iter().map(|row| {
-            Data::new_training_data(
-                row.to_training_data().to_vec(),
-                1.0,
-                row.target.unwrap().to_f32().unwrap(),
-                None,
-            )
-        }));
-        let y_test = test
-            .iter()
-            .map(|x| x.target.unwrap().to_f32().unwrap())
-            .collect::<Vec<f32>>();
-        let test_dv: DataVec = DataVec::from_iter(test.into_iter().map(|row| {
-            Data::new_test_data(
-                row.to_training_data().to_vec(),
-                Some(row.target.unwrap().to_f32().unwrap()),
-            )
-        }));
-        let mut gbdt = model_mutex.lock().await;
-        gbdt.model.conf.feature_size = 18;
-        info!("Fitting model...");
-        gbdt.model.fit(&mut train_dv);
-        gbdt.model
-            .save_model("gbdt.model")
-            .expect("failed to save the model");
-        // load model and do inference
-        let predicted: PredVec = gbdt.model.predict(&test_dv);
-        let tick_size = tick_size.to_f32().unwrap();
-        let predicted: Vec<i32> = predicted
-            .into_iter()
-            .map(|x| ((x / tick_size).round() * tick_size) as i32)
-            .collect();
-        let predicted: Vec<i32> = predicted
-            .into_iter()
-            .map(|x| if x == -0 { 0 } else { x })
-            .collect();
-        let mut same_sign = 0;
-        let mut exact_same = 0;
-        let mut error_sum = 0.0;
-        for (i, prediction) in predicted.iter().enumerate() {
-            if (y_test[i] as i32 * prediction) >= 0 {
-                same_sign += 1;
-            }
-            if y_test[i] == *prediction as f32 {
-                exact_same += 1;
+        let tick_size = market.get_tick_size().unwrap();
+        train_model(
+            &dataframe_rwl,
+            tick_size,
+            &model_swap,
+            weighting,
+            &telemetry,
+        )
+        .await;
+    }
+}
+
+/// Serves the registry's promoted version if it isn't the served one. Returns true if the model
+/// was swapped.
+async fn follow_promoted(model_swap: &ModelSwap, telemetry: &Telemetry) -> bool {
+    let served = model_swap.load_full();
+    let promoted = match served.registry.promoted() {
+        Ok(Some(version)) if Some(version) != served.registry_version => version,
+        Ok(_) => return false,
+        Err(e) => {
+            warn!("Could not read the promoted model version: {}", e);
+            return false;
+        }
+    };
+    let loaded = load_model_data(
+        served.kind,
+        served.labeler,
+        &served.gbdt_params,
+        &served.registry,
+        promoted,
+    )
+    .map_err(|e| e.to_string());
+    match loaded {
+        Ok(mut model_data) => {
+            info!(
+                "Serving model version {} promoted in the registry",
+                promoted
+            );
+            model_data.version = served.version + 1;
+            publish(model_swap, model_data, telemetry).await;
+            true
+        }
+        Err(e) => {
+            warn!("Not serving promoted model version {}: {}", promoted, e);
+            false
+        }
+    }
+}
+
+/// Serves `model_data` and reports it in the status and metrics.
+async fn publish(model_swap: &ModelSwap, model_data: ModelData, telemetry: &Telemetry) {
+    if let Some(mae) = model_data.mae {
+        telemetry.metrics.model_mae.set(mae as i64);
+    }
+    let mut status = telemetry.status.write().await;
+    status.model_version = model_data.version;
+    status.registry_version = model_data.registry_version;
+    status.model_mae = model_data.mae;
+    status.last_training_at = model_data.trained_at;
+    model_swap.store(Arc::new(model_data));
+}
+
+/// Scores of a candidate's walk-forward fold models and of the served model on the same test
+/// rows, those newer than the served model's training window.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Comparison {
+    pub rows: usize,
+    pub candidate: FoldMetrics,
+    pub incumbent: FoldMetrics,
+}
+
+/// A model fitted on a whole dataset, with its walk-forward scores and its comparison with the
+/// served model.
+pub(super) struct Evaluation {
+    pub candidate: Box<dyn Predictor>,
+    pub metrics: FoldMetrics,
+    /// None if the served model was never fitted.
+    pub incumbent: Option<Comparison>,
+    training_window: TrainingWindow,
+    report: ModelReport,
+    distribution: TrainingDistribution,
+}
+impl Evaluation {
+    /// True if the candidate should replace the served model: it was never fitted, or the
+    /// candidate beats it on rows neither was trained on. Without such rows the served model is
+    /// kept.
+    pub fn improves(&self) -> bool {
+        match &self.incumbent {
+            Some(comparison) => {
+                comparison.rows > 0 && comparison.candidate.beats(&comparison.incumbent)
             }
-            error_sum += (y_test[i] - *prediction as f32).abs();
+            None => true,
         }
-        let average_error = error_sum / predicted.len() as f32;
-        info!("Average error: {}", average_error);
-        //round the average error to the nearest multiple of the tick size
-        let average_error = ((average_error / tick_size).round() * tick_size) as i32;
-        info!("Average error: {}", average_error);
+    }
+    /// The served model with the candidate in its place.
+    pub fn replace(self, served: &ModelData) -> ModelData {
+        let mut model_data = ModelData::clone(served);
+        model_data.model = Arc::from(self.candidate);
+        model_data.fitted = true;
+        model_data.mae = Some(std::cmp::max(self.metrics.mae.round() as i32, 4));
+        model_data.version += 1;
+        model_data.trained_at = Some(Utc::now());
+        model_data.training_window = Some(self.training_window);
+        model_data.metrics = Some(self.metrics);
+        model_data.report = Some(self.report);
+        model_data.distribution = Some(self.distribution);
+        model_data
+    }
+}
+
+/// Labels a snapshot of the live observations for the served model and evaluates a candidate on
+/// it, see [`evaluate_dataset`].
+fn evaluate_candidate(
+    snapshot: Snapshot,
+    tick_size: Decimal,
+    served: &ModelData,
+    weighting: SampleWeighting,
+) -> Result<Option<Evaluation>, Box<dyn Error>> {
+    let mut features = TrainingFrame::from(snapshot);
+    features.prepare(&served.labeler, tick_size);
+    evaluate_dataset(Dataset::from_rows(&features.data)?, served, weighting)
+}
+
+/// Scores a new model of the served model's kind with walk-forward validation on `dataset`,
+/// compares it with the served model if it was ever fitted, then fits the new model on all rows.
+/// Rows are weighted with `weighting`. The report's holdout is the last fold. Returns None without
+/// enough rows for a fold, and an error if a model can't be created.
+pub(super) fn evaluate_dataset(
+    mut dataset: Dataset,
+    served: &ModelData,
+    weighting: SampleWeighting,
+) -> Result<Option<Evaluation>, Box<dyn Error>> {
+    let walk_forward = WalkForward {
+        folds: WALK_FORWARD_FOLDS,
+        horizon: served.labeler.horizon(),
+        embargo: EMBARGO,
+    };
+    let folds = walk_forward.folds(&dataset.timestamps);
+    if folds.is_empty() {
+        return Ok(None);
+    }
+    let kind = served.kind;
+    dataset.w = weighting.weights(
+        &dataset.timestamps,
+        &dataset.y,
+        served.labeler.horizon(),
+        kind.class_thresholds(&served.labeler).as_ref(),
+    );
+    info!(
+        "Sample weights {:?}: min {:.3}, max {:.3}, {:.0} effective of {} rows",
+        weighting,
+        dataset.w.iter().copied().fold(f32::INFINITY, f32::min),
+        dataset.w.iter().copied().fold(0.0, f32::max),
+        effective_rows(&dataset.w),
+        dataset.len()
+    );
+    info!("Fitting model...");
+    let labeler = &served.labeler;
+    let predictions = predict_folds(&dataset, &folds, || {
+        kind.new_predictor(feature_size(), labeler, &served.gbdt_params)
+    })?;
+    let metrics = FoldMetrics::mean(&fold_metrics(&dataset, &folds, &predictions));
+    let incumbent = served
+        .fitted
+        .then(|| compare_incumbent(served, &dataset, &walk_forward, &folds, &predictions));
+    let mut candidate = kind.new_predictor(feature_size(), labeler, &served.gbdt_params)?;
+    candidate.fit(&dataset.x, &dataset.y, &dataset.w);
+    let holdout = folds.last().unwrap();
+    let mut holdout_model = kind.new_predictor(feature_size(), labeler, &served.gbdt_params)?;
+    holdout_model.fit(
+        &dataset.x[holdout.train.clone()],
+        &dataset.y[holdout.train.clone()],
+        &dataset.w[holdout.train.clone()],
+    );
+    let report = ModelReport::new(
+        candidate.as_ref(),
+        &dataset,
+        holdout_model.as_ref(),
+        &dataset.slice(holdout.test.clone()),
+    );
+    for feature in report.top_features(5) {
         info!(
-            "Same sign %: {:.2}",
-            100.0 * (same_sign as f32 / predicted.len() as f32)
+            "Feature {}: {} splits, gain {:.4}, permutation {:.4}",
+            feature.name, feature.splits, feature.gain, feature.permutation
         );
-        info!(
-            "Exact same: {:.2}%",
-            100.0 * (exact_same as f32 / predicted.len() as f32)
+    }
+    if let Some(depth) = &report.depth {
+        info!("Trees: {:?}", depth);
+    }
+    Ok(Some(Evaluation {
+        candidate,
+        metrics,
+        incumbent,
+        training_window: TrainingWindow {
+            first_timestamp: dataset.timestamps[0],
+            last_timestamp: *dataset.timestamps.last().unwrap(),
+            rows: dataset.len(),
+        },
+        report,
+        distribution: TrainingDistribution::fit(&dataset.x),
+    }))
+}
+
+/// Scores the candidate's fold predictions and the served model on the test rows past the served
+/// model's training window, its label horizon and the embargo, so none share a label with the rows
+/// it was fitted on.
+fn compare_incumbent(
+    served: &ModelData,
+    dataset: &Dataset,
+    walk_forward: &WalkForward,
+    folds: &[Fold],
+    fold_predictions: &[Vec<f32>],
+) -> Comparison {
+    let first_unseen = match served.training_window {
+        Some(window) => walk_forward.first_unseen(&dataset.timestamps, window.last_timestamp),
+        None => dataset.len(),
+    };
+    let (mut rows, mut candidate, mut targets) = (Vec::new(), Vec::new(), Vec::new());
+    for (fold, predictions) in folds.iter().zip(fold_predictions) {
+        for (row, prediction) in fold.test.clone().zip(predictions) {
+            if row >= first_unseen {
+                rows.push(dataset.x[row].clone());
+                candidate.push(*prediction);
+                targets.push(dataset.y[row]);
+            }
+        }
+    }
+    if rows.is_empty() {
+        return Comparison {
+            rows: 0,
+            candidate: FoldMetrics::default(),
+            incumbent: FoldMetrics::default(),
+        };
+    }
+    Comparison {
+        rows: rows.len(),
+        candidate: FoldMetrics::compute(&candidate, &targets),
+        incumbent: FoldMetrics::compute(&served.model.predict(&rows), &targets),
+    }
+}
+
+/// Scores a new model with walk-forward validation on a snapshot of the dataframe, then fits it
+/// on the whole snapshot, all on the blocking thread pool while the served model keeps serving.
+/// The new model is saved to the registry, also off the runtime, promoted and swapped in if the
+/// served one has never been fitted, or if the new one beats it out-of-sample. If it can't be
+/// saved, or training panics, the served model is kept. Returns true if the model was replaced.
+pub async fn train_model(
+    dataframe_rwl: &Dfrwl,
+    tick_size: Decimal,
+    model_swap: &ModelSwap,
+    weighting: SampleWeighting,
+    telemetry: &Telemetry,
+) -> bool {
+    info!("Training model...");
+    let snapshot = dataframe_rwl.read().await.snapshot();
+    let served = model_swap.load_full();
+    let metrics = telemetry.metrics.clone();
+    let trained = tokio::task::spawn_blocking(move || {
+        let fit_started = Instant::now();
+        let evaluation = match evaluate_candidate(snapshot, tick_size, &served, weighting) {
+            Ok(Some(evaluation)) => evaluation,
+            Ok(None) => {
+                info!("No training data");
+                return None;
+            }
+            Err(e) => {
+                error!("Training failed: {}", e);
+                return None;
+            }
+        };
+        metrics
+            .training_duration
+            .observe(fit_started.elapsed().as_secs_f64());
+        info!("Candidate: {:?}", evaluation.metrics);
+        if let Some(comparison) = evaluation.incumbent {
+            info!("Against the incumbent: {:?}", comparison);
+        }
+        if !evaluation.improves() {
+            info!("Keeping the incumbent model");
+            metrics
+                .model_evaluations
+                .with_label_values(&["rejected"])
+                .inc();
+            return None;
+        }
+        let mut model_data = evaluation.replace(&served);
+        match model_data.save() {
+            Ok(version) => {
+                info!(
+                    "Promoted model version {}: {}",
+                    version,
+                    model_data.model.describe()
+                );
+                Some(model_data)
+            }
+            Err(e) => {
+                error!(
+                    "Keeping the incumbent model, the new one can't be saved: {}",
+                    e
+                );
+                None
+            }
+        }
+    })
+    .await;
+    let model_data = match trained {
+        Ok(Some(model_data)) => model_data,
+        Ok(None) => return false,
+        Err(e) => {
+            error!("Training stopped: {}", e);
+            return false;
+        }
+    };
+    telemetry
+        .metrics
+        .model_evaluations
+        .with_label_values(&["promoted"])
+        .inc();
+    publish(model_swap, model_data, telemetry).await;
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        binance::models::model_config::new_model_data,
+        model::{
+            data_handling::new_dataframe_rwl,
+            labeling::Labeler,
+            predictor::{gbdt::GbdtParams, ModelKind},
+            store::Retention,
+        },
+        monitoring::new_telemetry,
+        test_support::{self, dec, observations, temp_registry},
+        ROLLING_WINDOW,
+    };
+
+    #[tokio::test]
+    async fn train_model_fits_and_publishes_the_model() {
+        let dataframe_rwl = new_dataframe_rwl(Retention::default());
+        let mut df = dataframe_rwl.write().await;
+        for observation in observations(2 * ROLLING_WINDOW + 600) {
+            df.push(observation);
+        }
+        drop(df);
+        let model_swap = new_model_data(
+            ModelKind::Gbdt,
+            Labeler::default(),
+            GbdtParams::default(),
+            temp_registry(),
+        )
+        .unwrap();
+        let telemetry = new_telemetry(test_support::SYMBOL);
+        let served = model_swap.load_full();
+
+        assert!(
+            train_model(
+                &dataframe_rwl,
+                dec(test_support::TICK_SIZE),
+                &model_swap,
+                SampleWeighting::default(),
+                &telemetry
+            )
+            .await
+        );
+
+        // the model served during training is left untouched and replaced whole
+        assert!(!served.fitted);
+        assert!(!Arc::ptr_eq(&served, &model_swap.load_full()));
+        let model = ModelData::clone(&model_swap.load());
+        assert_eq!(model.version, 1);
+        assert!(model.trained_at.is_some());
+        assert!(model.mae.unwrap() >= 4);
+        let version = model.registry_version.unwrap();
+        assert_eq!(model.registry.promoted().unwrap(), Some(version));
+        let status = telemetry.status.read().await;
+        assert_eq!(status.model_version, 1);
+        assert_eq!(status.registry_version, Some(version));
+        assert_eq!(status.model_mae, model.mae);
+        assert_eq!(telemetry.metrics.training_duration.get_sample_count(), 1);
+
+        let metadata = model.registry.metadata(version).unwrap();
+        assert_eq!(metadata.parent, None);
+        assert_eq!(metadata.mae, model.mae.unwrap());
+        let window = metadata.training_window;
+        assert!(window.rows > 0 && window.first_timestamp < window.last_timestamp);
+        let report = model.registry.report(version).unwrap();
+        assert_eq!(report.rows, window.rows);
+        assert_eq!(report.features.len(), feature_size());
+        assert!(report.holdout_rows > 0 && report.holdout_rows < report.rows);
+        let reloaded = new_model_data(
+            ModelKind::Gbdt,
+            Labeler::default(),
+            GbdtParams::default(),
+            model.registry.clone(),
+        )
+        .unwrap();
+        let reloaded = reloaded.load();
+        assert!(reloaded.fitted);
+        assert_eq!(reloaded.registry_version, Some(version));
+        assert_eq!(reloaded.distribution, model.distribution);
+        assert_eq!(
+            reloaded.distribution.as_ref().unwrap().features.len(),
+            feature_size()
+        );
+    }
+
+    #[tokio::test]
+    async fn train_model_skips_without_enough_data() {
+        let dataframe_rwl = new_dataframe_rwl(Retention::default());
+        let mut df = dataframe_rwl.write().await;
+        for observation in observations(10) {
+            df.push(observation);
+        }
+        drop(df);
+        let model_swap = new_model_data(
+            ModelKind::Gbdt,
+            Labeler::default(),
+            GbdtParams::default(),
+            temp_registry(),
+        )
+        .unwrap();
+        let telemetry = new_telemetry(test_support::SYMBOL);
+        assert!(
+            !train_model(
+                &dataframe_rwl,
+                dec(test_support::TICK_SIZE),
+                &model_swap,
+                SampleWeighting::default(),
+                &telemetry
+            )
+            .await
+        );
+        assert_eq!(model_swap.load().version, 0);
+    }
+
+    #[tokio::test]
+    async fn train_model_keeps_an_incumbent_without_newer_rows() {
+        let dataframe_rwl = new_dataframe_rwl(Retention::default());
+        let mut df = dataframe_rwl.write().await;
+        for observation in observations(2 * ROLLING_WINDOW + 600) {
+            df.push(observation);
+        }
+        drop(df);
+        let model_swap = new_model_data(
+            ModelKind::Ridge,
+            Labeler::default(),
+            GbdtParams::default(),
+            temp_registry(),
+        )
+        .unwrap();
+        let telemetry = new_telemetry(test_support::SYMBOL);
+        let tick_size = dec(test_support::TICK_SIZE);
+        for promoted in [true, false] {
+            // the second candidate has no rows the incumbent wasn't trained on to beat it on
+            assert_eq!(
+                train_model(
+                    &dataframe_rwl,
+                    tick_size,
+                    &model_swap,
+                    SampleWeighting::default(),
+                    &telemetry
+                )
+                .await,
+                promoted
+            );
+        }
+        assert_eq!(model_swap.load().version, 1);
+        let evaluations = &telemetry.metrics.model_evaluations;
+        assert_eq!(evaluations.with_label_values(&["promoted"]).get(), 1);
+        assert_eq!(evaluations.with_label_values(&["rejected"]).get(), 1);
+    }
+
+    #[tokio::test]
+    async fn follows_versions_promoted_by_hand() {
+        let dataframe_rwl = new_dataframe_rwl(Retention::default());
+        let mut df = dataframe_rwl.write().await;
+        for observation in observations(2 * ROLLING_WINDOW + 600) {
+            df.push(observation);
+        }
+        drop(df);
+        let model_swap = new_model_data(
+            ModelKind::Ridge,
+            Labeler::default(),
+            GbdtParams::default(),
+            temp_registry(),
+        )
+        .unwrap();
+        let telemetry = new_telemetry(test_support::SYMBOL);
+        assert!(
+            train_model(
+                &dataframe_rwl,
+                dec(test_support::TICK_SIZE),
+                &model_swap,
+                SampleWeighting::default(),
+                &telemetry
+            )
+            .await
+        );
+        assert!(!follow_promoted(&model_swap, &telemetry).await);
+
+        let registry = model_swap.load().registry.clone();
+        let second = model_swap.load().register().unwrap();
+        registry.promote(second).unwrap();
+        assert!(follow_promoted(&model_swap, &telemetry).await);
+        assert_eq!(model_swap.load().registry_version, Some(second));
+        assert_eq!(telemetry.status.read().await.registry_version, Some(second));
+
+        let first = registry.rollback().unwrap();
+        assert!(follow_promoted(&model_swap, &telemetry).await);
+        assert_eq!(model_swap.load().registry_version, Some(first));
+        assert!(!follow_promoted(&model_swap, &telemetry).await);
+        // every swap is a new served version, so the drift monitor starts over
+        assert_eq!(model_swap.load().version, 3);
+    }
+
+    #[tokio::test]
+    async fn train_model_promotes_a_candidate_that_beats_the_incumbent_on_newer_rows() {
+        let dataframe_rwl = new_dataframe_rwl(Retention::default());
+        let mut df = dataframe_rwl.write().await;
+        for observation in observations(2 * ROLLING_WINDOW + 600) {
+            df.push(observation);
+        }
+        let mut frame = TrainingFrame::from(df.snapshot());
+        drop(df);
+        let tick_size = dec(test_support::TICK_SIZE);
+        frame.prepare(&Labeler::default(), tick_size);
+        let dataset = Dataset::from_rows(&frame.data).unwrap();
+
+        // an incumbent fitted to the opposite of the targets, trained before every row
+        let mut inverted = ModelKind::Ridge
+            .new_predictor(feature_size(), &Labeler::default(), &GbdtParams::default())
+            .unwrap();
+        let targets: Vec<f32> = dataset.y.iter().map(|y| -y).collect();
+        inverted.fit(&dataset.x, &targets, &dataset.w);
+        let mut served = ModelData::new(
+            ModelKind::Ridge,
+            Labeler::default(),
+            GbdtParams::default(),
+            inverted,
+            true,
+            temp_registry(),
+        );
+        served.training_window = Some(TrainingWindow {
+            first_timestamp: dataset.timestamps[0] - 1000,
+            last_timestamp: dataset.timestamps[0] - 1,
+            rows: 10,
+        });
+        let model_swap: ModelSwap = Arc::new(arc_swap::ArcSwap::from_pointee(served));
+
+        let evaluation = evaluate_dataset(dataset, &model_swap.load(), SampleWeighting::default())
+            .unwrap()
+            .unwrap();
+        let comparison = evaluation.incumbent.unwrap();
+        assert!(comparison.rows > 0);
+        assert!(comparison.candidate.mae < comparison.incumbent.mae);
+        let telemetry = new_telemetry(test_support::SYMBOL);
+        assert!(
+            train_model(
+                &dataframe_rwl,
+                tick_size,
+                &model_swap,
+                SampleWeighting::default(),
+                &telemetry
+            )
+            .await
         );
-        let real_mae = std::cmp::max(average_error, 4);
-        gbdt.mae = Some(real_mae);
+        assert_eq!(model_swap.load().version, 1);
+        let evaluations = &telemetry.metrics.model_evaluations;
+        assert_eq!(evaluations.with_label_values(&["promoted"]).get(), 1);
     }
 }
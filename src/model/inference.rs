@@ -1,65 +1,218 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Instant};
 
-use gbdt::decision_tree::{Data, DataVec, PredVec};
+use chrono::Utc;
 
-use log::{debug, info};
-use rust_decimal::prelude::ToPrimitive;
+use log::{debug, info, warn};
+use rust_decimal::{prelude::ToPrimitive, Decimal};
 use tokio::sync::{mpsc, Notify};
 
 use crate::{
-    binance::models::{fapi_exchange_info::Symbol, model_config::ModelMutex},
-    ROLLING_WINDOW,
+    binance::{
+        models::{fapi_exchange_info::Symbol, model_config::ModelSwap},
+        websocket::requests::Stream,
+    },
+    monitoring::{latency::LatencyStage, status::PredictionStatus, Telemetry},
+    KS_HALT_THRESHOLD, KS_WARN_THRESHOLD, MIN_BARRIER_SIGNAL, MIN_CLASS_PROBABILITY,
+    PSI_HALT_THRESHOLD, PSI_WARN_THRESHOLD,
 };
 
-use super::data_handling::Dfrwl;
+use super::{
+    data_handling::Dfrwl,
+    drift::{DriftAction, DriftMonitor, FeatureDrift},
+    feature_registry::{checked_features, max_lookback},
+};
 
 pub async fn make_predictions(
     dataframe_rwl: Dfrwl,
     market: Symbol,
-    model_mutex: ModelMutex,
+    model_swap: ModelSwap,
     notify: Arc<Notify>,
     order_send: mpsc::Sender<String>,
+    drift_action: DriftAction,
+    telemetry: Telemetry,
 ) {
+    let trade_stream = Stream::Trade(market.symbol.clone()).to_string();
     let mut position = 0;
-    let mut entry_index = None;
+    // observation count and timestamp of the position's entry
+    let mut entry = None;
+    let mut entry_price = None;
+    let mut realized_pnl = Decimal::ZERO;
+    let mut drift: Option<DriftMonitor> = None;
+    // no new positions are opened while halted
+    let mut halted = false;
     loop {
         notify.notified().await;
-        let df = dataframe_rwl.read().await.clone();
-        let ts_index = df.data.len();
+        let (ts_index, last) = {
+            let df = dataframe_rwl.read().await;
+            (df.pushed(), df.last().cloned())
+        };
         let tick_size = market.get_tick_size().unwrap();
-        if ts_index > ROLLING_WINDOW {
-            let test = df.data.last().unwrap();
+        if ts_index > max_lookback() {
+            let test = last.unwrap();
             if test.has_rolling_features() {
-                let test_dv: DataVec =
-                    vec![Data::new_test_data(test.to_training_data().to_vec(), None)];
-                let mut gbdt = model_mutex.lock().await;
-                gbdt.model.conf.feature_size = 18;
-                let predicted: PredVec = gbdt.model.predict(&test_dv);
-                let round_pred = ((predicted.first().unwrap() / tick_size.to_f32().unwrap())
-                    .round()
+                let model_data = model_swap.load_full();
+                let features = match checked_features(&test) {
+                    Ok(features) => features,
+                    Err(e) => {
+                        warn!("Skipping prediction: {}", e);
+                        telemetry
+                            .metrics
+                            .feature_errors
+                            .with_label_values(&[e.feature()])
+                            .inc();
+                        continue;
+                    }
+                };
+                if drift.as_ref().map(|monitor| monitor.model_version) != Some(model_data.version) {
+                    drift = model_data
+                        .distribution
+                        .clone()
+                        .map(|distribution| DriftMonitor::new(distribution, model_data.version));
+                    halted = false;
+                }
+                if let Some(checked) = drift.as_mut().and_then(|monitor| monitor.push(&features)) {
+                    let exceeded = report_drift(&checked, &telemetry);
+                    let halt = exceeded && drift_action == DriftAction::Halt;
+                    if halt != halted {
+                        if halt {
+                            warn!(
+                                "Features drifted past the halt thresholds, not opening positions"
+                            );
+                        } else {
+                            info!(
+                                "Features drifted back under the halt thresholds, trading resumes"
+                            );
+                        }
+                        halted = halt;
+                    }
+                }
+                let row = [features];
+                let predicted = model_data.model.predict(&row);
+                let probabilities = model_data
+                    .model
+                    .class_probabilities(&row)
+                    .map(|probabilities| probabilities[0]);
+                let predicted_at = Instant::now();
+                telemetry.metrics.predictions.inc();
+                telemetry.latency.write().await.record_since_handled(
+                    &trade_stream,
+                    LatencyStage::HandledToPrediction,
+                    predicted_at,
+                );
+                let prediction = *predicted.first().unwrap();
+                let round_pred = ((prediction / tick_size.to_f32().unwrap()).round()
                     * tick_size.to_f32().unwrap()) as i32;
-                let exit = ts_index >= entry_index.unwrap_or(1000000000000) + ROLLING_WINDOW;
+                // classifiers trade on their probabilities, regressors on a large enough target,
+                // and meta labels on the primary signal's side
+                let labeler = &model_data.labeler;
+                let (signal, direction) = match probabilities {
+                    Some(probabilities) => {
+                        let direction = labeler
+                            .side(probabilities.direction(MIN_CLASS_PROBABILITY) as f32, &test);
+                        (direction != 0, direction)
+                    }
+                    None if labeler.in_ticks() => (
+                        round_pred >= model_data.mae.unwrap_or(100000000),
+                        round_pred.signum(),
+                    ),
+                    None if prediction.abs() >= MIN_BARRIER_SIGNAL => {
+                        let direction = labeler.side(prediction, &test);
+                        (direction != 0, direction)
+                    }
+                    None => (false, 0),
+                };
+                let exit = matches!(entry, Some((index, timestamp))
+                    if !model_data.labeler.horizon().contains(ts_index - index, test.timestamp - timestamp));
                 if exit {
                     info!("Exit price {}", test.price);
+                    realized_pnl += closed_pnl(position, entry_price, test.price);
                     position = 0;
-                    entry_index = None;
+                    entry = None;
+                    entry_price = None;
                 }
-                if round_pred >= gbdt.mae.unwrap_or(100000000) || exit {
-                    if round_pred > 0 && position != 1 {
+                if (signal || exit) && !halted {
+                    if direction > 0 && position != 1 {
                         info!("Buy price {}", test.price);
                         order_send.send("buy!".to_string()).await.unwrap();
-                        entry_index = Some(ts_index);
+                        telemetry.latency.write().await.record(
+                            &trade_stream,
+                            LatencyStage::PredictionToOrder,
+                            predicted_at.elapsed(),
+                        );
+                        telemetry.metrics.orders.with_label_values(&["buy"]).inc();
+                        realized_pnl += closed_pnl(position, entry_price, test.price);
+                        entry = Some((ts_index, test.timestamp));
+                        entry_price = Some(test.price);
                         position = 1;
-                    } else if round_pred < 0 && position != -1 {
+                    } else if direction < 0 && position != -1 {
                         info!("Sell price {}", test.price);
                         order_send.send("sell!".to_string()).await.unwrap();
-                        entry_index = Some(ts_index);
+                        telemetry.latency.write().await.record(
+                            &trade_stream,
+                            LatencyStage::PredictionToOrder,
+                            predicted_at.elapsed(),
+                        );
+                        telemetry.metrics.orders.with_label_values(&["sell"]).inc();
+                        realized_pnl += closed_pnl(position, entry_price, test.price);
+                        entry = Some((ts_index, test.timestamp));
+                        entry_price = Some(test.price);
                         position = -1;
                     }
                 }
+                {
+                    let mut status = telemetry.status.write().await;
+                    status.last_prediction = Some(PredictionStatus {
+                        ticks: round_pred,
+                        probabilities,
+                        price: test.price,
+                        time: Utc::now(),
+                    });
+                    status.position = position;
+                    status.trading_halted = halted;
+                }
+                telemetry.metrics.position.set(position as i64);
+                telemetry.metrics.trading_halted.set(halted as i64);
+                telemetry
+                    .metrics
+                    .realized_pnl
+                    .set(realized_pnl.to_f64().unwrap_or_default());
             }
         } else {
             debug!("Not enough data {}", ts_index);
         }
     }
 }
+
+/// Exports the drift of every feature and warns of those past the warning thresholds. Returns
+/// true if any is past the halt thresholds.
+fn report_drift(drift: &[FeatureDrift], telemetry: &Telemetry) -> bool {
+    for feature in drift {
+        telemetry
+            .metrics
+            .feature_psi
+            .with_label_values(&[&feature.name])
+            .set(feature.psi as f64);
+        telemetry
+            .metrics
+            .feature_ks
+            .with_label_values(&[&feature.name])
+            .set(feature.ks as f64);
+        if feature.exceeds(PSI_WARN_THRESHOLD, KS_WARN_THRESHOLD) {
+            warn!(
+                "Feature {} drifted: PSI {:.3}, KS {:.3}",
+                feature.name, feature.psi, feature.ks
+            );
+        }
+    }
+    drift
+        .iter()
+        .any(|feature| feature.exceeds(PSI_HALT_THRESHOLD, KS_HALT_THRESHOLD))
+}
+
+/// PnL per unit of closing `position` opened at `entry_price` at `exit_price`.
+fn closed_pnl(position: i32, entry_price: Option<Decimal>, exit_price: Decimal) -> Decimal {
+    match entry_price {
+        Some(entry_price) => (exit_price - entry_price) * Decimal::from(position),
+        None => Decimal::ZERO,
+    }
+}
@@ -0,0 +1,456 @@
+use std::{collections::BTreeMap, error::Error};
+
+use clap::Args;
+use gbdt::gradient_boost::GBDT;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use super::{
+    evaluation::{Dataset, FoldMetrics},
+    feature_registry::FEATURES,
+    predictor::Predictor,
+    registry::ModelRegistry,
+};
+
+/// Latest holdout rows whose predictions are broken down into path contributions.
+const CONTRIBUTION_ROWS: usize = 10;
+/// Seed of the column shuffles of permutation importance, fixed so reports are comparable.
+const PERMUTATION_SEED: u64 = 0;
+
+/// The fields of a saved `gbdt` model the report reads, the rest are ignored.
+#[derive(Deserialize)]
+struct SavedGbdt {
+    conf: SavedConfig,
+    trees: Vec<SavedTree>,
+    #[serde(default)]
+    init_score: f32,
+}
+#[derive(Deserialize)]
+struct SavedConfig {
+    shrinkage: f32,
+}
+#[derive(Deserialize)]
+struct SavedTree {
+    tree: SavedNodes,
+}
+#[derive(Deserialize)]
+struct SavedNodes {
+    tree: Vec<SavedNode>,
+}
+/// A node of a `gbdt` binary tree, whose children are indices into the tree's nodes, 0 for none.
+#[derive(Deserialize)]
+struct SavedNode {
+    value: SavedSplit,
+    left: usize,
+    right: usize,
+}
+#[derive(Deserialize)]
+struct SavedSplit {
+    feature_index: usize,
+    feature_value: f32,
+    pred: f32,
+    is_leaf: bool,
+}
+
+/// A tree node. Rows whose feature is below the threshold go left, the others right.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeNode {
+    pub feature: usize,
+    /// In the units of [`Predictor::transform`], the preprocessed ones for preprocessed models.
+    pub threshold: f32,
+    /// The node's prediction, the mean residual of the rows it was fitted on.
+    pub pred: f32,
+    pub left: Option<usize>,
+    pub right: Option<usize>,
+}
+
+/// A regression tree, its root first.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tree {
+    pub nodes: Vec<TreeNode>,
+}
+impl Tree {
+    /// Indices of the nodes `row` passes through, from the root to its leaf.
+    pub fn path(&self, row: &[f32]) -> Vec<usize> {
+        let mut path = Vec::new();
+        let mut next = (!self.nodes.is_empty()).then_some(0);
+        while let Some(index) = next {
+            path.push(index);
+            let node = &self.nodes[index];
+            next = if row[node.feature] < node.threshold {
+                node.left
+            } else {
+                node.right
+            };
+        }
+        path
+    }
+    /// Edges from the root to the deepest leaf.
+    pub fn depth(&self) -> usize {
+        let mut deepest = 0;
+        let mut stack = vec![(0, 0)];
+        while let Some((index, depth)) = stack.pop() {
+            if index >= self.nodes.len() {
+                continue;
+            }
+            deepest = deepest.max(depth);
+            let node = &self.nodes[index];
+            stack.extend(node.left.iter().chain(&node.right).map(|c| (*c, depth + 1)));
+        }
+        deepest
+    }
+    pub fn leaves(&self) -> usize {
+        self.nodes
+            .iter()
+            .filter(|node| node.left.is_none() && node.right.is_none())
+            .count()
+    }
+}
+
+/// Boosted trees whose raw score is `init_score` plus `shrinkage` times the sum of the leaf
+/// predictions of each tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeEnsemble {
+    /// Which of the predictor's models this is, such as `gbdt` or a classifier's `up`.
+    pub name: String,
+    pub init_score: f32,
+    pub shrinkage: f32,
+    pub trees: Vec<Tree>,
+}
+impl TreeEnsemble {
+    /// Reads the trees of a fitted `gbdt` model from its serialized form.
+    pub fn from_gbdt(name: &str, model: &GBDT) -> Result<Self, Box<dyn Error>> {
+        let saved: SavedGbdt = serde_json::from_value(serde_json::to_value(model)?)?;
+        let child = |index: usize| (index != 0).then_some(index);
+        Ok(Self {
+            name: name.to_string(),
+            init_score: saved.init_score,
+            shrinkage: saved.conf.shrinkage,
+            trees: saved
+                .trees
+                .into_iter()
+                .map(|tree| Tree {
+                    nodes: tree
+                        .tree
+                        .tree
+                        .into_iter()
+                        .map(|node| TreeNode {
+                            feature: node.value.feature_index,
+                            threshold: node.value.feature_value,
+                            pred: node.value.pred,
+                            left: child(node.left).filter(|_| !node.value.is_leaf),
+                            right: child(node.right).filter(|_| !node.value.is_leaf),
+                        })
+                        .collect(),
+                })
+                .collect(),
+        })
+    }
+    /// Adds each split's count and gain to its feature: the drop in squared error of the node
+    /// predictions over the rows routed through it.
+    fn add_split_importance(&self, x: &[Vec<f32>], splits: &mut [usize], gains: &mut [f32]) {
+        for tree in &self.trees {
+            let mut rows = vec![0usize; tree.nodes.len()];
+            for row in x {
+                for index in tree.path(row) {
+                    rows[index] += 1;
+                }
+            }
+            for (index, node) in tree.nodes.iter().enumerate() {
+                if node.left.is_none() && node.right.is_none() {
+                    continue;
+                }
+                splits[node.feature] += 1;
+                let squares = |index: usize| rows[index] as f32 * tree.nodes[index].pred.powi(2);
+                let children: f32 = node
+                    .left
+                    .iter()
+                    .chain(&node.right)
+                    .map(|c| squares(*c))
+                    .sum();
+                gains[node.feature] += (children - squares(index)).max(0.0);
+            }
+        }
+    }
+    /// Saabas contributions of `row`: the score at the roots, and each feature's share of the
+    /// change in node prediction along the paths, which together add up to the raw score.
+    pub fn contributions(&self, row: &[f32]) -> (f32, Vec<f32>) {
+        let mut bias = self.init_score;
+        let mut contributions = vec![0.0; row.len()];
+        for tree in &self.trees {
+            let path = tree.path(row);
+            if let Some(root) = path.first() {
+                bias += self.shrinkage * tree.nodes[*root].pred;
+            }
+            for step in path.windows(2) {
+                let (parent, child) = (&tree.nodes[step[0]], &tree.nodes[step[1]]);
+                contributions[parent.feature] += self.shrinkage * (child.pred - parent.pred);
+            }
+        }
+        (bias, contributions)
+    }
+}
+
+/// How much a feature is used by the model and how much its predictions rely on it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FeatureImportance {
+    pub name: String,
+    /// Splits on the feature, over every tree.
+    pub splits: usize,
+    /// Drop in squared error of the splits on the feature, over the training rows.
+    pub gain: f32,
+    /// Rise in holdout MAE when the feature's values are shuffled.
+    pub permutation: f32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DepthStats {
+    pub trees: usize,
+    pub leaves: usize,
+    pub min: usize,
+    pub mean: f32,
+    pub max: usize,
+}
+
+/// One prediction broken down into the contribution of each feature along its tree paths.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PathContribution {
+    pub timestamp: i64,
+    pub ensemble: String,
+    pub prediction: f32,
+    /// Raw score at the tree roots, before any split.
+    pub bias: f32,
+    /// Non-zero contributions by feature name.
+    pub contributions: BTreeMap<String, f32>,
+}
+
+/// What a training run learned: feature importances, the shape of the trees and a breakdown of
+/// the latest predictions. Tree statistics are empty for models without trees.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelReport {
+    pub config: String,
+    pub rows: usize,
+    pub holdout_rows: usize,
+    /// By descending gain, then permutation importance.
+    pub features: Vec<FeatureImportance>,
+    pub depth: Option<DepthStats>,
+    pub contributions: Vec<PathContribution>,
+}
+impl ModelReport {
+    /// Reports on `model` fitted on `dataset`. Permutation importance is measured with
+    /// `holdout_model`, fitted on earlier rows only, on the `holdout` rows, and the latest
+    /// holdout predictions of `model` are broken down into path contributions. Rows walk the trees
+    /// as the model transforms them.
+    pub fn new(
+        model: &dyn Predictor,
+        dataset: &Dataset,
+        holdout_model: &dyn Predictor,
+        holdout: &Dataset,
+    ) -> Self {
+        let ensembles = model.trees();
+        let mut splits = vec![0; FEATURES.len()];
+        let mut gains = vec![0.0; FEATURES.len()];
+        let x = model.transform(&dataset.x);
+        for ensemble in &ensembles {
+            ensemble.add_split_importance(&x, &mut splits, &mut gains);
+        }
+        let permutation = permutation_importance(holdout_model, holdout);
+        let mut features: Vec<FeatureImportance> = FEATURES
+            .iter()
+            .enumerate()
+            .map(|(i, feature)| FeatureImportance {
+                name: feature.name.to_string(),
+                splits: splits[i],
+                gain: gains[i],
+                permutation: permutation[i],
+            })
+            .collect();
+        features.sort_by(|a, b| {
+            b.gain
+                .total_cmp(&a.gain)
+                .then(b.permutation.total_cmp(&a.permutation))
+        });
+        let trees: Vec<&Tree> = ensembles.iter().flat_map(|e| &e.trees).collect();
+        let depths: Vec<usize> = trees.iter().map(|tree| tree.depth()).collect();
+        let depth = (!trees.is_empty()).then(|| DepthStats {
+            trees: trees.len(),
+            leaves: trees.iter().map(|tree| tree.leaves()).sum(),
+            min: *depths.iter().min().unwrap(),
+            mean: depths.iter().sum::<usize>() as f32 / depths.len() as f32,
+            max: *depths.iter().max().unwrap(),
+        });
+        let start = holdout.len().saturating_sub(CONTRIBUTION_ROWS);
+        let latest = &holdout.x[start..];
+        let predictions = model.predict(latest);
+        let mut contributions = Vec::new();
+        for (i, row) in model.transform(latest).iter().enumerate() {
+            for ensemble in &ensembles {
+                let (bias, values) = ensemble.contributions(row);
+                contributions.push(PathContribution {
+                    timestamp: holdout.timestamps[start + i],
+                    ensemble: ensemble.name.clone(),
+                    prediction: predictions[i],
+                    bias,
+                    contributions: FEATURES
+                        .iter()
+                        .zip(values)
+                        .filter(|(_, value)| *value != 0.0)
+                        .map(|(feature, value)| (feature.name.to_string(), value))
+                        .collect(),
+                });
+            }
+        }
+        Self {
+            config: model.describe(),
+            rows: dataset.len(),
+            holdout_rows: holdout.len(),
+            features,
+            depth,
+            contributions,
+        }
+    }
+    /// The features with the most gain, or permutation importance for models without trees.
+    pub fn top_features(&self, n: usize) -> Vec<&FeatureImportance> {
+        let mut features: Vec<&FeatureImportance> = self.features.iter().collect();
+        if self.depth.is_none() {
+            features.sort_by(|a, b| b.permutation.total_cmp(&a.permutation));
+        }
+        features.truncate(n);
+        features
+    }
+}
+
+/// Rise in the MAE of `model` on `holdout` when each feature column is shuffled in turn.
+fn permutation_importance(model: &dyn Predictor, holdout: &Dataset) -> Vec<f32> {
+    if holdout.len() == 0 {
+        return vec![0.0; FEATURES.len()];
+    }
+    let base = FoldMetrics::compute(&model.predict(&holdout.x), &holdout.y).mae;
+    let mut rng = StdRng::seed_from_u64(PERMUTATION_SEED);
+    (0..FEATURES.len())
+        .map(|j| {
+            let mut column: Vec<f32> = holdout.x.iter().map(|row| row[j]).collect();
+            column.shuffle(&mut rng);
+            let shuffled: Vec<Vec<f32>> = holdout
+                .x
+                .iter()
+                .zip(column)
+                .map(|(row, value)| {
+                    let mut row = row.clone();
+                    row[j] = value;
+                    row
+                })
+                .collect();
+            FoldMetrics::compute(&model.predict(&shuffled), &holdout.y).mae - base
+        })
+        .collect()
+}
+
+/// Options of the `inspect-model` command.
+#[derive(Debug, Clone, Args)]
+pub struct InspectModelArgs {
+    /// Registry version to inspect, the promoted one by default.
+    pub version: Option<u64>,
+    /// Features listed.
+    #[arg(long, default_value_t = 20)]
+    pub top: usize,
+    /// Print the whole report as JSON.
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// Prints the report saved with a registry version.
+pub fn inspect_model(
+    registry: &ModelRegistry,
+    args: &InspectModelArgs,
+) -> Result<(), Box<dyn Error>> {
+    let version = match args.version {
+        Some(version) => version,
+        None => registry.promoted()?.ok_or("no model was promoted")?,
+    };
+    let report = registry.report(version)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    println!(
+        "Model version {}: {}, {} rows, {} holdout rows",
+        version, report.config, report.rows, report.holdout_rows
+    );
+    if let Some(depth) = &report.depth {
+        println!(
+            "{} trees, {} leaves, depth {}-{} (mean {:.2})",
+            depth.trees, depth.leaves, depth.min, depth.max, depth.mean
+        );
+    }
+    println!(
+        "{:<32} {:>8} {:>12} {:>12}",
+        "feature", "splits", "gain", "permutation"
+    );
+    for feature in report.top_features(args.top) {
+        println!(
+            "{:<32} {:>8} {:>12.4} {:>12.4}",
+            feature.name, feature.splits, feature.gain, feature.permutation
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node(
+        feature: usize,
+        threshold: f32,
+        pred: f32,
+        children: Option<(usize, usize)>,
+    ) -> TreeNode {
+        TreeNode {
+            feature,
+            threshold,
+            pred,
+            left: children.map(|(left, _)| left),
+            right: children.map(|(_, right)| right),
+        }
+    }
+
+    #[test]
+    fn breaks_trees_down_by_feature() {
+        // splits feature 0 at 5, then the right side on feature 1 at 2
+        let ensemble = TreeEnsemble {
+            name: "gbdt".to_string(),
+            init_score: 1.0,
+            shrinkage: 0.5,
+            trees: vec![Tree {
+                nodes: vec![
+                    node(0, 5.0, 0.0, Some((1, 2))),
+                    node(0, 0.0, -2.0, None),
+                    node(1, 2.0, 2.0, Some((3, 4))),
+                    node(0, 0.0, 1.0, None),
+                    node(0, 0.0, 3.0, None),
+                ],
+            }],
+        };
+        let tree = &ensemble.trees[0];
+        assert_eq!(tree.path(&[6.0, 3.0]), vec![0, 2, 4]);
+        assert_eq!((tree.depth(), tree.leaves()), (2, 3));
+
+        let (bias, contributions) = ensemble.contributions(&[6.0, 3.0]);
+        assert_eq!(bias, 1.0);
+        assert_eq!(contributions, vec![1.0, 0.5]);
+        assert_eq!(bias + contributions.iter().sum::<f32>(), 1.0 + 0.5 * 3.0);
+
+        let x = vec![
+            vec![1.0, 0.0],
+            vec![6.0, 1.0],
+            vec![6.0, 3.0],
+            vec![7.0, 3.0],
+        ];
+        let (mut splits, mut gains) = (vec![0; 2], vec![0.0; 2]);
+        ensemble.add_split_importance(&x, &mut splits, &mut gains);
+        assert_eq!(splits, vec![1, 1]);
+        // root: 1 * 4 + 3 * 4 - 0, right: 1 * 1 + 2 * 9 - 3 * 4
+        assert_eq!(gains, vec![16.0, 7.0]);
+    }
+}
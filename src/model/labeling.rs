@@ -0,0 +1,352 @@
+use std::collections::VecDeque;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use super::{data_handling::Observation, predictor::classifier::ClassThresholds, rolling::Window};
+use crate::{META_IMBALANCE_THRESHOLD, STOP_TICKS, TAKE_PROFIT_TICKS, TARGET_HORIZON};
+
+/// Rule deciding the side a meta-labelled row would be traded on.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum PrimarySignal {
+    /// Buy when the top of book imbalance is at least `threshold`, sell when at most `-threshold`.
+    BookImbalance { threshold: Decimal },
+    /// The side of the order flow imbalance since the previous trade.
+    OrderFlow,
+}
+impl PrimarySignal {
+    /// 1 to buy, -1 to sell, 0 for no trade.
+    pub fn side(&self, observation: &Observation) -> i32 {
+        let value = match self {
+            Self::BookImbalance { threshold } if observation.imbalance_1.abs() < *threshold => {
+                return 0
+            }
+            Self::BookImbalance { .. } => observation.imbalance_1,
+            Self::OrderFlow => observation.ofi_1,
+        };
+        if value.is_sign_positive() && !value.is_zero() {
+            1
+        } else if value.is_sign_negative() && !value.is_zero() {
+            -1
+        } else {
+            0
+        }
+    }
+}
+
+/// How the target of each observation is computed from the observations after it, selected with
+/// the `LABELER` environment variable.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Labeler {
+    /// Ticks the price rises above the start beyond the ticks it falls below it, within the
+    /// horizon.
+    RangeSkew { horizon: Window },
+    /// Mid price change from the start to the first observation past the horizon, in ticks.
+    ForwardReturn { horizon: Window },
+    /// 1 if the price first rises `take_profit_ticks` above the start, -1 if it first falls
+    /// `stop_ticks` below it, 0 if neither happens within the horizon.
+    TripleBarrier {
+        take_profit_ticks: Decimal,
+        stop_ticks: Decimal,
+        horizon: Window,
+    },
+    /// 1 if trading the `primary` signal's side would have reached its take-profit barrier
+    /// before its stop and the horizon, 0 otherwise. Rows without a signal aren't labelled.
+    Meta {
+        primary: PrimarySignal,
+        take_profit_ticks: Decimal,
+        stop_ticks: Decimal,
+        horizon: Window,
+    },
+}
+impl Default for Labeler {
+    fn default() -> Self {
+        Self::RangeSkew {
+            horizon: TARGET_HORIZON,
+        }
+    }
+}
+impl Labeler {
+    /// The labeler named by `LABELER`, with the horizon in `LABEL_HORIZON` or `TARGET_HORIZON`,
+    /// or why either isn't valid.
+    pub fn from_env() -> Result<Self, String> {
+        let horizon = match std::env::var("LABEL_HORIZON") {
+            Ok(horizon) => horizon
+                .parse()
+                .map_err(|e| format!("invalid LABEL_HORIZON: {}", e))?,
+            Err(_) => TARGET_HORIZON,
+        };
+        let take_profit_ticks = Decimal::from(TAKE_PROFIT_TICKS);
+        let stop_ticks = Decimal::from(STOP_TICKS);
+        Ok(match std::env::var("LABELER").as_deref() {
+            Err(_) | Ok("range-skew") => Self::RangeSkew { horizon },
+            Ok("forward-return") => Self::ForwardReturn { horizon },
+            Ok("triple-barrier") => Self::TripleBarrier {
+                take_profit_ticks,
+                stop_ticks,
+                horizon,
+            },
+            Ok("meta") => Self::Meta {
+                primary: PrimarySignal::BookImbalance {
+                    threshold: META_IMBALANCE_THRESHOLD,
+                },
+                take_profit_ticks,
+                stop_ticks,
+                horizon,
+            },
+            Ok(labeler) => return Err(format!("invalid LABELER: unknown labeler {}", labeler)),
+        })
+    }
+    /// How far ahead labels look. Training rows this close to the test rows are purged.
+    pub fn horizon(&self) -> Window {
+        match self {
+            Self::RangeSkew { horizon }
+            | Self::ForwardReturn { horizon }
+            | Self::TripleBarrier { horizon, .. }
+            | Self::Meta { horizon, .. } => *horizon,
+        }
+    }
+    /// True for labels in ticks, which regressors trade once the prediction reaches the model's
+    /// MAE. Barrier labels are -1, 0 or 1 for triple barriers and 0 or 1 for meta labels.
+    pub fn in_ticks(&self) -> bool {
+        matches!(self, Self::RangeSkew { .. } | Self::ForwardReturn { .. })
+    }
+    /// Thresholds classifiers split these labels into up, flat and down with: `CLASS_UP_TICKS`
+    /// and `CLASS_DOWN_TICKS` for labels in ticks, and 1 for barrier labels, so a meta label is
+    /// up when the primary signal wins and never down.
+    pub fn class_thresholds(&self) -> ClassThresholds {
+        if self.in_ticks() {
+            ClassThresholds::default()
+        } else {
+            ClassThresholds {
+                up_ticks: 1.0,
+                down_ticks: 1.0,
+            }
+        }
+    }
+    /// Side to trade at `observation` on a predicted label, or a classified direction, of
+    /// `value`: 1 to buy, -1 to sell, 0 for none. Meta labels trade the primary signal's side
+    /// when the value is positive.
+    pub fn side(&self, value: f32, observation: &Observation) -> i32 {
+        match self {
+            Self::Meta { primary, .. } if value > 0.0 => primary.side(observation),
+            Self::Meta { .. } => 0,
+            _ if value > 0.0 => 1,
+            _ if value < 0.0 => -1,
+            _ => 0,
+        }
+    }
+    /// Sets the target of each observation whose horizon closes before the last one.
+    pub fn label(&self, data: &mut [Observation], tick_size: Decimal) {
+        match *self {
+            Self::RangeSkew { horizon } => range_skew(data, horizon, tick_size),
+            Self::ForwardReturn { horizon } => label_windows(data, horizon, |window| {
+                let (start, end) = (&window[0], &window[window.len() - 1]);
+                Some((end.mid_price - start.mid_price) / tick_size)
+            }),
+            Self::TripleBarrier {
+                take_profit_ticks,
+                stop_ticks,
+                horizon,
+            } => label_windows(data, horizon, |window| {
+                let touch = first_touch(
+                    window,
+                    1,
+                    take_profit_ticks * tick_size,
+                    stop_ticks * tick_size,
+                );
+                Some(Decimal::from(touch))
+            }),
+            Self::Meta {
+                primary,
+                take_profit_ticks,
+                stop_ticks,
+                horizon,
+            } => label_windows(data, horizon, |window| match primary.side(&window[0]) {
+                0 => None,
+                side => {
+                    let touch = first_touch(
+                        window,
+                        side,
+                        take_profit_ticks * tick_size,
+                        stop_ticks * tick_size,
+                    );
+                    Some(if touch == 1 {
+                        Decimal::ONE
+                    } else {
+                        Decimal::ZERO
+                    })
+                }
+            }),
+        }
+    }
+}
+
+/// Labels each observation from the window of observations from it up to the first one past
+/// `horizon`. Observations whose horizon doesn't close before the last observation aren't
+/// labelled.
+fn label_windows<F>(data: &mut [Observation], horizon: Window, label: F)
+where
+    F: Fn(&[Observation]) -> Option<Decimal>,
+{
+    let mut end = 0;
+    for start in 0..data.len() {
+        end = end.max(start + 1);
+        while end < data.len()
+            && horizon.contains(end - start, data[end].timestamp - data[start].timestamp)
+        {
+            end += 1;
+        }
+        if end >= data.len() {
+            break;
+        }
+        data[start].target = label(&data[start..=end]);
+    }
+}
+
+/// Which barrier a position on `side` opened at the first observation of `window` reaches first
+/// within the horizon: 1 for `take_profit` in its favour, -1 for `stop` against it, 0 for
+/// neither. The last observation of `window` is past the horizon and isn't looked at.
+fn first_touch(window: &[Observation], side: i32, take_profit: Decimal, stop: Decimal) -> i32 {
+    let entry = window[0].price;
+    let side = Decimal::from(side);
+    for observation in &window[1..window.len() - 1] {
+        let gain = (observation.price - entry) * side;
+        if gain >= take_profit {
+            return 1;
+        }
+        if -gain >= stop {
+            return -1;
+        }
+    }
+    0
+}
+
+/// Sets the target of each observation to how many more ticks the price rises above it than it
+/// falls below it within `horizon`, using monotonic deques of the window's high and low.
+fn range_skew(data: &mut [Observation], horizon: Window, tick_size: Decimal) {
+    // indices of the window's prices, decreasing for the high and increasing for the low
+    let mut highs: VecDeque<usize> = VecDeque::new();
+    let mut lows: VecDeque<usize> = VecDeque::new();
+    let mut end = 0;
+    for start in 0..data.len() {
+        while end < data.len()
+            && horizon.contains(end - start, data[end].timestamp - data[start].timestamp)
+        {
+            let price = data[end].price;
+            while matches!(highs.back(), Some(i) if data[*i].price <= price) {
+                highs.pop_back();
+            }
+            highs.push_back(end);
+            while matches!(lows.back(), Some(i) if data[*i].price >= price) {
+                lows.pop_back();
+            }
+            lows.push_back(end);
+            end += 1;
+        }
+        // the window must close before the last observation
+        if end + 1 >= data.len() {
+            break;
+        }
+        while matches!(highs.front(), Some(i) if *i < start) {
+            highs.pop_front();
+        }
+        while matches!(lows.front(), Some(i) if *i < start) {
+            lows.pop_front();
+        }
+        let start_of_period = data[start].price;
+        let high = data[*highs.front().unwrap()].price;
+        let low = data[*lows.front().unwrap()].price;
+        let distance_to_high = (high - start_of_period) / tick_size;
+        let distance_to_low = (start_of_period - low) / tick_size;
+        data[start].target = Some(distance_to_high - distance_to_low);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::predictor::classifier::Class,
+        test_support::{dec, observations},
+    };
+
+    fn rows(prices: &[&str], imbalances: &[&str]) -> Vec<Observation> {
+        observations(prices.len())
+            .into_iter()
+            .zip(prices.iter().zip(imbalances))
+            .map(|(mut row, (price, imbalance))| {
+                row.price = dec(price);
+                row.mid_price = dec(price);
+                row.imbalance_1 = dec(imbalance);
+                row
+            })
+            .collect()
+    }
+
+    fn targets(labeler: Labeler, mut data: Vec<Observation>) -> Vec<Option<Decimal>> {
+        labeler.label(&mut data, dec("0.1"));
+        data.iter().map(|row| row.target).collect()
+    }
+
+    #[test]
+    fn labels_forward_returns_and_barrier_touches() {
+        let data = rows(
+            &["100", "100.2", "100.6", "99.9", "100.0", "100.1", "100.1"],
+            &["0.5", "-0.5", "-0.5", "0.1", "0", "0", "0"],
+        );
+        let horizon = Window::Count(3);
+        let labels = |values: &[i64]| -> Vec<Option<Decimal>> {
+            let mut labels: Vec<Option<Decimal>> =
+                values.iter().map(|v| Some(Decimal::from(*v))).collect();
+            labels.resize(data.len(), None);
+            labels
+        };
+        assert_eq!(
+            targets(Labeler::ForwardReturn { horizon }, data.clone()),
+            labels(&[-1, -2, -5, 2])
+        );
+        let (take_profit_ticks, stop_ticks) = (dec("3"), dec("3"));
+        let triple_barrier = Labeler::TripleBarrier {
+            take_profit_ticks,
+            stop_ticks,
+            horizon,
+        };
+        assert_eq!(
+            targets(triple_barrier, data.clone()),
+            labels(&[1, 1, -1, 0])
+        );
+        let meta = Labeler::Meta {
+            primary: PrimarySignal::BookImbalance {
+                threshold: dec("0.3"),
+            },
+            take_profit_ticks,
+            stop_ticks,
+            horizon,
+        };
+        // the fourth row has no signal, the second is short into a rise
+        let mut expected = labels(&[1, 0, 1]);
+        expected[3] = None;
+        assert_eq!(targets(meta, data.clone()), expected);
+        assert_eq!(meta.horizon(), horizon);
+
+        // meta predictions trade the primary signal's side, and only when it has one
+        assert_eq!(meta.side(1.0, &data[1]), -1);
+        assert_eq!(meta.side(0.0, &data[1]), 0);
+        assert_eq!(meta.side(1.0, &data[3]), 0);
+        assert_eq!(triple_barrier.side(-1.0, &data[0]), -1);
+        let classes = meta.class_thresholds();
+        assert_eq!(
+            [0.0, 1.0].map(|label| classes.label(label)),
+            [Class::Flat, Class::Up]
+        );
+        let classes = triple_barrier.class_thresholds();
+        assert_eq!(
+            [-1.0, 0.0, 1.0].map(|label| classes.label(label)),
+            [Class::Down, Class::Flat, Class::Up]
+        );
+        assert!(Labeler::ForwardReturn { horizon }.in_ticks());
+    }
+}
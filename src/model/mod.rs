@@ -1,3 +1,20 @@
 pub mod data_handling;
+pub mod drift;
+pub mod evaluation;
+pub mod export;
+pub mod feature_registry;
 pub mod features;
 pub mod inference;
+pub mod introspection;
+pub mod labeling;
+pub mod order_flow;
+pub mod predictor;
+pub mod preprocessing;
+pub mod registry;
+pub mod rolling;
+pub mod search;
+pub mod store;
+pub mod time_features;
+pub mod trade_flow;
+pub mod train;
+pub mod weighting;
@@ -0,0 +1,244 @@
+use std::sync::Arc;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use tokio::sync::RwLock;
+
+use crate::binance::models::orderbook::{OrderBook, PriceSize};
+
+use super::rolling::RollingWindow;
+
+pub type OrderFlowRwl = Arc<RwLock<OrderFlow>>;
+
+pub fn new_order_flow_rwl() -> OrderFlowRwl {
+    Arc::new(RwLock::new(OrderFlow::new()))
+}
+
+/// Price levels of each side compared between consecutive book updates.
+const OFI_LEVELS: usize = 5;
+/// Book updates the rolling order flow and queue rates cover.
+pub const ORDER_FLOW_WINDOW: usize = 100;
+
+/// Order flow between trades, attached to the next observation.
+#[derive(Default, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OrderFlowFeatures {
+    /// Order flow imbalance at the best level since the previous observation.
+    pub ofi_1: Decimal,
+    /// Order flow imbalance summed over the top `OFI_LEVELS` since the previous observation.
+    pub ofi_multi: Decimal,
+    /// Order flow imbalance over the top levels during the last `ORDER_FLOW_WINDOW` updates,
+    /// `None` until there were that many.
+    pub ofi_rolling: Option<Decimal>,
+    /// Size per second taken from or added to unchanged top levels during the window.
+    pub bid_depletion_rate: Option<Decimal>,
+    pub bid_replenishment_rate: Option<Decimal>,
+    pub ask_depletion_rate: Option<Decimal>,
+    pub ask_replenishment_rate: Option<Decimal>,
+}
+
+/// Queue size taken from and added to one side's top levels by an update.
+#[derive(Default)]
+struct QueueChange {
+    depletion: Decimal,
+    replenishment: Decimal,
+}
+
+/// Order flow imbalance (Cont, Kukanov & Stoikov) and queue depletion/replenishment at the top of
+/// the book, updated on every depth update.
+#[derive(Debug, Clone, Default)]
+pub struct OrderFlow {
+    bids: Vec<PriceSize>,
+    asks: Vec<PriceSize>,
+    ofi_1: Decimal,
+    ofi_multi: Decimal,
+    ofi: RollingWindow,
+    bid_depletion: RollingWindow,
+    bid_replenishment: RollingWindow,
+    ask_depletion: RollingWindow,
+    ask_replenishment: RollingWindow,
+    /// Milliseconds since the previous update of each change, for the rates.
+    intervals: RollingWindow,
+    /// Time of the update the top levels are from, in milliseconds.
+    time: i64,
+}
+impl OrderFlow {
+    pub fn new() -> Self {
+        Self::with_window(ORDER_FLOW_WINDOW)
+    }
+    /// Order flow whose rolling features cover the last `window` updates.
+    pub fn with_window(window: usize) -> Self {
+        Self {
+            ofi: RollingWindow::new(window),
+            bid_depletion: RollingWindow::new(window),
+            bid_replenishment: RollingWindow::new(window),
+            ask_depletion: RollingWindow::new(window),
+            ask_replenishment: RollingWindow::new(window),
+            intervals: RollingWindow::new(window),
+            ..Default::default()
+        }
+    }
+    /// Forgets the previous top levels, so a rebuilt book isn't compared with the stale one.
+    pub fn reset(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+    }
+    /// Accumulates the order flow between the previous top levels and those of `book`.
+    pub fn update(&mut self, book: &OrderBook) {
+        let bids: Vec<PriceSize> = book.bids.iter().take(OFI_LEVELS).cloned().collect();
+        let asks: Vec<PriceSize> = book.asks.iter().take(OFI_LEVELS).cloned().collect();
+        if !self.bids.is_empty() && !self.asks.is_empty() {
+            let mut ofi = Decimal::ZERO;
+            for (level, (old, new)) in self.bids.iter().zip(bids.iter()).enumerate() {
+                let flow = bid_flow(old, new);
+                if level == 0 {
+                    self.ofi_1 += flow;
+                }
+                ofi += flow;
+            }
+            for (level, (old, new)) in self.asks.iter().zip(asks.iter()).enumerate() {
+                let flow = -ask_flow(old, new);
+                if level == 0 {
+                    self.ofi_1 += flow;
+                }
+                ofi += flow;
+            }
+            self.ofi_multi += ofi;
+            self.ofi.push(ofi);
+            let bid_change = queue_change(&self.bids, &bids);
+            let ask_change = queue_change(&self.asks, &asks);
+            self.bid_depletion.push(bid_change.depletion);
+            self.bid_replenishment.push(bid_change.replenishment);
+            self.ask_depletion.push(ask_change.depletion);
+            self.ask_replenishment.push(ask_change.replenishment);
+            self.intervals
+                .push(Decimal::from(book.time.timestamp_millis() - self.time));
+        }
+        self.bids = bids;
+        self.asks = asks;
+        self.time = book.time.timestamp_millis();
+    }
+    /// The order flow since the previous call, which starts a new interval.
+    pub fn take_features(&mut self) -> OrderFlowFeatures {
+        // the changes of the window span the intervals before each of them
+        let seconds = self.intervals.sum() / Decimal::ONE_THOUSAND;
+        // every window is pushed with the intervals
+        let full = self.intervals.is_full();
+        let rate = |window: &RollingWindow| {
+            full.then(|| {
+                if seconds.is_zero() {
+                    Decimal::ZERO
+                } else {
+                    window.sum() / seconds
+                }
+            })
+        };
+        let features = OrderFlowFeatures {
+            ofi_1: self.ofi_1,
+            ofi_multi: self.ofi_multi,
+            ofi_rolling: full.then(|| self.ofi.sum()),
+            bid_depletion_rate: rate(&self.bid_depletion),
+            bid_replenishment_rate: rate(&self.bid_replenishment),
+            ask_depletion_rate: rate(&self.ask_depletion),
+            ask_replenishment_rate: rate(&self.ask_replenishment),
+        };
+        self.ofi_1 = Decimal::ZERO;
+        self.ofi_multi = Decimal::ZERO;
+        features
+    }
+}
+
+/// Buying pressure from a bid level: size added at the same or a better price, less the size
+/// that was there when the price held or worsened.
+fn bid_flow(old: &PriceSize, new: &PriceSize) -> Decimal {
+    let mut flow = Decimal::ZERO;
+    if new.price >= old.price {
+        flow += new.size;
+    }
+    if new.price <= old.price {
+        flow -= old.size;
+    }
+    flow
+}
+
+/// Selling pressure from an ask level, the mirror of [`bid_flow`].
+fn ask_flow(old: &PriceSize, new: &PriceSize) -> Decimal {
+    let mut flow = Decimal::ZERO;
+    if new.price <= old.price {
+        flow += new.size;
+    }
+    if new.price >= old.price {
+        flow -= old.size;
+    }
+    flow
+}
+
+/// Size changes of levels whose price didn't move.
+fn queue_change(old: &[PriceSize], new: &[PriceSize]) -> QueueChange {
+    let mut change = QueueChange::default();
+    for (old, new) in old.iter().zip(new.iter()) {
+        if old.price != new.price {
+            continue;
+        }
+        let delta = new.size - old.size;
+        if delta.is_sign_negative() {
+            change.depletion -= delta;
+        } else {
+            change.replenishment += delta;
+        }
+    }
+    change
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+    use crate::test_support::dec;
+
+    fn book(ms: i64, bid: (&str, &str), ask: (&str, &str)) -> OrderBook {
+        let level = |(price, size): (&str, &str)| PriceSize {
+            price: dec(price),
+            size: dec(size),
+        };
+        OrderBook {
+            bids: vec![level(bid)],
+            asks: vec![level(ask)],
+            time: Utc.timestamp_millis_opt(ms).unwrap(),
+            is_valid: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn accumulates_order_flow_imbalance_between_observations() {
+        let mut flow = OrderFlow::with_window(2);
+        flow.update(&book(0, ("100.0", "1"), ("100.1", "2")));
+        // the rolling features wait for a full window of updates
+        assert_eq!(flow.clone().take_features().ofi_rolling, None);
+        // bid queue grows by 2 and the ask queue shrinks by 1
+        flow.update(&book(1000, ("100.0", "3"), ("100.1", "1")));
+        // the bid moves up with 4 resting, the old bid queue no longer counts against it
+        flow.update(&book(2000, ("100.1", "4"), ("100.2", "1")));
+        let features = flow.take_features();
+        assert_eq!(features.ofi_1, dec("3") + dec("4") + dec("1"));
+        assert_eq!(features.ofi_multi, features.ofi_1);
+        // 2 added to the bid and 1 taken from the ask over the two seconds of the window's changes
+        assert_eq!(features.bid_replenishment_rate, Some(dec("1")));
+        assert_eq!(features.ask_depletion_rate, Some(dec("0.5")));
+        assert_eq!(features.bid_depletion_rate, Some(Decimal::ZERO));
+
+        let features = flow.take_features();
+        assert_eq!(features.ofi_1, Decimal::ZERO);
+        assert_eq!(features.ofi_rolling, Some(dec("8")));
+    }
+
+    #[test]
+    fn reset_starts_over_from_the_next_book() {
+        let mut flow = OrderFlow::new();
+        flow.update(&book(0, ("100.0", "1"), ("100.1", "2")));
+        flow.reset();
+        flow.update(&book(1000, ("90.0", "5"), ("90.1", "5")));
+        assert_eq!(flow.take_features(), OrderFlowFeatures::default());
+    }
+}
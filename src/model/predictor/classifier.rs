@@ -0,0 +1,193 @@
+use std::error::Error;
+
+use gbdt::gradient_boost::GBDT;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    super::introspection::TreeEnsemble,
+    gbdt::{fit_gbdt, gbdt_trees, new_gbdt, predict_gbdt, GbdtParams},
+    ClassProbabilities, Predictor,
+};
+use crate::{CLASS_DOWN_TICKS, CLASS_UP_TICKS};
+
+/// Loss of the one-vs-rest models, which `gbdt` fits on labels of 1 and -1 and predicts as the
+/// probability of 1.
+const CLASSIFIER_LOSS: &str = "LogLikelyhood";
+
+/// Direction of the target beyond the class thresholds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Class {
+    Up,
+    Flat,
+    Down,
+}
+impl Class {
+    const ALL: [Class; 3] = [Class::Up, Class::Flat, Class::Down];
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Up => "up",
+            Self::Flat => "flat",
+            Self::Down => "down",
+        }
+    }
+}
+
+/// Targets from which a row is labelled up or down rather than flat, in ticks for labels in ticks
+/// and in label values for barrier labels, see `Labeler::class_thresholds`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ClassThresholds {
+    pub up_ticks: f32,
+    pub down_ticks: f32,
+}
+impl Default for ClassThresholds {
+    fn default() -> Self {
+        Self {
+            up_ticks: CLASS_UP_TICKS,
+            down_ticks: CLASS_DOWN_TICKS,
+        }
+    }
+}
+impl ClassThresholds {
+    pub fn label(&self, target: f32) -> Class {
+        if target >= self.up_ticks {
+            Class::Up
+        } else if target <= -self.down_ticks {
+            Class::Down
+        } else {
+            Class::Flat
+        }
+    }
+}
+
+/// Up, flat and down classes from one-vs-rest `gbdt` classifiers. It is fitted on targets
+/// labelled with its thresholds, and predicts the expected target beyond them:
+/// `up_ticks * P(up) - down_ticks * P(down)`.
+#[derive(Serialize, Deserialize)]
+pub struct ClassifierPredictor {
+    thresholds: ClassThresholds,
+    /// One model per class, in the order of `Class::ALL`.
+    models: Vec<GBDT>,
+}
+impl ClassifierPredictor {
+    pub fn new(feature_size: usize, params: &GbdtParams, thresholds: ClassThresholds) -> Self {
+        let params = GbdtParams {
+            loss: CLASSIFIER_LOSS.to_string(),
+            ..params.clone()
+        };
+        Self {
+            thresholds,
+            models: Class::ALL
+                .iter()
+                .map(|_| new_gbdt(feature_size, &params))
+                .collect(),
+        }
+    }
+}
+impl Predictor for ClassifierPredictor {
+    fn fit(&mut self, features: &[Vec<f32>], targets: &[f32], weights: &[f32]) {
+        let classes: Vec<Class> = targets
+            .iter()
+            .map(|target| self.thresholds.label(*target))
+            .collect();
+        for (model, class) in self.models.iter_mut().zip(Class::ALL) {
+            let labels: Vec<f32> = classes
+                .iter()
+                .map(|c| if *c == class { 1.0 } else { -1.0 })
+                .collect();
+            fit_gbdt(model, features, &labels, weights);
+        }
+    }
+    fn predict(&self, features: &[Vec<f32>]) -> Vec<f32> {
+        self.class_probabilities(features)
+            .unwrap()
+            .iter()
+            .map(|p| self.thresholds.up_ticks * p.up - self.thresholds.down_ticks * p.down)
+            .collect()
+    }
+    fn class_probabilities(&self, features: &[Vec<f32>]) -> Option<Vec<ClassProbabilities>> {
+        let scores: Vec<Vec<f32>> = self
+            .models
+            .iter()
+            .map(|model| predict_gbdt(model, features))
+            .collect();
+        Some(
+            (0..features.len())
+                .map(|i| ClassProbabilities::normalized(scores[0][i], scores[1][i], scores[2][i]))
+                .collect(),
+        )
+    }
+    fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+    fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+    fn describe(&self) -> String {
+        let conf = &self.models[0].conf;
+        format!(
+            "gbdt-classifier(features={}, depth={}, iterations={}, shrinkage={}, up={}, down={})",
+            conf.feature_size,
+            conf.max_depth,
+            conf.iterations,
+            conf.shrinkage,
+            self.thresholds.up_ticks,
+            self.thresholds.down_ticks
+        )
+    }
+    fn trees(&self) -> Vec<TreeEnsemble> {
+        self.models
+            .iter()
+            .zip(Class::ALL)
+            .filter_map(|(model, class)| gbdt_trees(class.name(), model))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_targets_beyond_the_thresholds() {
+        let thresholds = ClassThresholds {
+            up_ticks: 2.0,
+            down_ticks: 3.0,
+        };
+        let labels: Vec<Class> = [2.0, 1.5, -2.5, -3.0]
+            .iter()
+            .map(|target| thresholds.label(*target))
+            .collect();
+        assert_eq!(labels, [Class::Up, Class::Flat, Class::Flat, Class::Down]);
+        let probabilities = ClassProbabilities::normalized(0.6, 0.6, 0.3);
+        assert!((probabilities.up + probabilities.flat + probabilities.down - 1.0).abs() < 1e-6);
+        assert!((probabilities.up - 0.4).abs() < 1e-6);
+        assert_eq!(probabilities.direction(0.5), 0);
+        assert_eq!(probabilities.direction(0.4), 1);
+    }
+
+    #[test]
+    fn predicts_probabilities_and_round_trips_through_a_file() {
+        let features: Vec<Vec<f32>> = (0..60).map(|i| vec![i as f32, (i % 3) as f32]).collect();
+        let targets: Vec<f32> = (0..60).map(|i| (i % 3) as f32 * 3.0 - 3.0).collect();
+        let params = GbdtParams {
+            max_depth: 2,
+            iterations: 5,
+            ..Default::default()
+        };
+        let mut classifier = ClassifierPredictor::new(2, &params, ClassThresholds::default());
+        classifier.fit(&features, &targets, &vec![1.0; targets.len()]);
+        let probabilities = classifier.class_probabilities(&features).unwrap();
+        assert_eq!(probabilities.len(), features.len());
+        assert!(probabilities
+            .iter()
+            .all(|p| (p.up + p.flat + p.down - 1.0).abs() < 1e-4));
+
+        let path = std::env::temp_dir().join(format!("classifier-{}.model", std::process::id()));
+        let path = path.to_str().unwrap();
+        classifier.save(path).unwrap();
+        let loaded = ClassifierPredictor::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.predict(&features), classifier.predict(&features));
+    }
+}
@@ -0,0 +1,160 @@
+use std::{error::Error, path::Path};
+
+use gbdt::{
+    config::Config,
+    decision_tree::{Data, DataVec},
+    gradient_boost::GBDT,
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::{super::introspection::TreeEnsemble, Predictor};
+
+/// File the tuned gbdt parameters are read from when present, as written by `search`.
+pub const GBDT_PARAMS_FILE: &str = "gbdt_params.json";
+
+/// The tunable part of the gbdt configuration.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GbdtParams {
+    pub max_depth: u32,
+    pub iterations: usize,
+    pub shrinkage: f32,
+    pub data_sample_ratio: f64,
+    pub feature_sample_ratio: f64,
+    /// A `gbdt` loss name, such as `SquaredError` or `LAD`.
+    pub loss: String,
+}
+impl Default for GbdtParams {
+    fn default() -> Self {
+        Self {
+            max_depth: 6,
+            iterations: 500,
+            shrinkage: 0.1,
+            data_sample_ratio: 1.0,
+            feature_sample_ratio: 1.0,
+            loss: "SquaredError".to_string(),
+        }
+    }
+}
+impl GbdtParams {
+    /// The parameters saved in `GBDT_PARAMS_FILE`, or the defaults if there are none. A file
+    /// that doesn't parse is an error rather than ignored. Read once at startup, the parameters
+    /// are passed on to every model built from then on.
+    pub fn from_file_or_default() -> Result<Self, Box<dyn Error>> {
+        Self::read_or_default(Path::new(GBDT_PARAMS_FILE))
+    }
+    fn read_or_default(path: &Path) -> Result<Self, Box<dyn Error>> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)
+                .map_err(|e| format!("invalid {}: {}", path.display(), e).into()),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+}
+
+/// A `gbdt` model with these parameters, not yet fitted.
+pub(super) fn new_gbdt(feature_size: usize, params: &GbdtParams) -> GBDT {
+    let mut cfg = Config::new();
+    cfg.set_feature_size(feature_size);
+    cfg.set_max_depth(params.max_depth);
+    cfg.set_iterations(params.iterations);
+    cfg.set_shrinkage(params.shrinkage);
+    cfg.set_loss(&params.loss);
+    cfg.set_debug(false);
+    cfg.set_data_sample_ratio(params.data_sample_ratio);
+    cfg.set_feature_sample_ratio(params.feature_sample_ratio);
+    cfg.set_training_optimization_level(2);
+    GBDT::new(&cfg)
+}
+
+pub(super) fn fit_gbdt(model: &mut GBDT, features: &[Vec<f32>], labels: &[f32], weights: &[f32]) {
+    let mut train_dv: DataVec = features
+        .iter()
+        .zip(labels.iter().zip(weights))
+        .map(|(row, (label, weight))| Data::new_training_data(row.clone(), *weight, *label, None))
+        .collect();
+    if let Some(row) = features.first() {
+        model.conf.feature_size = row.len();
+    }
+    model.fit(&mut train_dv);
+}
+
+pub(super) fn predict_gbdt(model: &GBDT, features: &[Vec<f32>]) -> Vec<f32> {
+    let test_dv: DataVec = features
+        .iter()
+        .map(|row| Data::new_test_data(row.clone(), None))
+        .collect();
+    model.predict(&test_dv)
+}
+
+/// The trees of `model`, or none if they can't be read.
+pub(super) fn gbdt_trees(name: &str, model: &GBDT) -> Option<TreeEnsemble> {
+    TreeEnsemble::from_gbdt(name, model)
+        .map_err(|e| warn!("Can't read the trees of {}: {}", name, e))
+        .ok()
+}
+
+/// Gradient boosted trees from `gbdt`.
+pub struct GbdtPredictor {
+    model: GBDT,
+}
+impl GbdtPredictor {
+    pub fn with_params(feature_size: usize, params: &GbdtParams) -> Self {
+        Self {
+            model: new_gbdt(feature_size, params),
+        }
+    }
+}
+impl Predictor for GbdtPredictor {
+    fn fit(&mut self, features: &[Vec<f32>], targets: &[f32], weights: &[f32]) {
+        fit_gbdt(&mut self.model, features, targets, weights);
+    }
+    fn predict(&self, features: &[Vec<f32>]) -> Vec<f32> {
+        predict_gbdt(&self.model, features)
+    }
+    fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.model.save_model(path)
+    }
+    fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(Self {
+            model: GBDT::load_model(path)?,
+        })
+    }
+    fn describe(&self) -> String {
+        format!(
+            "gbdt(features={}, depth={}, iterations={}, shrinkage={})",
+            self.model.conf.feature_size,
+            self.model.conf.max_depth,
+            self.model.conf.iterations,
+            self.model.conf.shrinkage
+        )
+    }
+    fn trees(&self) -> Vec<TreeEnsemble> {
+        gbdt_trees("gbdt", &self.model).into_iter().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_params_and_rejects_a_malformed_file() {
+        let path = std::env::temp_dir().join(format!("gbdt-params-{}.json", std::process::id()));
+        assert_eq!(
+            GbdtParams::read_or_default(&path).unwrap(),
+            GbdtParams::default()
+        );
+        let params = GbdtParams {
+            max_depth: 3,
+            ..GbdtParams::default()
+        };
+        std::fs::write(&path, serde_json::to_string(&params).unwrap()).unwrap();
+        assert_eq!(GbdtParams::read_or_default(&path).unwrap(), params);
+        std::fs::write(&path, "{\"max_depth\": 3,").unwrap();
+        let error = GbdtParams::read_or_default(&path).unwrap_err();
+        assert!(error.to_string().contains("invalid"));
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,190 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    introspection::TreeEnsemble,
+    labeling::Labeler,
+    preprocessing::{PreprocessedPredictor, Step},
+};
+
+pub mod classifier;
+pub mod gbdt;
+pub mod ridge;
+
+use self::{
+    classifier::{ClassThresholds, ClassifierPredictor},
+    gbdt::{GbdtParams, GbdtPredictor},
+    ridge::RidgePredictor,
+};
+
+/// Probabilities of the target moving up, staying flat or moving down, summing to one.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ClassProbabilities {
+    pub up: f32,
+    pub flat: f32,
+    pub down: f32,
+}
+impl ClassProbabilities {
+    /// Scales independent per-class scores so they sum to one.
+    pub fn normalized(up: f32, flat: f32, down: f32) -> Self {
+        let total = up + flat + down;
+        if total <= 0.0 {
+            return Self {
+                up: 0.0,
+                flat: 1.0,
+                down: 0.0,
+            };
+        }
+        Self {
+            up: up / total,
+            flat: flat / total,
+            down: down / total,
+        }
+    }
+    /// 1 or -1 for the more likely of up and down, if it is at least `min_probability` and more
+    /// likely than flat, otherwise 0.
+    pub fn direction(&self, min_probability: f32) -> i32 {
+        let (probability, direction) = if self.up >= self.down {
+            (self.up, 1)
+        } else {
+            (self.down, -1)
+        };
+        if probability >= min_probability && probability >= self.flat {
+            direction
+        } else {
+            0
+        }
+    }
+}
+
+/// A regression model the training and inference loops can fit and query without knowing the
+/// backend.
+pub trait Predictor: Send + Sync {
+    /// Fits the model on rows of features, their targets and sample weights.
+    fn fit(&mut self, features: &[Vec<f32>], targets: &[f32], weights: &[f32]);
+    fn predict(&self, features: &[Vec<f32>]) -> Vec<f32>;
+    /// Up, flat and down probabilities of each row, for classifiers.
+    fn class_probabilities(&self, _features: &[Vec<f32>]) -> Option<Vec<ClassProbabilities>> {
+        None
+    }
+    fn save(&self, path: &str) -> Result<(), Box<dyn Error>>;
+    fn load(path: &str) -> Result<Self, Box<dyn Error>>
+    where
+        Self: Sized;
+    /// Short human readable summary of the backend and its parameters.
+    fn describe(&self) -> String;
+    /// The fitted trees, for tree models.
+    fn trees(&self) -> Vec<TreeEnsemble> {
+        Vec::new()
+    }
+    /// Rows of features as the backend sees them, after any preprocessing. Tree thresholds are in
+    /// these units.
+    fn transform(&self, features: &[Vec<f32>]) -> Vec<Vec<f32>> {
+        features.to_vec()
+    }
+}
+
+/// The available predictor backends, selected with the `MODEL_KIND` environment variable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ModelKind {
+    #[default]
+    Gbdt,
+    Ridge,
+    /// Up, flat and down classes of the target, see `CLASS_UP_TICKS` and `CLASS_DOWN_TICKS`.
+    #[serde(rename = "gbdt-classifier")]
+    GbdtClassifier,
+}
+impl ModelKind {
+    /// The kind named by `MODEL_KIND`, gbdt by default, or why it isn't a kind.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("MODEL_KIND") {
+            Ok(kind) => kind
+                .parse()
+                .map_err(|e| format!("invalid MODEL_KIND: {}", e)),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+    /// Where models of this kind are saved and loaded from.
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Self::Gbdt => "gbdt.model",
+            Self::Ridge => "ridge.model",
+            Self::GbdtClassifier => "gbdt-classifier.model",
+        }
+    }
+    /// A new model of this kind for targets labelled by `labeler`, fed through the
+    /// `PREPROCESSING` steps if any are set. Gbdt backends are configured with `gbdt_params`.
+    pub fn new_predictor(
+        &self,
+        feature_size: usize,
+        labeler: &Labeler,
+        gbdt_params: &GbdtParams,
+    ) -> Result<Box<dyn Predictor>, Box<dyn Error>> {
+        let model = self.new_backend(feature_size, labeler, gbdt_params)?;
+        let steps = Step::from_env();
+        if steps.is_empty() {
+            Ok(model)
+        } else {
+            Ok(Box::new(PreprocessedPredictor::new(*self, &steps, model)))
+        }
+    }
+    pub(crate) fn new_backend(
+        &self,
+        feature_size: usize,
+        labeler: &Labeler,
+        gbdt_params: &GbdtParams,
+    ) -> Result<Box<dyn Predictor>, Box<dyn Error>> {
+        Ok(match self {
+            Self::Gbdt => Box::new(GbdtPredictor::with_params(feature_size, gbdt_params)),
+            Self::Ridge => Box::new(RidgePredictor::new(1.0)),
+            Self::GbdtClassifier => Box::new(ClassifierPredictor::new(
+                feature_size,
+                gbdt_params,
+                labeler.class_thresholds(),
+            )),
+        })
+    }
+    /// The thresholds targets labelled by `labeler` are classified with, for classifiers.
+    pub fn class_thresholds(&self, labeler: &Labeler) -> Option<ClassThresholds> {
+        match self {
+            Self::GbdtClassifier => Some(labeler.class_thresholds()),
+            Self::Gbdt | Self::Ridge => None,
+        }
+    }
+    /// Loads a model of this kind, with the preprocessing it was saved with if any.
+    pub fn load(&self, path: &str) -> Result<Box<dyn Predictor>, Box<dyn Error>> {
+        if std::path::Path::new(&PreprocessedPredictor::path_for(path)).exists() {
+            return Ok(Box::new(PreprocessedPredictor::load(path)?));
+        }
+        self.load_backend(path)
+    }
+    pub(crate) fn load_backend(&self, path: &str) -> Result<Box<dyn Predictor>, Box<dyn Error>> {
+        Ok(match self {
+            Self::Gbdt => Box::new(GbdtPredictor::load(path)?),
+            Self::Ridge => Box::new(RidgePredictor::load(path)?),
+            Self::GbdtClassifier => Box::new(ClassifierPredictor::load(path)?),
+        })
+    }
+}
+impl FromStr for ModelKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "gbdt" => Ok(Self::Gbdt),
+            "ridge" => Ok(Self::Ridge),
+            "gbdt-classifier" => Ok(Self::GbdtClassifier),
+            _ => Err(format!("unknown model kind {}", s)),
+        }
+    }
+}
+impl fmt::Display for ModelKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Gbdt => write!(f, "gbdt"),
+            Self::Ridge => write!(f, "ridge"),
+            Self::GbdtClassifier => write!(f, "gbdt-classifier"),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use std::error::Error;
+
+use serde::{Deserialize, Serialize};
+
+use super::Predictor;
+
+/// Linear regression with an L2 penalty, fitted by weighted least squares on standardized
+/// features.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RidgePredictor {
+    lambda: f64,
+    means: Vec<f64>,
+    stds: Vec<f64>,
+    weights: Vec<f64>,
+    intercept: f64,
+}
+impl RidgePredictor {
+    pub fn new(lambda: f64) -> Self {
+        Self {
+            lambda,
+            ..Default::default()
+        }
+    }
+    fn standardize<'a>(&'a self, row: &'a [f32]) -> impl Iterator<Item = f64> + 'a {
+        row.iter()
+            .zip(self.means.iter().zip(self.stds.iter()))
+            .map(|(x, (mean, std))| (*x as f64 - mean) / std)
+    }
+}
+impl Predictor for RidgePredictor {
+    fn fit(&mut self, features: &[Vec<f32>], targets: &[f32], weights: &[f32]) {
+        let (n, k) = match features.first() {
+            Some(row) => (weights.iter().map(|w| *w as f64).sum::<f64>(), row.len()),
+            None => return,
+        };
+        self.means = (0..k)
+            .map(|j| {
+                features
+                    .iter()
+                    .zip(weights)
+                    .map(|(row, w)| *w as f64 * row[j] as f64)
+                    .sum::<f64>()
+                    / n
+            })
+            .collect();
+        self.stds = (0..k)
+            .map(|j| {
+                let variance = features
+                    .iter()
+                    .zip(weights)
+                    .map(|(row, w)| *w as f64 * (row[j] as f64 - self.means[j]).powi(2))
+                    .sum::<f64>()
+                    / n;
+                // constant features get a unit std so they standardize to zero
+                if variance > 0.0 {
+                    variance.sqrt()
+                } else {
+                    1.0
+                }
+            })
+            .collect();
+        self.intercept = targets
+            .iter()
+            .zip(weights)
+            .map(|(y, w)| *w as f64 * *y as f64)
+            .sum::<f64>()
+            / n;
+        // normal equations (X'WX + lambda * I) b = X'Wy on centered data
+        let mut gram = vec![vec![0.0; k]; k];
+        let mut moment = vec![0.0; k];
+        for ((row, target), weight) in features.iter().zip(targets).zip(weights) {
+            let x: Vec<f64> = self.standardize(row).collect();
+            let (y, w) = (*target as f64 - self.intercept, *weight as f64);
+            for ((gram_row, moment), xi) in gram.iter_mut().zip(moment.iter_mut()).zip(&x) {
+                *moment += w * xi * y;
+                for (value, xj) in gram_row.iter_mut().zip(&x) {
+                    *value += w * xi * xj;
+                }
+            }
+        }
+        for (i, row) in gram.iter_mut().enumerate() {
+            row[i] += self.lambda;
+        }
+        self.weights = solve(gram, moment);
+    }
+    fn predict(&self, features: &[Vec<f32>]) -> Vec<f32> {
+        features
+            .iter()
+            .map(|row| {
+                let dot: f64 = self
+                    .standardize(row)
+                    .zip(self.weights.iter())
+                    .map(|(x, w)| x * w)
+                    .sum();
+                (self.intercept + dot) as f32
+            })
+            .collect()
+    }
+    fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+    fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+    fn describe(&self) -> String {
+        format!(
+            "ridge(features={}, lambda={})",
+            self.weights.len(),
+            self.lambda
+        )
+    }
+}
+
+/// Solves `a x = b` by Gaussian elimination with partial pivoting. `a` is positive definite
+/// here thanks to the ridge penalty.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Vec<f64> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|i, j| a[*i][col].abs().total_cmp(&a[*j][col].abs()))
+            .unwrap();
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        let (upper, lower) = a.split_at_mut(col + 1);
+        let pivot_row = &upper[col];
+        for (offset, row) in lower.iter_mut().enumerate() {
+            let factor = row[col] / pivot_row[col];
+            for (value, pivot) in row[col..].iter_mut().zip(&pivot_row[col..]) {
+                *value -= factor * pivot;
+            }
+            b[col + 1 + offset] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let tail: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - tail) / a[row][row];
+    }
+    x
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn linear_data() -> (Vec<Vec<f32>>, Vec<f32>) {
+        let features: Vec<Vec<f32>> = (0..200)
+            .map(|i| vec![i as f32, (i % 7) as f32, 5.0])
+            .collect();
+        let targets = features
+            .iter()
+            .map(|row| 3.0 + 2.0 * row[0] - 4.0 * row[1])
+            .collect();
+        (features, targets)
+    }
+
+    #[test]
+    fn recovers_a_linear_relationship() {
+        let (features, targets) = linear_data();
+        let mut ridge = RidgePredictor::new(1e-6);
+        ridge.fit(&features, &targets, &vec![1.0; targets.len()]);
+        let predicted = ridge.predict(&[vec![10.0, 3.0, 5.0], vec![250.0, 0.0, 5.0]]);
+        assert!((predicted[0] - 11.0).abs() < 1e-2, "{:?}", predicted);
+        assert!((predicted[1] - 503.0).abs() < 1e-2, "{:?}", predicted);
+
+        // rows without weight don't move the fit
+        let (mut features, mut targets) = linear_data();
+        let mut weights = vec![1.0; targets.len()];
+        features.push(vec![10.0, 3.0, 5.0]);
+        targets.push(-1000.0);
+        weights.push(0.0);
+        let mut weighted = RidgePredictor::new(1e-6);
+        weighted.fit(&features, &targets, &weights);
+        let predicted = weighted.predict(&[vec![10.0, 3.0, 5.0]]);
+        assert!((predicted[0] - 11.0).abs() < 1e-2, "{:?}", predicted);
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let (features, targets) = linear_data();
+        let mut ridge = RidgePredictor::new(1.0);
+        ridge.fit(&features, &targets, &vec![1.0; targets.len()]);
+        let path = std::env::temp_dir().join(format!("ridge-{}.model", std::process::id()));
+        let path = path.to_str().unwrap();
+        ridge.save(path).unwrap();
+        let loaded = RidgePredictor::load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.predict(&features), ridge.predict(&features));
+        assert_eq!(loaded.describe(), "ridge(features=3, lambda=1)");
+    }
+}
@@ -0,0 +1,418 @@
+use std::{error::Error, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{CLIP_LIMIT, WINSORIZE_QUANTILE};
+
+use super::{
+    introspection::TreeEnsemble,
+    predictor::{ClassProbabilities, ModelKind, Predictor},
+};
+
+/// A feature transform, applied to every column in the order given in `PREPROCESSING`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Step {
+    /// `sign(x) * ln(1 + |x|)`, for heavy tailed features such as notional.
+    Log,
+    /// Limits each column to its `WINSORIZE_QUANTILE` and `1 - WINSORIZE_QUANTILE` quantiles.
+    Winsorize,
+    /// Limits each column to `[-CLIP_LIMIT, CLIP_LIMIT]`, meant to follow a scaling step.
+    Clip,
+    /// Subtracts the mean and divides by the standard deviation.
+    Standardize,
+    /// Subtracts the median and divides by the interquartile range.
+    RobustScale,
+}
+impl Step {
+    /// The steps listed in `PREPROCESSING`, comma separated, none by default.
+    pub fn from_env() -> Vec<Self> {
+        match std::env::var("PREPROCESSING") {
+            Ok(steps) => steps
+                .split(',')
+                .filter(|step| !step.is_empty())
+                .map(|step| step.parse().unwrap())
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+    fn fit(&self, x: &[Vec<f32>]) -> FittedStep {
+        let columns = x.first().map_or(0, |row| row.len());
+        let column = |j: usize| -> Vec<f32> { x.iter().map(|row| row[j]).collect() };
+        match self {
+            Self::Log => FittedStep::Log,
+            Self::Winsorize => {
+                let (lower, upper) = (0..columns)
+                    .map(|j| {
+                        let mut values = column(j);
+                        values.sort_by(f32::total_cmp);
+                        (
+                            quantile(&values, WINSORIZE_QUANTILE),
+                            quantile(&values, 1.0 - WINSORIZE_QUANTILE),
+                        )
+                    })
+                    .unzip();
+                FittedStep::Winsorize { lower, upper }
+            }
+            Self::Clip => FittedStep::Clip { limit: CLIP_LIMIT },
+            Self::Standardize => {
+                let n = x.len() as f64;
+                let (centers, scales) = (0..columns)
+                    .map(|j| {
+                        let values = column(j);
+                        let mean = values.iter().map(|v| *v as f64).sum::<f64>() / n;
+                        let variance = values
+                            .iter()
+                            .map(|v| (*v as f64 - mean).powi(2))
+                            .sum::<f64>()
+                            / n;
+                        (mean as f32, variance.sqrt() as f32)
+                    })
+                    .unzip();
+                FittedStep::Scale { centers, scales }
+            }
+            Self::RobustScale => {
+                let (centers, scales) = (0..columns)
+                    .map(|j| {
+                        let mut values = column(j);
+                        values.sort_by(f32::total_cmp);
+                        (
+                            quantile(&values, 0.5),
+                            quantile(&values, 0.75) - quantile(&values, 0.25),
+                        )
+                    })
+                    .unzip();
+                FittedStep::Scale { centers, scales }
+            }
+        }
+    }
+}
+impl FromStr for Step {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "log" => Ok(Self::Log),
+            "winsorize" => Ok(Self::Winsorize),
+            "clip" => Ok(Self::Clip),
+            "standardize" => Ok(Self::Standardize),
+            "robust-scale" => Ok(Self::RobustScale),
+            _ => Err(format!("unknown preprocessing step {}", s)),
+        }
+    }
+}
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Log => write!(f, "log"),
+            Self::Winsorize => write!(f, "winsorize"),
+            Self::Clip => write!(f, "clip"),
+            Self::Standardize => write!(f, "standardize"),
+            Self::RobustScale => write!(f, "robust-scale"),
+        }
+    }
+}
+
+/// The value at quantile `q` of sorted values, zero if there are none.
+fn quantile(sorted: &[f32], q: f32) -> f32 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    sorted[((sorted.len() - 1) as f32 * q).round() as usize]
+}
+
+/// A step with the parameters it was fitted with, one per column.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+enum FittedStep {
+    Log,
+    Winsorize {
+        lower: Vec<f32>,
+        upper: Vec<f32>,
+    },
+    Clip {
+        limit: f32,
+    },
+    /// `(x - center) / scale`. Constant columns are fitted with a zero scale and map every value to
+    /// zero, since the model never saw them vary.
+    Scale {
+        centers: Vec<f32>,
+        scales: Vec<f32>,
+    },
+}
+impl FittedStep {
+    fn apply(&self, row: &mut [f32]) {
+        match self {
+            Self::Log => row
+                .iter_mut()
+                .for_each(|x| *x = x.signum() * x.abs().ln_1p()),
+            Self::Winsorize { lower, upper } => {
+                for ((x, lower), upper) in row.iter_mut().zip(lower).zip(upper) {
+                    *x = x.clamp(*lower, *upper);
+                }
+            }
+            Self::Clip { limit } => row.iter_mut().for_each(|x| *x = x.clamp(-limit, *limit)),
+            Self::Scale { centers, scales } => {
+                for ((x, center), scale) in row.iter_mut().zip(centers).zip(scales) {
+                    *x = if *scale > 0.0 {
+                        (*x - center) / scale
+                    } else {
+                        0.0
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// Preprocessing steps fitted on the training features, applied the same way to every row the
+/// model is fitted on or predicts.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Preprocessor {
+    steps: Vec<Step>,
+    fitted: Vec<FittedStep>,
+}
+impl Preprocessor {
+    /// Fits each step on the output of the previous ones.
+    pub fn fit(steps: &[Step], x: &[Vec<f32>]) -> Self {
+        let mut x = x.to_vec();
+        let mut fitted = Vec::new();
+        for step in steps {
+            let step = step.fit(&x);
+            x.iter_mut().for_each(|row| step.apply(row));
+            fitted.push(step);
+        }
+        Self {
+            steps: steps.to_vec(),
+            fitted,
+        }
+    }
+    pub fn transform(&self, x: &[Vec<f32>]) -> Vec<Vec<f32>> {
+        x.iter()
+            .map(|row| {
+                let mut row = row.clone();
+                self.fitted.iter().for_each(|step| step.apply(&mut row));
+                row
+            })
+            .collect()
+    }
+}
+
+/// The sidecar a preprocessed model's pipeline and backend are saved to, beside the model.
+#[derive(Serialize, Deserialize)]
+struct Sidecar {
+    kind: ModelKind,
+    preprocessor: Preprocessor,
+}
+
+/// A model fed its features through a [`Preprocessor`] fitted with it. The backend is saved at
+/// the model path as usual and the pipeline in `<model>.preprocessing.json`.
+pub struct PreprocessedPredictor {
+    kind: ModelKind,
+    preprocessor: Preprocessor,
+    model: Box<dyn Predictor>,
+}
+impl PreprocessedPredictor {
+    pub fn new(kind: ModelKind, steps: &[Step], model: Box<dyn Predictor>) -> Self {
+        Self {
+            kind,
+            preprocessor: Preprocessor {
+                steps: steps.to_vec(),
+                fitted: Vec::new(),
+            },
+            model,
+        }
+    }
+    pub fn path_for(model_path: &str) -> String {
+        format!("{}.preprocessing.json", model_path)
+    }
+}
+impl Predictor for PreprocessedPredictor {
+    fn fit(&mut self, features: &[Vec<f32>], targets: &[f32], weights: &[f32]) {
+        self.preprocessor = Preprocessor::fit(&self.preprocessor.steps, features);
+        self.model
+            .fit(&self.preprocessor.transform(features), targets, weights);
+    }
+    fn predict(&self, features: &[Vec<f32>]) -> Vec<f32> {
+        self.model.predict(&self.preprocessor.transform(features))
+    }
+    fn class_probabilities(&self, features: &[Vec<f32>]) -> Option<Vec<ClassProbabilities>> {
+        self.model
+            .class_probabilities(&self.preprocessor.transform(features))
+    }
+    fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        self.model.save(path)?;
+        let sidecar = Sidecar {
+            kind: self.kind,
+            preprocessor: self.preprocessor.clone(),
+        };
+        std::fs::write(
+            Self::path_for(path),
+            serde_json::to_string_pretty(&sidecar)?,
+        )?;
+        Ok(())
+    }
+    fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let sidecar: Sidecar =
+            serde_json::from_str(&std::fs::read_to_string(Self::path_for(path))?)?;
+        Ok(Self {
+            kind: sidecar.kind,
+            preprocessor: sidecar.preprocessor,
+            model: sidecar.kind.load_backend(path)?,
+        })
+    }
+    fn describe(&self) -> String {
+        let steps: Vec<String> = self
+            .preprocessor
+            .steps
+            .iter()
+            .map(|step| step.to_string())
+            .collect();
+        format!("{} {}", steps.join(">"), self.model.describe())
+    }
+    /// Trees split on the preprocessed features, see [`Predictor::transform`].
+    fn trees(&self) -> Vec<TreeEnsemble> {
+        self.model.trees()
+    }
+    fn transform(&self, features: &[Vec<f32>]) -> Vec<Vec<f32>> {
+        self.preprocessor.transform(features)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::{
+        evaluation::Dataset,
+        feature_registry::feature_size,
+        introspection::{ModelReport, Tree, TreeNode},
+        labeling::Labeler,
+        predictor::gbdt::GbdtParams,
+    };
+
+    /// A tree splitting the first feature at 0, predicting -1 below and 1 above.
+    struct Stump;
+    impl Predictor for Stump {
+        fn fit(&mut self, _features: &[Vec<f32>], _targets: &[f32], _weights: &[f32]) {}
+        fn predict(&self, features: &[Vec<f32>]) -> Vec<f32> {
+            features
+                .iter()
+                .map(|row| if row[0] < 0.0 { -1.0 } else { 1.0 })
+                .collect()
+        }
+        fn save(&self, _path: &str) -> Result<(), Box<dyn Error>> {
+            Ok(())
+        }
+        fn load(_path: &str) -> Result<Self, Box<dyn Error>> {
+            Ok(Self)
+        }
+        fn describe(&self) -> String {
+            "stump".to_string()
+        }
+        fn trees(&self) -> Vec<TreeEnsemble> {
+            let node = |threshold, pred, children: Option<(usize, usize)>| TreeNode {
+                feature: 0,
+                threshold,
+                pred,
+                left: children.map(|(left, _)| left),
+                right: children.map(|(_, right)| right),
+            };
+            vec![TreeEnsemble {
+                name: "stump".to_string(),
+                init_score: 0.0,
+                shrinkage: 1.0,
+                trees: vec![Tree {
+                    nodes: vec![
+                        node(0.0, 0.0, Some((1, 2))),
+                        node(0.0, -1.0, None),
+                        node(0.0, 1.0, None),
+                    ],
+                }],
+            }]
+        }
+    }
+
+    #[test]
+    fn reports_trees_on_the_preprocessed_features() {
+        let x: Vec<Vec<f32>> = (0..100)
+            .map(|i| {
+                let mut row = vec![0.0; feature_size()];
+                row[0] = i as f32;
+                row
+            })
+            .collect();
+        let dataset = Dataset {
+            y: vec![0.0; x.len()],
+            w: vec![1.0; x.len()],
+            timestamps: (0..x.len() as i64).collect(),
+            x,
+        };
+        let mut model =
+            PreprocessedPredictor::new(ModelKind::Gbdt, &[Step::Standardize], Box::new(Stump));
+        model.fit(&dataset.x, &dataset.y, &dataset.w);
+        // every raw value is at least the threshold, but the standardized low half isn't
+        let report = ModelReport::new(&model, &dataset, &model, &dataset.slice(0..10));
+        assert_eq!(report.contributions.len(), 10);
+        for contribution in &report.contributions {
+            assert_eq!(contribution.prediction, -1.0);
+            let total: f32 = contribution.contributions.values().sum();
+            assert_eq!(contribution.bias + total, contribution.prediction);
+        }
+    }
+
+    #[test]
+    fn fits_steps_in_order_on_the_previous_output() {
+        let x: Vec<Vec<f32>> = (0..=100).map(|i| vec![i as f32, 5.0]).collect();
+        let preprocessor = Preprocessor::fit(&[Step::Winsorize, Step::RobustScale, Step::Clip], &x);
+        // winsorized to [1, 99], then a median of 50 and an IQR of 50
+        let transformed =
+            preprocessor.transform(&[vec![0.0, 5.0], vec![75.0, 7.0], vec![1e9, 5.0]]);
+        assert_eq!(
+            transformed,
+            vec![vec![-0.98, 0.0], vec![0.5, 0.0], vec![0.98, 0.0]]
+        );
+
+        let standardized = Preprocessor::fit(&[Step::Log, Step::Standardize], &x).transform(&x);
+        let mean = standardized.iter().map(|row| row[0]).sum::<f32>() / x.len() as f32;
+        assert!(mean.abs() < 1e-5);
+        assert_eq!(Preprocessor::fit(&[], &x).transform(&x), x);
+        assert_eq!("robust-scale".parse(), Ok(Step::RobustScale));
+        assert!("scale".parse::<Step>().is_err());
+    }
+
+    #[test]
+    fn maps_constant_columns_to_zero() {
+        let x: Vec<Vec<f32>> = (0..10).map(|i| vec![i as f32, 3.0]).collect();
+        for step in [Step::Standardize, Step::RobustScale] {
+            let transformed =
+                Preprocessor::fit(&[step], &x).transform(&[vec![4.0, 3.0], vec![4.0, -8.0]]);
+            assert_eq!(transformed[0][1], 0.0);
+            assert_eq!(transformed[1][1], 0.0);
+            assert!(transformed[0][0].is_finite());
+        }
+    }
+
+    #[test]
+    fn round_trips_with_the_model() {
+        let features: Vec<Vec<f32>> = (0..200)
+            .map(|i| vec![i as f32 * 1e6, (i % 7) as f32])
+            .collect();
+        let targets: Vec<f32> = features.iter().map(|row| row[0] / 1e6 - row[1]).collect();
+        let kind = ModelKind::Ridge;
+        let mut model = PreprocessedPredictor::new(
+            kind,
+            &[Step::Log, Step::Standardize],
+            kind.new_backend(2, &Labeler::default(), &GbdtParams::default())
+                .unwrap(),
+        );
+        model.fit(&features, &targets, &vec![1.0; targets.len()]);
+
+        let path = std::env::temp_dir().join(format!("preprocessed-{}.model", std::process::id()));
+        let path = path.to_str().unwrap();
+        model.save(path).unwrap();
+        let loaded = kind.load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(PreprocessedPredictor::path_for(path)).unwrap();
+        assert_eq!(loaded.predict(&features), model.predict(&features));
+        assert!(loaded.describe().starts_with("log>standardize ridge("));
+    }
+}
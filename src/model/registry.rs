@@ -0,0 +1,308 @@
+use std::{error::Error, fs, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    drift::TrainingDistribution,
+    evaluation::FoldMetrics,
+    feature_registry::FeatureSchema,
+    introspection::ModelReport,
+    labeling::Labeler,
+    predictor::{ModelKind, Predictor},
+};
+use crate::MODEL_REGISTRY_DIR;
+
+/// File in the registry root holding the version the runtime serves.
+const PROMOTED_FILE: &str = "promoted";
+const METADATA_FILE: &str = "metadata.json";
+const REPORT_FILE: &str = "report.json";
+const DISTRIBUTION_FILE: &str = "distribution.json";
+
+/// Span of the observations a model was fitted on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrainingWindow {
+    pub first_timestamp: i64,
+    pub last_timestamp: i64,
+    pub rows: usize,
+}
+
+/// What is recorded about each model in the registry.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelMetadata {
+    pub version: u64,
+    /// The version that was promoted when this one was trained.
+    pub parent: Option<u64>,
+    pub kind: ModelKind,
+    pub labeler: Labeler,
+    /// The backend and its parameters, as described by the predictor.
+    pub config: String,
+    pub schema_hash: String,
+    pub trained_at: DateTime<Utc>,
+    pub training_window: TrainingWindow,
+    /// Mean walk-forward scores of the model.
+    pub metrics: FoldMetrics,
+    /// Predicted ticks needed before trading on a prediction.
+    pub mae: i32,
+}
+
+/// A directory of versioned models, one subdirectory per version with the model, its feature
+/// schema and its metadata, and a pointer to the promoted version.
+#[derive(Debug, Clone)]
+pub struct ModelRegistry {
+    root: PathBuf,
+}
+impl ModelRegistry {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+    /// The registry in `MODEL_REGISTRY_DIR`, or the directory it names.
+    pub fn from_env() -> Self {
+        Self::new(std::env::var("MODEL_REGISTRY_DIR").unwrap_or(MODEL_REGISTRY_DIR.to_string()))
+    }
+    fn version_dir(&self, version: u64) -> PathBuf {
+        self.root.join(format!("{:06}", version))
+    }
+    fn model_path(&self, version: u64, kind: ModelKind) -> String {
+        self.version_dir(version)
+            .join(kind.file_name())
+            .to_string_lossy()
+            .into_owned()
+    }
+    /// Metadata of every version, oldest first.
+    pub fn list(&self) -> Result<Vec<ModelMetadata>, Box<dyn Error>> {
+        if !self.root.exists() {
+            return Ok(Vec::new());
+        }
+        let mut versions = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path().join(METADATA_FILE);
+            if path.exists() {
+                versions.push(serde_json::from_str::<ModelMetadata>(&fs::read_to_string(
+                    path,
+                )?)?);
+            }
+        }
+        versions.sort_by_key(|metadata| metadata.version);
+        Ok(versions)
+    }
+    pub fn metadata(&self, version: u64) -> Result<ModelMetadata, Box<dyn Error>> {
+        let path = self.version_dir(version).join(METADATA_FILE);
+        let json = fs::read_to_string(&path)
+            .map_err(|e| format!("no model version {} ({:?}): {}", version, path, e))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+    pub fn next_version(&self) -> Result<u64, Box<dyn Error>> {
+        Ok(self
+            .list()?
+            .last()
+            .map_or(1, |metadata| metadata.version + 1))
+    }
+    /// Stores `model` under `metadata.version`, with the current feature schema, and the report of
+    /// its training run and the distribution of its training features if there are any.
+    pub fn register(
+        &self,
+        model: &dyn Predictor,
+        metadata: &ModelMetadata,
+        report: Option<&ModelReport>,
+        distribution: Option<&TrainingDistribution>,
+    ) -> Result<(), Box<dyn Error>> {
+        let dir = self.version_dir(metadata.version);
+        if dir.exists() {
+            return Err(format!("model version {} already exists", metadata.version).into());
+        }
+        fs::create_dir_all(&dir)?;
+        let path = self.model_path(metadata.version, metadata.kind);
+        model.save(&path)?;
+        FeatureSchema::current().save(&path)?;
+        fs::write(
+            dir.join(METADATA_FILE),
+            serde_json::to_string_pretty(metadata)?,
+        )?;
+        if let Some(report) = report {
+            fs::write(dir.join(REPORT_FILE), serde_json::to_string_pretty(report)?)?;
+        }
+        if let Some(distribution) = distribution {
+            fs::write(
+                dir.join(DISTRIBUTION_FILE),
+                serde_json::to_string(distribution)?,
+            )?;
+        }
+        Ok(())
+    }
+    pub fn distribution(&self, version: u64) -> Result<TrainingDistribution, Box<dyn Error>> {
+        let path = self.version_dir(version).join(DISTRIBUTION_FILE);
+        let json = fs::read_to_string(&path).map_err(|e| {
+            format!(
+                "no training distribution of model version {} ({:?}): {}",
+                version, path, e
+            )
+        })?;
+        Ok(serde_json::from_str(&json)?)
+    }
+    pub fn report(&self, version: u64) -> Result<ModelReport, Box<dyn Error>> {
+        let path = self.version_dir(version).join(REPORT_FILE);
+        let json = fs::read_to_string(&path)
+            .map_err(|e| format!("no report of model version {} ({:?}): {}", version, path, e))?;
+        Ok(serde_json::from_str(&json)?)
+    }
+    /// The version the runtime serves, if any was promoted.
+    pub fn promoted(&self) -> Result<Option<u64>, Box<dyn Error>> {
+        match fs::read_to_string(self.root.join(PROMOTED_FILE)) {
+            Ok(version) => Ok(Some(version.trim().parse()?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+    /// Points the runtime at `version`, replacing the pointer atomically.
+    pub fn promote(&self, version: u64) -> Result<(), Box<dyn Error>> {
+        self.metadata(version)?;
+        let tmp = self.root.join(format!("{}.tmp", PROMOTED_FILE));
+        fs::write(&tmp, version.to_string())?;
+        fs::rename(tmp, self.root.join(PROMOTED_FILE))?;
+        Ok(())
+    }
+    /// Promotes the parent of the promoted version and returns it.
+    pub fn rollback(&self) -> Result<u64, Box<dyn Error>> {
+        let promoted = self.promoted()?.ok_or("no promoted model to roll back")?;
+        let parent = self
+            .metadata(promoted)?
+            .parent
+            .ok_or(format!("model version {} has no parent", promoted))?;
+        self.promote(parent)?;
+        Ok(parent)
+    }
+    /// Loads a version, failing if it was trained on different features.
+    pub fn load(
+        &self,
+        version: u64,
+    ) -> Result<(Box<dyn Predictor>, ModelMetadata), Box<dyn Error>> {
+        let metadata = self.metadata(version)?;
+        let path = self.model_path(version, metadata.kind);
+        let model = metadata.kind.load(&path)?;
+        FeatureSchema::current().check_saved(&path)?;
+        Ok((model, metadata))
+    }
+}
+
+/// Actions of the `models` command.
+#[derive(Debug, Clone, Subcommand)]
+pub enum ModelsCommand {
+    /// List every version, marking the promoted one.
+    List,
+    /// Print the metadata of a version.
+    Inspect { version: u64 },
+    /// Serve a version from the next start.
+    Promote { version: u64 },
+    /// Promote the parent of the promoted version.
+    Rollback,
+}
+
+/// Runs a `models` action against `registry`, printing its result.
+pub fn run_models_command(
+    registry: &ModelRegistry,
+    command: &ModelsCommand,
+) -> Result<(), Box<dyn Error>> {
+    match command {
+        ModelsCommand::List => {
+            let promoted = registry.promoted()?;
+            for metadata in registry.list()? {
+                println!(
+                    "{}{:>6} {} parent={:?} rows={} mae={:.3} pnl={:.1} {}",
+                    if promoted == Some(metadata.version) {
+                        "*"
+                    } else {
+                        " "
+                    },
+                    metadata.version,
+                    metadata.trained_at.format("%Y-%m-%d %H:%M:%S"),
+                    metadata.parent,
+                    metadata.training_window.rows,
+                    metadata.metrics.mae,
+                    metadata.metrics.pnl,
+                    metadata.config
+                );
+            }
+        }
+        ModelsCommand::Inspect { version } => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&registry.metadata(*version)?)?
+            );
+        }
+        ModelsCommand::Promote { version } => {
+            registry.promote(*version)?;
+            println!("Promoted model version {}", version);
+        }
+        ModelsCommand::Rollback => {
+            println!("Rolled back to model version {}", registry.rollback()?);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::{feature_registry::feature_size, predictor::gbdt::GbdtParams},
+        test_support::temp_registry,
+    };
+
+    fn metadata(registry: &ModelRegistry, parent: Option<u64>) -> ModelMetadata {
+        ModelMetadata {
+            version: registry.next_version().unwrap(),
+            parent,
+            kind: ModelKind::Ridge,
+            labeler: Labeler::default(),
+            config: "ridge".to_string(),
+            schema_hash: FeatureSchema::current().hash,
+            trained_at: Utc::now(),
+            training_window: TrainingWindow {
+                first_timestamp: 0,
+                last_timestamp: 1000,
+                rows: 10,
+            },
+            metrics: FoldMetrics::default(),
+            mae: 4,
+        }
+    }
+
+    #[test]
+    fn registers_promotes_and_rolls_back_versions() {
+        let registry = temp_registry();
+        assert_eq!(registry.promoted().unwrap(), None);
+        let model = ModelKind::Ridge
+            .new_predictor(1, &Labeler::default(), &GbdtParams::default())
+            .unwrap();
+        let first = metadata(&registry, None);
+        registry
+            .register(model.as_ref(), &first, None, None)
+            .unwrap();
+        registry.promote(first.version).unwrap();
+        let second = metadata(&registry, Some(first.version));
+        let distribution = TrainingDistribution::fit(&[vec![0.0; feature_size()]]);
+        registry
+            .register(model.as_ref(), &second, None, Some(&distribution))
+            .unwrap();
+        registry.promote(second.version).unwrap();
+
+        assert_eq!(
+            registry.list().unwrap(),
+            vec![first.clone(), second.clone()]
+        );
+        assert!(registry
+            .register(model.as_ref(), &second, None, None)
+            .is_err());
+        let (_, loaded) = registry.load(second.version).unwrap();
+        assert_eq!(loaded, second);
+        assert_eq!(registry.distribution(second.version).unwrap(), distribution);
+        assert!(registry.distribution(first.version).is_err());
+        assert_eq!(registry.rollback().unwrap(), first.version);
+        assert_eq!(registry.promoted().unwrap(), Some(first.version));
+        assert!(registry.rollback().is_err());
+        assert!(registry.promote(99).is_err());
+        fs::remove_dir_all(&registry.root).unwrap();
+    }
+}
@@ -0,0 +1,194 @@
+use std::{collections::VecDeque, fmt, str::FromStr};
+
+use rust_decimal::{Decimal, MathematicalOps};
+use serde::{Deserialize, Serialize};
+
+/// How far back a rolling feature or the target looks: the last `Count` observations, or those
+/// within `Time` milliseconds of the latest one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Window {
+    Count(usize),
+    Time(i64),
+}
+impl Window {
+    /// True if an observation `count` observations and `ms` milliseconds after the start of the
+    /// window still falls in it.
+    pub fn contains(&self, count: usize, ms: i64) -> bool {
+        match self {
+            Self::Count(window) => count < *window,
+            Self::Time(window_ms) => ms < *window_ms,
+        }
+    }
+}
+impl Default for Window {
+    fn default() -> Self {
+        Self::Count(0)
+    }
+}
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Count(window) => write!(f, "{}", window),
+            Self::Time(window_ms) => write!(f, "{}ms", window_ms),
+        }
+    }
+}
+
+/// Parses a count of observations such as `500`, or a duration such as `60000ms` or `60s`.
+impl FromStr for Window {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parsed = if let Some(ms) = s.strip_suffix("ms") {
+            ms.parse().map(Self::Time)
+        } else if let Some(seconds) = s.strip_suffix('s') {
+            seconds
+                .parse()
+                .map(|seconds: i64| Self::Time(seconds * 1000))
+        } else {
+            s.parse().map(Self::Count)
+        };
+        parsed.map_err(|e| format!("invalid window {}: {}", s, e))
+    }
+}
+
+/// Sum, mean and population variance of the values in a [`Window`], each push is O(1)
+/// amortised. Sums are kept exactly in `Decimal`, so evicting a value leaves no rounding drift.
+#[derive(Debug, Clone, Default)]
+pub struct RollingWindow {
+    window: Window,
+    /// (timestamp, value), timestamps are only used by time windows.
+    values: VecDeque<(i64, Decimal)>,
+    sum: Decimal,
+    sum_squares: Decimal,
+    first_timestamp: Option<i64>,
+}
+impl RollingWindow {
+    /// A window over the last `window` values.
+    pub fn new(window: usize) -> Self {
+        Self::over(Window::Count(window))
+    }
+    pub fn over(window: Window) -> Self {
+        let capacity = match window {
+            Window::Count(window) => window + 1,
+            Window::Time(_) => 0,
+        };
+        Self {
+            window,
+            values: VecDeque::with_capacity(capacity),
+            ..Default::default()
+        }
+    }
+    /// Pushes a value into a count window.
+    pub fn push(&mut self, value: Decimal) {
+        debug_assert!(matches!(self.window, Window::Count(_)));
+        self.push_at(0, value);
+    }
+    /// Pushes a value observed at `timestamp` in milliseconds, timestamps must not decrease.
+    pub fn push_at(&mut self, timestamp: i64, value: Decimal) {
+        self.first_timestamp.get_or_insert(timestamp);
+        self.values.push_back((timestamp, value));
+        self.sum += value;
+        self.sum_squares += value * value;
+        while let Some((oldest_timestamp, _)) = self.values.front() {
+            if self
+                .window
+                .contains(self.values.len() - 1, timestamp - oldest_timestamp)
+            {
+                break;
+            }
+            let (_, evicted) = self.values.pop_front().unwrap();
+            self.sum -= evicted;
+            self.sum_squares -= evicted * evicted;
+        }
+    }
+    /// True once the values span the whole window.
+    pub fn is_full(&self) -> bool {
+        match (self.window, self.values.back(), self.first_timestamp) {
+            (Window::Count(window), _, _) => self.values.len() == window,
+            (Window::Time(window_ms), Some((latest, _)), Some(first)) => {
+                latest - first >= window_ms
+            }
+            _ => false,
+        }
+    }
+    pub fn sum(&self) -> Decimal {
+        self.sum
+    }
+    pub fn mean(&self) -> Option<Decimal> {
+        (!self.values.is_empty()).then(|| self.sum / Decimal::from(self.values.len()))
+    }
+    /// Population variance, `(n·Σx² − (Σx)²) / n²`.
+    pub fn variance(&self) -> Option<Decimal> {
+        if self.values.is_empty() {
+            return None;
+        }
+        let n = Decimal::from(self.values.len());
+        Some((n * self.sum_squares - self.sum * self.sum) / (n * n))
+    }
+    pub fn std(&self) -> Option<Decimal> {
+        self.variance()
+            .and_then(|variance| variance.max(Decimal::ZERO).sqrt())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> Vec<Decimal> {
+        (0..500)
+            .map(|i| Decimal::from((i * 7919 % 613) as i64 - 300) / Decimal::from(8))
+            .collect()
+    }
+
+    #[test]
+    fn matches_a_full_recomputation_of_every_window() {
+        let window = 37;
+        let values = values();
+        let mut rolling = RollingWindow::new(window);
+        for (i, value) in values.iter().enumerate() {
+            rolling.push(*value);
+            let slice = &values[(i + 1).saturating_sub(window)..=i];
+            let n = Decimal::from(slice.len());
+            let mean = slice.iter().sum::<Decimal>() / n;
+            let variance = slice.iter().map(|x| (x - mean).powi(2)).sum::<Decimal>() / n;
+            assert_eq!(rolling.is_full(), i + 1 >= window);
+            assert_eq!(rolling.sum(), slice.iter().sum::<Decimal>());
+            assert_eq!(rolling.mean(), Some(mean));
+            assert!((rolling.variance().unwrap() - variance).abs() < Decimal::new(1, 18));
+        }
+    }
+
+    #[test]
+    fn time_window_keeps_values_within_its_duration() {
+        let mut rolling = RollingWindow::over(Window::Time(1_000));
+        rolling.push_at(0, Decimal::from(5));
+        rolling.push_at(400, Decimal::from(1));
+        assert!(!rolling.is_full());
+        rolling.push_at(999, Decimal::from(2));
+        assert_eq!(rolling.sum(), Decimal::from(8));
+        rolling.push_at(1_000, Decimal::from(3));
+        assert!(rolling.is_full());
+        assert_eq!(rolling.sum(), Decimal::from(6));
+        rolling.push_at(5_000, Decimal::from(4));
+        assert_eq!(rolling.mean(), Some(Decimal::from(4)));
+    }
+
+    #[test]
+    fn empty_window_has_no_statistics() {
+        let rolling = RollingWindow::new(3);
+        assert!(!rolling.is_full());
+        assert_eq!(rolling.mean(), None);
+        assert_eq!(rolling.std(), None);
+    }
+
+    #[test]
+    fn parses_windows() {
+        assert_eq!("500".parse(), Ok(Window::Count(500)));
+        assert_eq!("60000ms".parse(), Ok(Window::Time(60_000)));
+        assert_eq!("60s".parse(), Ok(Window::Time(60_000)));
+        let window = Window::Time(1_500);
+        assert_eq!(window.to_string().parse(), Ok(window));
+        assert!("1m".parse::<Window>().is_err());
+    }
+}
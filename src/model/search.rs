@@ -0,0 +1,312 @@
+use std::{
+    error::Error,
+    fs,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use clap::{Args, ValueEnum};
+use log::{info, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use rayon::prelude::*;
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+use crate::{EMBARGO, WALK_FORWARD_FOLDS};
+
+use super::{
+    data_handling::TrainingFrame,
+    evaluation::{score_folds, Dataset, FoldMetrics, WalkForward},
+    feature_registry::feature_size,
+    labeling::Labeler,
+    predictor::gbdt::{GbdtParams, GbdtPredictor, GBDT_PARAMS_FILE},
+    store::read_spills,
+    weighting::SampleWeighting,
+};
+
+const DEPTHS: [u32; 3] = [4, 6, 8];
+const ITERATIONS: [usize; 3] = [100, 300, 500];
+const SHRINKAGES: [f32; 3] = [0.05, 0.1, 0.2];
+const SAMPLE_RATIOS: [f64; 2] = [0.8, 1.0];
+const LOSSES: [&str; 2] = ["SquaredError", "LAD"];
+/// Share of the candidates kept after each successive-halving round.
+const HALVING_ETA: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SearchMode {
+    /// Every combination of a few values of each parameter.
+    Grid,
+    /// Parameters drawn at random from ranges around the grid.
+    Random,
+}
+
+/// Options of the `search` command.
+#[derive(Debug, Clone, Args)]
+pub struct SearchArgs {
+    /// Observations recorded as JSON lines, such as `OBSERVATIONS_SPILL_PATH` files.
+    #[arg(long = "data", required = true)]
+    pub data: Vec<PathBuf>,
+    #[arg(long, value_enum, default_value_t = SearchMode::Grid)]
+    pub mode: SearchMode,
+    /// Candidates drawn in random mode.
+    #[arg(long, default_value_t = 50)]
+    pub trials: usize,
+    #[arg(long, default_value_t = 0)]
+    pub seed: u64,
+    /// Score all candidates on the latest rows first and only the best on all of them.
+    #[arg(long)]
+    pub halving: bool,
+    #[arg(long, default_value = "0.10")]
+    pub tick_size: Decimal,
+    #[arg(long, default_value_t = WALK_FORWARD_FOLDS)]
+    pub folds: usize,
+    /// Directory the leaderboard and best parameters are written to.
+    #[arg(long, default_value = "search")]
+    pub output: PathBuf,
+}
+
+/// A candidate configuration and its mean walk-forward scores.
+#[derive(Debug, Clone, Serialize)]
+pub struct Trial {
+    pub params: GbdtParams,
+    pub metrics: FoldMetrics,
+    /// Rows the candidate was scored on.
+    pub rows: usize,
+}
+
+/// Every combination of the grid values.
+pub fn grid() -> Vec<GbdtParams> {
+    let mut candidates = Vec::new();
+    for max_depth in DEPTHS {
+        for iterations in ITERATIONS {
+            for shrinkage in SHRINKAGES {
+                for data_sample_ratio in SAMPLE_RATIOS {
+                    for feature_sample_ratio in SAMPLE_RATIOS {
+                        for loss in LOSSES {
+                            candidates.push(GbdtParams {
+                                max_depth,
+                                iterations,
+                                shrinkage,
+                                data_sample_ratio,
+                                feature_sample_ratio,
+                                loss: loss.to_string(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+    candidates
+}
+
+/// `trials` candidates drawn at random, shrinkage on a log scale.
+pub fn random(trials: usize, rng: &mut impl Rng) -> Vec<GbdtParams> {
+    (0..trials)
+        .map(|_| GbdtParams {
+            max_depth: rng.gen_range(3..=10),
+            iterations: rng.gen_range(50..=1000),
+            shrinkage: 10f32.powf(rng.gen_range(-2.0..-0.5)),
+            data_sample_ratio: rng.gen_range(0.5..=1.0),
+            feature_sample_ratio: rng.gen_range(0.5..=1.0),
+            loss: LOSSES[rng.gen_range(0..LOSSES.len())].to_string(),
+        })
+        .collect()
+}
+
+/// Scores the candidates in parallel, best first. Candidates are scored with `score`, which
+/// returns `None` when the data is too short to split into folds.
+fn run_trials<F>(candidates: &[GbdtParams], score: F) -> Vec<Trial>
+where
+    F: Fn(&GbdtParams) -> Option<Trial> + Send + Sync,
+{
+    let mut trials: Vec<Trial> = candidates.par_iter().filter_map(score).collect();
+    trials.sort_by(|a, b| a.metrics.mae.total_cmp(&b.metrics.mae));
+    trials
+}
+
+/// Successive halving: scores every candidate on the latest rows, keeps the best
+/// `1 / HALVING_ETA` of them, and repeats on `HALVING_ETA` times as many rows until one round
+/// uses all of them. Returns the last round's trials first, then those eliminated earlier.
+fn successive_halving<F>(candidates: Vec<GbdtParams>, score: F) -> Vec<Trial>
+where
+    F: Fn(&GbdtParams, f64) -> Option<Trial> + Send + Sync,
+{
+    let mut rounds = 0;
+    while HALVING_ETA.pow(rounds) < candidates.len() {
+        rounds += 1;
+    }
+    let mut survivors = candidates;
+    let mut eliminated = Vec::new();
+    for round in 0..=rounds {
+        let fraction = 1.0 / HALVING_ETA.pow(rounds - round) as f64;
+        let mut trials = run_trials(&survivors, |params| score(params, fraction));
+        info!(
+            "Halving round {}: {} candidates on {:.0}% of the rows",
+            round,
+            survivors.len(),
+            fraction * 100.0
+        );
+        if round == rounds {
+            trials.extend(eliminated.into_iter().rev().flatten());
+            return trials;
+        }
+        if trials.is_empty() {
+            // too few rows to score anything yet, carry every survivor over
+            continue;
+        }
+        let keep = (trials.len() / HALVING_ETA).max(1);
+        eliminated.push(trials.split_off(keep));
+        survivors = trials.into_iter().map(|trial| trial.params).collect();
+    }
+    unreachable!()
+}
+
+/// Scores a candidate with walk-forward validation on `dataset`.
+fn evaluate(params: &GbdtParams, dataset: &Dataset, walk_forward: &WalkForward) -> Option<Trial> {
+    let folds = walk_forward.folds(&dataset.timestamps);
+    if folds.is_empty() {
+        warn!("Not enough rows to score {:?}", params);
+        return None;
+    }
+    let fold_metrics = match score_folds(dataset, &folds, || {
+        Ok(Box::new(GbdtPredictor::with_params(feature_size(), params)))
+    }) {
+        Ok(fold_metrics) => fold_metrics,
+        Err(e) => {
+            warn!("Could not score {:?}: {}", params, e);
+            return None;
+        }
+    };
+    Some(Trial {
+        params: params.clone(),
+        metrics: FoldMetrics::mean(&fold_metrics),
+        rows: dataset.len(),
+    })
+}
+
+fn write_leaderboard(path: &Path, trials: &[Trial]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(fs::File::create(path)?);
+    writeln!(
+        writer,
+        "rank,rows,mae,directional_accuracy,ic,pnl,max_depth,iterations,shrinkage,data_sample_ratio,feature_sample_ratio,loss"
+    )?;
+    for (rank, trial) in trials.iter().enumerate() {
+        let (metrics, params) = (&trial.metrics, &trial.params);
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{}",
+            rank + 1,
+            trial.rows,
+            metrics.mae,
+            metrics.directional_accuracy,
+            metrics.ic,
+            metrics.pnl,
+            params.max_depth,
+            params.iterations,
+            params.shrinkage,
+            params.data_sample_ratio,
+            params.feature_sample_ratio,
+            params.loss
+        )?;
+    }
+    writer.flush()
+}
+
+/// Runs a hyperparameter search on recorded observations, writing the leaderboard and the best
+/// parameters to the output directory.
+pub fn search(args: &SearchArgs) -> Result<(), Box<dyn Error>> {
+    let observations = read_spills(&args.data)?;
+    info!("Loaded {} observations", observations.len());
+    let mut features = TrainingFrame { data: observations };
+    let labeler = Labeler::from_env()?;
+    features.prepare(&labeler, args.tick_size);
+    let mut dataset = Dataset::from_rows(&features.data)?;
+    dataset.w = SampleWeighting::from_env().weights(
+        &dataset.timestamps,
+        &dataset.y,
+        labeler.horizon(),
+        None,
+    );
+    let walk_forward = WalkForward {
+        folds: args.folds,
+        horizon: labeler.horizon(),
+        embargo: EMBARGO,
+    };
+    let candidates = match args.mode {
+        SearchMode::Grid => grid(),
+        SearchMode::Random => random(args.trials, &mut StdRng::seed_from_u64(args.seed)),
+    };
+    info!(
+        "Searching {} candidates on {} rows",
+        candidates.len(),
+        dataset.len()
+    );
+    let trials = if args.halving {
+        successive_halving(candidates, |params, fraction| {
+            evaluate(params, &dataset.tail(fraction), &walk_forward)
+        })
+    } else {
+        run_trials(&candidates, |params| {
+            evaluate(params, &dataset, &walk_forward)
+        })
+    };
+    let best = trials.first().ok_or("no candidate could be scored")?;
+    info!("Best: {:?} {:?}", best.params, best.metrics);
+    fs::create_dir_all(&args.output)?;
+    write_leaderboard(&args.output.join("leaderboard.csv"), &trials)?;
+    fs::write(
+        args.output.join(GBDT_PARAMS_FILE),
+        serde_json::to_string_pretty(&best.params)?,
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trial(params: &GbdtParams, fraction: f64) -> Option<Trial> {
+        // deeper trees score better, and only on enough rows
+        (fraction > 0.1).then(|| Trial {
+            params: params.clone(),
+            metrics: FoldMetrics {
+                mae: 1.0 / params.max_depth as f32,
+                ..Default::default()
+            },
+            rows: (fraction * 900.0) as usize,
+        })
+    }
+
+    #[test]
+    fn generates_candidates() {
+        let grid = grid();
+        assert_eq!(grid.len(), 3 * 3 * 3 * 2 * 2 * 2);
+        assert!(grid.contains(&GbdtParams::default()));
+        let mut rng = StdRng::seed_from_u64(7);
+        let random = random(20, &mut rng);
+        assert_eq!(random.len(), 20);
+        assert!(random
+            .iter()
+            .all(|params| (0.01..=0.32).contains(&params.shrinkage)
+                && (3..=10).contains(&params.max_depth)));
+    }
+
+    #[test]
+    fn halving_scores_the_best_candidates_on_all_rows() {
+        let candidates: Vec<GbdtParams> = (1..=10)
+            .map(|max_depth| GbdtParams {
+                max_depth,
+                ..Default::default()
+            })
+            .collect();
+        // rounds on 1/27, 1/9, 1/3 and all of the rows, none can be scored in the first
+        let trials = successive_halving(candidates, trial);
+        let scored: Vec<(u32, usize)> = trials
+            .iter()
+            .map(|trial| (trial.params.max_depth, trial.rows))
+            .collect();
+        assert_eq!(scored[..4], [(10, 900), (9, 300), (8, 300), (7, 100)]);
+        assert_eq!(scored.len(), 10);
+    }
+}
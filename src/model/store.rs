@@ -0,0 +1,278 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+};
+
+use log::{debug, error};
+
+use super::data_handling::Observation;
+
+/// Observations per sealed chunk, eviction and snapshots work a chunk at a time.
+const CHUNK_SIZE: usize = 4096;
+
+/// How long observations are kept in memory. Each limit keeps at least what it asks for,
+/// evicting whole chunks once they fall entirely outside it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Retention {
+    pub max_len: Option<usize>,
+    pub max_age_ms: Option<i64>,
+    /// Evicted observations are appended here as JSON lines, if set.
+    pub spill_path: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+enum SpillMessage {
+    Chunk(Arc<Vec<Observation>>),
+    /// Answered once every chunk sent before it is written.
+    Flush(mpsc::Sender<()>),
+}
+
+/// Appends chunks to the spill file on a thread of its own, so eviction doesn't write to disk
+/// while the dataframe is locked.
+#[derive(Debug)]
+struct SpillWriter {
+    sender: mpsc::Sender<SpillMessage>,
+}
+impl SpillWriter {
+    fn start(path: PathBuf) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            for message in receiver {
+                match message {
+                    SpillMessage::Chunk(observations) => {
+                        if let Err(e) = spill(&path, &observations) {
+                            error!("Error spilling observations to {:?}: {:?}", path, e);
+                        }
+                    }
+                    SpillMessage::Flush(done) => {
+                        let _ = done.send(());
+                    }
+                }
+            }
+        });
+        Self { sender }
+    }
+    fn send(&self, observations: Arc<Vec<Observation>>) {
+        if self.sender.send(SpillMessage::Chunk(observations)).is_err() {
+            error!("The spill writer stopped, observations are dropped");
+        }
+    }
+    /// Blocks until every chunk sent so far is written.
+    fn flush(&self) {
+        let (done, written) = mpsc::channel();
+        if self.sender.send(SpillMessage::Flush(done)).is_ok() {
+            let _ = written.recv();
+        }
+    }
+}
+
+/// Append-only ring buffer of observations. Full chunks are sealed behind an `Arc`, so a
+/// snapshot only copies the pointers and the unsealed tail. Not `Clone`, so only one store spills
+/// its rows to the spill writer.
+#[derive(Debug, Default)]
+pub struct ObservationStore {
+    chunks: VecDeque<Arc<Vec<Observation>>>,
+    tail: Vec<Observation>,
+    len: usize,
+    pushed: usize,
+    retention: Retention,
+    spill_writer: Option<SpillWriter>,
+}
+impl ObservationStore {
+    pub fn new(retention: Retention) -> Self {
+        Self {
+            spill_writer: retention.spill_path.clone().map(SpillWriter::start),
+            retention,
+            tail: Vec::with_capacity(CHUNK_SIZE),
+            ..Default::default()
+        }
+    }
+    pub fn push(&mut self, observation: Observation) {
+        self.tail.push(observation);
+        self.len += 1;
+        self.pushed += 1;
+        if self.tail.len() == CHUNK_SIZE {
+            let sealed = std::mem::replace(&mut self.tail, Vec::with_capacity(CHUNK_SIZE));
+            self.chunks.push_back(Arc::new(sealed));
+            self.evict();
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.len
+    }
+    /// Observations pushed since creation, including evicted ones.
+    pub fn pushed(&self) -> usize {
+        self.pushed
+    }
+    pub fn last(&self) -> Option<&Observation> {
+        self.tail
+            .last()
+            .or_else(|| self.chunks.back().and_then(|chunk| chunk.last()))
+    }
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            chunks: self.chunks.iter().cloned().collect(),
+            tail: self.tail.clone(),
+        }
+    }
+    fn evict(&mut self) {
+        let newest = match self.last() {
+            Some(observation) => observation.timestamp,
+            None => return,
+        };
+        while let Some(oldest) = self.chunks.front() {
+            let over_len = matches!(
+                self.retention.max_len,
+                Some(max_len) if self.len - oldest.len() >= max_len
+            );
+            let over_age = matches!(
+                self.retention.max_age_ms,
+                Some(max_age_ms) if oldest.last().unwrap().timestamp < newest - max_age_ms
+            );
+            if !over_len && !over_age {
+                break;
+            }
+            let evicted = self.chunks.pop_front().unwrap();
+            self.len -= evicted.len();
+            debug!("Evicted {} observations", evicted.len());
+            if let Some(writer) = &self.spill_writer {
+                writer.send(evicted);
+            }
+        }
+    }
+    /// Moves every retained observation to the spill file, after the evicted ones, and blocks
+    /// until they are written. Called on shutdown so the spill holds every observation.
+    pub fn spill_retained(&mut self) {
+        let Some(writer) = &self.spill_writer else {
+            return;
+        };
+        for chunk in self.chunks.drain(..) {
+            writer.send(chunk);
+        }
+        if !self.tail.is_empty() {
+            writer.send(Arc::new(std::mem::take(&mut self.tail)));
+        }
+        self.len = 0;
+        writer.flush();
+    }
+}
+
+fn spill(path: &PathBuf, observations: &[Observation]) -> std::io::Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = BufWriter::new(file);
+    for observation in observations {
+        serde_json::to_writer(&mut writer, observation)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()
+}
+
+/// Reads back observations spilled as JSON lines.
+pub fn read_spill(path: &Path) -> Result<Vec<Observation>, Box<dyn std::error::Error>> {
+    let mut observations = Vec::new();
+    for line in BufReader::new(File::open(path)?).lines() {
+        observations.push(serde_json::from_str(&line?)?);
+    }
+    Ok(observations)
+}
+
+/// Reads back the observations spilled to each of `paths`, in timestamp order.
+pub fn read_spills(paths: &[PathBuf]) -> Result<Vec<Observation>, Box<dyn std::error::Error>> {
+    let mut observations = Vec::new();
+    for path in paths {
+        observations.extend(read_spill(path)?);
+    }
+    observations.sort_by_key(|observation| observation.timestamp);
+    Ok(observations)
+}
+
+/// Read-only view of the store at the time it was taken.
+#[derive(Debug, Clone, Default)]
+pub struct Snapshot {
+    chunks: Vec<Arc<Vec<Observation>>>,
+    tail: Vec<Observation>,
+}
+impl Snapshot {
+    pub fn len(&self) -> usize {
+        self.chunks.iter().map(|chunk| chunk.len()).sum::<usize>() + self.tail.len()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &Observation> {
+        self.chunks
+            .iter()
+            .flat_map(|chunk| chunk.iter())
+            .chain(self.tail.iter())
+    }
+    pub fn to_vec(&self) -> Vec<Observation> {
+        let mut observations = Vec::with_capacity(self.len());
+        observations.extend(self.iter().cloned());
+        observations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::observations;
+
+    fn store(retention: Retention, count: usize) -> ObservationStore {
+        let mut store = ObservationStore::new(retention);
+        for observation in observations(count) {
+            store.push(observation);
+        }
+        store
+    }
+
+    #[test]
+    fn keeps_at_least_max_len_observations() {
+        let store = store(
+            Retention {
+                max_len: Some(5000),
+                ..Default::default()
+            },
+            5 * CHUNK_SIZE + 10,
+        );
+        assert!(store.len() >= 5000 && store.len() < 5000 + CHUNK_SIZE);
+        let snapshot = store.snapshot();
+        assert_eq!(snapshot.len(), store.len());
+        assert_eq!(snapshot.iter().last(), store.last());
+        let timestamps: Vec<i64> = snapshot.iter().map(|x| x.timestamp).collect();
+        assert!(timestamps.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn evicts_by_age_and_spills_evicted_rows() {
+        let spill_path = std::env::temp_dir().join(format!(
+            "nshft-spill-{}-{}.jsonl",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap()
+        ));
+        // observations are 100ms apart, keep the last minute
+        let store = store(
+            Retention {
+                max_age_ms: Some(60_000),
+                spill_path: Some(spill_path.clone()),
+                ..Default::default()
+            },
+            3 * CHUNK_SIZE + 1,
+        );
+        let newest = store.last().unwrap().timestamp;
+        let snapshot = store.snapshot();
+        assert!(snapshot.iter().nth(CHUNK_SIZE).unwrap().timestamp >= newest - 60_000);
+        assert!(store.len() < 3 * CHUNK_SIZE);
+
+        // on shutdown the retained rows follow the evicted ones
+        let mut store = store;
+        store.spill_retained();
+        assert_eq!(store.len(), 0);
+        let spilled = read_spill(&spill_path).unwrap();
+        std::fs::remove_file(&spill_path).unwrap();
+        assert_eq!(spilled.len(), 3 * CHUNK_SIZE + 1);
+        assert_eq!(spilled[0].timestamp, observations(1)[0].timestamp);
+        assert!(spilled
+            .windows(2)
+            .all(|pair| pair[0].timestamp < pair[1].timestamp));
+    }
+}
@@ -0,0 +1,96 @@
+use std::f64::consts::TAU;
+
+use chrono::{Datelike, TimeZone, Timelike, Utc};
+use rust_decimal::Decimal;
+
+use super::data_handling::Observation;
+
+const MS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+/// Funding is exchanged every 8 hours, at 00:00, 08:00 and 16:00 UTC.
+const FUNDING_INTERVAL_MS: i64 = 8 * 60 * 60 * 1000;
+/// Relative price change, in basis points, from the previous large move that counts as a new one.
+const LARGE_MOVE_BPS: i64 = 20;
+
+/// Position of `timestamp` in the UTC day, as an angle.
+pub fn time_of_day_angle(timestamp: i64) -> f64 {
+    timestamp.rem_euclid(MS_PER_DAY) as f64 / MS_PER_DAY as f64 * TAU
+}
+
+/// Position of `timestamp` in the week starting on Monday UTC, as an angle. `None` if the
+/// timestamp is out of the range of dates.
+pub fn day_of_week_angle(timestamp: i64) -> Option<f64> {
+    let time = Utc.timestamp_millis_opt(timestamp).single()?;
+    let day = time.weekday().num_days_from_monday() as f64;
+    let day_fraction = time.num_seconds_from_midnight() as f64 / 86_400.0;
+    Some((day + day_fraction) / 7.0 * TAU)
+}
+
+/// Seconds until the next funding time.
+pub fn seconds_to_funding(timestamp: i64) -> f64 {
+    let since_funding = timestamp.rem_euclid(FUNDING_INTERVAL_MS);
+    (FUNDING_INTERVAL_MS - since_funding) as f64 / 1000.0
+}
+
+/// Tracks the time of the last large price move, a move being large once the price is
+/// `LARGE_MOVE_BPS` away from where the previous one ended.
+#[derive(Debug, Clone, Default)]
+pub struct MoveClock {
+    /// Price and timestamp at the end of the last large move.
+    anchor: Option<(Decimal, i64)>,
+}
+impl MoveClock {
+    /// Sets the seconds since the last large move before `observation`, `None` before the first
+    /// observation.
+    pub fn fill(&self, observation: &mut Observation) {
+        observation.seconds_since_large_move = self
+            .anchor
+            .map(|(_, timestamp)| Decimal::new(observation.timestamp - timestamp, 3));
+    }
+    pub fn push(&mut self, observation: &Observation) {
+        match self.anchor {
+            Some((price, _))
+                if (observation.price - price).abs() * Decimal::from(10_000)
+                    < price * Decimal::from(LARGE_MOVE_BPS) => {}
+            _ => self.anchor = Some((observation.price, observation.timestamp)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{dec, observations};
+
+    // Monday 2024-01-01 07:59:00 UTC
+    const MONDAY_MORNING: i64 = 1_704_095_940_000;
+
+    #[test]
+    fn encodes_calendar_time() {
+        assert_eq!(seconds_to_funding(MONDAY_MORNING), 60.0);
+        assert_eq!(seconds_to_funding(MONDAY_MORNING + 60_000), 8.0 * 3600.0);
+        let monday_midnight = MONDAY_MORNING - 7 * 3_600_000 - 59 * 60_000;
+        assert_eq!(time_of_day_angle(monday_midnight), 0.0);
+        let noon = monday_midnight + MS_PER_DAY / 2;
+        assert!((time_of_day_angle(noon) - TAU / 2.0).abs() < 1e-9);
+        assert_eq!(day_of_week_angle(monday_midnight), Some(0.0));
+        let thursday_noon = monday_midnight + 3 * MS_PER_DAY + MS_PER_DAY / 2;
+        assert!((day_of_week_angle(thursday_noon).unwrap() - TAU / 2.0).abs() < 1e-9);
+        assert_eq!(day_of_week_angle(i64::MAX), None);
+    }
+
+    #[test]
+    fn counts_seconds_since_the_last_large_move() {
+        let mut clock = MoveClock::default();
+        let mut rows = observations(4);
+        // 0.1% moves don't count, 0.3% from the anchor does
+        for (row, price) in rows.iter_mut().zip(["100", "100.1", "100.3", "100.4"]) {
+            row.price = dec(price);
+            clock.fill(row);
+            clock.push(row);
+        }
+        assert_eq!(rows[0].seconds_since_large_move, None);
+        assert_eq!(rows[1].seconds_since_large_move, Some(dec("0.1")));
+        assert_eq!(rows[2].seconds_since_large_move, Some(dec("0.2")));
+        assert_eq!(rows[3].seconds_since_large_move, Some(dec("0.1")));
+    }
+}
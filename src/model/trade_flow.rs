@@ -0,0 +1,318 @@
+use std::collections::VecDeque;
+
+use rust_decimal::Decimal;
+
+use super::{
+    data_handling::Observation,
+    rolling::{RollingWindow, Window},
+};
+
+/// Trades each VPIN bucket is sized from, so buckets suit the symbol's trade sizes: a bucket holds
+/// `1 / VPIN_BUCKETS` of the volume of the last of these trades when it starts, ten average trades.
+pub const VPIN_VOLUME_TRADES: usize = 500;
+/// Buckets VPIN is averaged over.
+pub const VPIN_BUCKETS: usize = 50;
+/// Trades the arrival statistics, size quantiles and price impact are measured over.
+pub const TRADE_FLOW_WINDOW: usize = 500;
+/// Windows, in milliseconds, the buy share of volume is measured over.
+pub const BUY_SHARE_WINDOWS_MS: [i64; 3] = [1_000, 10_000, 60_000];
+
+/// Buy and sell volume of the trades within a wall-clock window of the latest one.
+#[derive(Debug, Clone)]
+struct VolumeWindow {
+    buy: RollingWindow,
+    sell: RollingWindow,
+}
+impl VolumeWindow {
+    fn new(window_ms: i64) -> Self {
+        Self {
+            buy: RollingWindow::over(Window::Time(window_ms)),
+            sell: RollingWindow::over(Window::Time(window_ms)),
+        }
+    }
+    fn push(&mut self, timestamp: i64, net_qty: Decimal) {
+        self.buy.push_at(timestamp, net_qty.max(Decimal::ZERO));
+        self.sell.push_at(timestamp, (-net_qty).max(Decimal::ZERO));
+    }
+    /// Share of the volume bought by takers, a half when there was none, once the trades span
+    /// the window.
+    fn buy_share(&self) -> Option<Decimal> {
+        if !self.buy.is_full() {
+            return None;
+        }
+        let total = self.buy.sum() + self.sell.sum();
+        Some(if total.is_zero() {
+            Decimal::new(5, 1)
+        } else {
+            self.buy.sum() / total
+        })
+    }
+}
+
+/// Trade sizes of the last `window` trades, kept sorted for quantiles.
+#[derive(Debug, Clone)]
+struct SizeQuantiles {
+    window: usize,
+    arrivals: VecDeque<Decimal>,
+    sorted: Vec<Decimal>,
+}
+impl SizeQuantiles {
+    fn new(window: usize) -> Self {
+        Self {
+            window,
+            arrivals: VecDeque::with_capacity(window + 1),
+            sorted: Vec::with_capacity(window + 1),
+        }
+    }
+    fn push(&mut self, size: Decimal) {
+        self.arrivals.push_back(size);
+        let index = self.sorted.partition_point(|s| *s < size);
+        self.sorted.insert(index, size);
+        if self.arrivals.len() > self.window {
+            let evicted = self.arrivals.pop_front().unwrap();
+            let index = self.sorted.binary_search(&evicted).unwrap();
+            self.sorted.remove(index);
+        }
+    }
+    fn is_full(&self) -> bool {
+        self.arrivals.len() == self.window
+    }
+    fn quantile(&self, q: f64) -> Option<Decimal> {
+        if self.sorted.is_empty() {
+            return None;
+        }
+        let index = ((self.sorted.len() - 1) as f64 * q).round() as usize;
+        Some(self.sorted[index])
+    }
+}
+
+/// `window` if it is full.
+fn full(window: &RollingWindow) -> Option<&RollingWindow> {
+    window.is_full().then_some(window)
+}
+
+/// Trade-flow toxicity, computed from the trades before each observation.
+#[derive(Debug, Clone)]
+pub struct TradeFlow {
+    /// Volume of the trades buckets are sized from.
+    volume: RollingWindow,
+    buckets: Decimal,
+    /// Volume of the bucket being filled, once the volume window is full.
+    bucket_volume: Option<Decimal>,
+    bucket_buy: Decimal,
+    bucket_sell: Decimal,
+    /// Order imbalance of each completed bucket, as a share of the bucket volume.
+    bucket_imbalances: RollingWindow,
+    last_timestamp: Option<i64>,
+    last_price: Option<Decimal>,
+    inter_arrival_ms: RollingWindow,
+    volumes: Vec<VolumeWindow>,
+    sizes: SizeQuantiles,
+    /// Signed volume and the price change it came with, for the price impact regression.
+    impact_qty: RollingWindow,
+    impact_price: RollingWindow,
+    impact_product: RollingWindow,
+}
+impl Default for TradeFlow {
+    fn default() -> Self {
+        Self::new(VPIN_VOLUME_TRADES, VPIN_BUCKETS, TRADE_FLOW_WINDOW)
+    }
+}
+impl TradeFlow {
+    /// Trade flow with VPIN over `buckets` buckets, each holding `1 / buckets` of the volume of
+    /// the last `volume_trades` trades, and the other features over the last `window` trades.
+    pub fn new(volume_trades: usize, buckets: usize, window: usize) -> Self {
+        Self {
+            volume: RollingWindow::new(volume_trades),
+            buckets: Decimal::from(buckets),
+            bucket_volume: None,
+            bucket_buy: Decimal::ZERO,
+            bucket_sell: Decimal::ZERO,
+            bucket_imbalances: RollingWindow::new(buckets),
+            last_timestamp: None,
+            last_price: None,
+            inter_arrival_ms: RollingWindow::new(window),
+            volumes: BUY_SHARE_WINDOWS_MS
+                .iter()
+                .map(|window_ms| VolumeWindow::new(*window_ms))
+                .collect(),
+            sizes: SizeQuantiles::new(window),
+            impact_qty: RollingWindow::new(window),
+            impact_price: RollingWindow::new(window),
+            impact_product: RollingWindow::new(window),
+        }
+    }
+    /// Sets the toxicity features of `observation` from the previous trades, each once its window
+    /// is full.
+    pub fn fill(&self, observation: &mut Observation) {
+        observation.vpin = full(&self.bucket_imbalances).and_then(RollingWindow::mean);
+        let gaps = full(&self.inter_arrival_ms);
+        observation.inter_arrival_mean_ms = gaps.and_then(RollingWindow::mean);
+        observation.inter_arrival_std_ms = gaps.and_then(RollingWindow::std);
+        observation.trade_rate = observation.inter_arrival_mean_ms.map(|gap| {
+            if gap.is_zero() {
+                Decimal::ZERO
+            } else {
+                Decimal::ONE_THOUSAND / gap
+            }
+        });
+        observation.buy_share_1s = self.volumes[0].buy_share();
+        observation.buy_share_10s = self.volumes[1].buy_share();
+        observation.buy_share_60s = self.volumes[2].buy_share();
+        let size = observation.net_qty.abs();
+        let flag = |q| {
+            self.sizes.is_full().then(|| match self.sizes.quantile(q) {
+                Some(quantile) if size > quantile => Decimal::ONE,
+                _ => Decimal::ZERO,
+            })
+        };
+        observation.large_trade_90 = flag(0.9);
+        observation.large_trade_99 = flag(0.99);
+        observation.kyle_lambda = full(&self.impact_qty).map(|_| self.kyle_lambda());
+    }
+    pub fn push(&mut self, observation: &Observation) {
+        self.volume.push(observation.net_qty.abs());
+        self.push_bucket_volume(observation.net_qty);
+        if let Some(last_timestamp) = self.last_timestamp {
+            self.inter_arrival_ms
+                .push(Decimal::from(observation.timestamp - last_timestamp));
+        }
+        self.last_timestamp = Some(observation.timestamp);
+        for volume in self.volumes.iter_mut() {
+            volume.push(observation.timestamp, observation.net_qty);
+        }
+        self.sizes.push(observation.net_qty.abs());
+        if let Some(last_price) = self.last_price {
+            let price_change = observation.price - last_price;
+            self.impact_qty.push(observation.net_qty);
+            self.impact_price.push(price_change);
+            self.impact_product.push(observation.net_qty * price_change);
+        }
+        self.last_price = Some(observation.price);
+    }
+    /// Fills the current volume bucket, splitting the trade across buckets once it's full. Each
+    /// bucket is sized when it starts, trades before the volume of enough trades is known aren't
+    /// bucketed.
+    fn push_bucket_volume(&mut self, net_qty: Decimal) {
+        let mut remaining = net_qty.abs();
+        while !remaining.is_zero() {
+            let bucket_volume = match self.bucket_volume.or_else(|| self.next_bucket_volume()) {
+                Some(bucket_volume) => bucket_volume,
+                None => return,
+            };
+            self.bucket_volume = Some(bucket_volume);
+            let room = bucket_volume - self.bucket_buy - self.bucket_sell;
+            let filled = remaining.min(room);
+            if net_qty.is_sign_negative() {
+                self.bucket_sell += filled;
+            } else {
+                self.bucket_buy += filled;
+            }
+            remaining -= filled;
+            if self.bucket_buy + self.bucket_sell >= bucket_volume {
+                self.bucket_imbalances
+                    .push((self.bucket_buy - self.bucket_sell).abs() / bucket_volume);
+                self.bucket_buy = Decimal::ZERO;
+                self.bucket_sell = Decimal::ZERO;
+                self.bucket_volume = None;
+            }
+        }
+    }
+    /// Size of a new bucket from the recent volume, `None` until there were enough trades or while
+    /// they had no volume.
+    fn next_bucket_volume(&self) -> Option<Decimal> {
+        full(&self.volume)
+            .map(|volume| volume.sum() / self.buckets)
+            .filter(|bucket_volume| !bucket_volume.is_zero())
+    }
+    /// Slope of the price change on the signed volume of each trade, by least squares.
+    fn kyle_lambda(&self) -> Decimal {
+        let (mean_qty, mean_price) = match (self.impact_qty.mean(), self.impact_price.mean()) {
+            (Some(mean_qty), Some(mean_price)) => (mean_qty, mean_price),
+            _ => return Decimal::ZERO,
+        };
+        let variance = self.impact_qty.variance().unwrap_or_default();
+        if variance.is_zero() {
+            return Decimal::ZERO;
+        }
+        let covariance = self.impact_product.mean().unwrap_or_default() - mean_qty * mean_price;
+        (covariance / variance).round_dp(8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{dec, observations};
+
+    /// Trades 200ms apart.
+    fn trades(net_qtys: &[&str]) -> Vec<Observation> {
+        let mut price = dec("100");
+        observations(net_qtys.len())
+            .into_iter()
+            .zip(net_qtys)
+            .enumerate()
+            .map(|(i, (mut observation, net_qty))| {
+                observation.timestamp = i as i64 * 200;
+                observation.net_qty = dec(net_qty);
+                // every unit bought moves the price up by 2
+                price += dec("2") * observation.net_qty;
+                observation.price = price;
+                observation
+            })
+            .collect()
+    }
+
+    fn replay(flow: &mut TradeFlow, observations: Vec<Observation>) -> Observation {
+        let mut last = None;
+        for mut observation in observations {
+            flow.fill(&mut observation);
+            flow.push(&observation);
+            last = Some(observation);
+        }
+        last.unwrap()
+    }
+
+    #[test]
+    fn measures_toxicity_from_the_previous_trades() {
+        let net_qtys = ["2", "-2", "1.5", "-1.5", "1", "-1", "0.5", "3"];
+        // every window fills with the trades before the last one, buckets hold half the volume of
+        // the last two trades
+        let mut flow = TradeFlow::new(2, 2, net_qtys.len() - 2);
+        let mut trades = trades(&net_qtys);
+        let last = trades.pop().unwrap();
+        let before_last = replay(&mut flow, trades);
+        assert_eq!(before_last.inter_arrival_mean_ms, None);
+        assert_eq!(before_last.kyle_lambda, None);
+        // 2.5 bought of the 7 traded within the second before
+        assert_eq!(before_last.buy_share_1s, Some(dec("2.5") / dec("7")));
+        assert_eq!(before_last.buy_share_10s, None);
+
+        let last = replay(&mut flow, vec![last]);
+        // the last two buckets: 0.75 bought and 0.5 sold of 1.25, then 0.5 of each of 1
+        assert_eq!(last.vpin, Some((dec("0.2") + dec("0")) / dec("2")));
+        assert_eq!(last.inter_arrival_mean_ms, Some(dec("200")));
+        assert_eq!(last.inter_arrival_std_ms, Some(Decimal::ZERO));
+        assert_eq!(last.trade_rate, Some(dec("5")));
+        assert_eq!(last.kyle_lambda, Some(dec("2")));
+        assert_eq!(last.large_trade_90, Some(Decimal::ONE));
+        assert_eq!(last.large_trade_99, Some(Decimal::ONE));
+        // 3 bought of the 5.5 traded within the second before the last trade
+        assert_eq!(last.buy_share_1s, Some(dec("3") / dec("5.5")));
+        assert_eq!(last.buy_share_60s, None);
+    }
+
+    #[test]
+    fn buy_share_forgets_trades_outside_its_window() {
+        let mut window = VolumeWindow::new(1_000);
+        assert_eq!(window.buy_share(), None);
+        window.push(0, dec("3"));
+        window.push(500, dec("-1"));
+        // the trades don't span the window yet
+        assert_eq!(window.buy_share(), None);
+        window.push(1_000, dec("-1"));
+        assert_eq!(window.buy_share(), Some(Decimal::ZERO));
+        window.push(2_500, Decimal::ZERO);
+        assert_eq!(window.buy_share(), Some(dec("0.5")));
+    }
+}
@@ -0,0 +1,248 @@
+use std::{error::Error, path::PathBuf};
+
+use clap::Args;
+use log::{info, warn};
+use rust_decimal::Decimal;
+
+use crate::binance::models::model_config::{load_model_data, ModelData};
+
+use super::{
+    data_handling::{FeatureDataFrame, TrainingFrame},
+    evaluation::Dataset,
+    export::{read_dataset, ExportFormat},
+    feature_registry::feature_size,
+    features::evaluate_dataset,
+    labeling::Labeler,
+    predictor::{gbdt::GbdtParams, ModelKind},
+    registry::ModelRegistry,
+    store::{read_spills, Retention},
+    weighting::SampleWeighting,
+};
+
+/// Options of the `train` command.
+#[derive(Debug, Clone, Args)]
+pub struct TrainArgs {
+    /// Observations recorded as JSON lines, such as `OBSERVATIONS_SPILL_PATH` files, or datasets
+    /// written by `export` as `.csv` or `.parquet`. All must be of the same kind.
+    #[arg(long = "data", required = true)]
+    pub data: Vec<PathBuf>,
+    #[arg(long, default_value = "0.10")]
+    pub tick_size: Decimal,
+    /// Promote the new model even if it doesn't beat the promoted one, or the promoted one can't
+    /// be compared with it.
+    #[arg(long)]
+    pub force: bool,
+}
+
+/// The training rows of `paths`. Recorded observations are replayed through a
+/// [`FeatureDataFrame`] and labelled with `labeler`, exported datasets are read as they are.
+fn read_training_data(
+    paths: &[PathBuf],
+    labeler: &Labeler,
+    tick_size: Decimal,
+) -> Result<Dataset, Box<dyn Error>> {
+    let formats: Vec<Option<ExportFormat>> = paths
+        .iter()
+        .map(|path| ExportFormat::from_path(path))
+        .collect();
+    if formats.iter().all(Option::is_none) {
+        let mut df = FeatureDataFrame::new_empty(Retention::default());
+        for observation in read_spills(paths)? {
+            df.push(observation);
+        }
+        let mut frame = TrainingFrame::from(df.snapshot());
+        frame.prepare(labeler, tick_size);
+        return Ok(Dataset::from_rows(&frame.data)?);
+    }
+    let mut dataset = Dataset::default();
+    for (path, format) in paths.iter().zip(formats) {
+        let format = format.ok_or("--data must be all recordings or all exports")?;
+        let exported = read_dataset(path, labeler, format)?;
+        dataset.x.extend(exported.x);
+        dataset.y.extend(exported.y);
+        dataset.w.extend(exported.w);
+        dataset.timestamps.extend(exported.timestamps);
+    }
+    if !dataset.timestamps.windows(2).all(|pair| pair[0] <= pair[1]) {
+        return Err("exported datasets must be given oldest first".into());
+    }
+    Ok(dataset)
+}
+
+/// The promoted model a new one of `kind` labelled by `labeler` is scored against. If it is of
+/// another kind, labeler or schema it can't be compared, so this fails unless `force` is set,
+/// and then returns an unfitted model whose parent is still the promoted version.
+fn incumbent(
+    kind: ModelKind,
+    labeler: Labeler,
+    gbdt_params: GbdtParams,
+    registry: ModelRegistry,
+    force: bool,
+) -> Result<ModelData, Box<dyn Error>> {
+    let Some(version) = registry.promoted()? else {
+        let model = kind.new_predictor(feature_size(), &labeler, &gbdt_params)?;
+        return Ok(ModelData::new(
+            kind,
+            labeler,
+            gbdt_params,
+            model,
+            false,
+            registry,
+        ));
+    };
+    match load_model_data(kind, labeler, &gbdt_params, &registry, version) {
+        Ok(model_data) => Ok(model_data),
+        Err(e) if force => {
+            warn!("Replacing promoted model version {} unscored: {}", version, e);
+            let model = kind.new_predictor(feature_size(), &labeler, &gbdt_params)?;
+            let mut model_data =
+                ModelData::new(kind, labeler, gbdt_params, model, false, registry);
+            model_data.registry_version = Some(version);
+            Ok(model_data)
+        }
+        Err(e) => Err(format!(
+            "the new model can't be compared with promoted version {}, pass --force to replace it: {}",
+            version, e
+        )
+        .into()),
+    }
+}
+
+/// Trains a model of `MODEL_KIND` labelled by `LABELER` on recorded or exported data, scores it
+/// against the promoted model with walk-forward validation and registers it. It is promoted if
+/// it beats the promoted model, or with `--force`.
+pub fn train(args: &TrainArgs) -> Result<(), Box<dyn Error>> {
+    let (kind, labeler) = (ModelKind::from_env()?, Labeler::from_env()?);
+    let served = incumbent(
+        kind,
+        labeler,
+        GbdtParams::from_file_or_default()?,
+        ModelRegistry::from_env(),
+        args.force,
+    )?;
+    let dataset = read_training_data(&args.data, &labeler, args.tick_size)?;
+    info!("Training a {} model on {} rows", kind, dataset.len());
+    let evaluation = evaluate_dataset(dataset, &served, SampleWeighting::from_env())?
+        .ok_or("not enough rows for a walk-forward fold")?;
+    info!("Candidate: {:?}", evaluation.metrics);
+    if let Some(comparison) = evaluation.incumbent {
+        info!("Against the promoted model: {:?}", comparison);
+    }
+    let promote = args.force || evaluation.improves();
+    let mut model = evaluation.replace(&served);
+    if promote {
+        info!("Promoted model version {}", model.save()?);
+    } else {
+        info!(
+            "Registered model version {}, the promoted model is kept",
+            model.register()?
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        model::{
+            export::{write_export, ExportRows},
+            registry::TrainingWindow,
+        },
+        test_support::{dec, observations, temp_registry, TICK_SIZE},
+        ROLLING_WINDOW,
+    };
+
+    #[test]
+    fn reads_recordings_and_exports_alike() {
+        let dir = std::env::temp_dir().join(format!("nshft-train-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let recording = dir.join("observations.jsonl");
+        let lines: Vec<String> = observations(2 * ROLLING_WINDOW)
+            .iter()
+            .map(|observation| serde_json::to_string(observation).unwrap())
+            .collect();
+        std::fs::write(&recording, lines.join("\n")).unwrap();
+        let labeler = Labeler::default();
+        let recorded =
+            read_training_data(std::slice::from_ref(&recording), &labeler, dec(TICK_SIZE)).unwrap();
+        assert!(recorded.len() > 0);
+
+        let export = dir.join("observations.parquet");
+        write_export(
+            &ExportRows::from(&recorded),
+            &labeler,
+            &export,
+            ExportFormat::Parquet,
+        )
+        .unwrap();
+        let exported =
+            read_training_data(std::slice::from_ref(&export), &labeler, dec(TICK_SIZE)).unwrap();
+        assert_eq!(exported.y, recorded.y);
+        assert_eq!(exported.timestamps, recorded.timestamps);
+        assert!(read_training_data(&[recording, export], &labeler, dec(TICK_SIZE)).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn needs_force_to_replace_a_promoted_model_it_cant_compare_with() {
+        let registry = temp_registry();
+        let (kind, labeler) = (ModelKind::Ridge, Labeler::default());
+        assert!(
+            !incumbent(
+                kind,
+                labeler,
+                GbdtParams::default(),
+                registry.clone(),
+                false
+            )
+            .unwrap()
+            .fitted
+        );
+        let mut promoted = ModelData::new(
+            kind,
+            labeler,
+            GbdtParams::default(),
+            kind.new_predictor(feature_size(), &labeler, &GbdtParams::default())
+                .unwrap(),
+            true,
+            registry.clone(),
+        );
+        promoted.training_window = Some(TrainingWindow {
+            first_timestamp: 0,
+            last_timestamp: 1,
+            rows: 2,
+        });
+        promoted.metrics = Some(Default::default());
+        let version = promoted.save().unwrap();
+
+        let same = incumbent(
+            kind,
+            labeler,
+            GbdtParams::default(),
+            registry.clone(),
+            false,
+        )
+        .unwrap();
+        assert!(same.fitted);
+        assert_eq!(same.registry_version, Some(version));
+        assert!(incumbent(
+            ModelKind::Gbdt,
+            labeler,
+            GbdtParams::default(),
+            registry.clone(),
+            false
+        )
+        .is_err());
+        let forced = incumbent(
+            ModelKind::Gbdt,
+            labeler,
+            GbdtParams::default(),
+            registry,
+            true,
+        )
+        .unwrap();
+        assert!(!forced.fitted);
+        assert_eq!(forced.registry_version, Some(version));
+    }
+}
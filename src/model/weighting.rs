@@ -0,0 +1,199 @@
+use serde::{Deserialize, Serialize};
+
+use super::{predictor::classifier::ClassThresholds, rolling::Window};
+
+/// Which sample weights training rows get, selected with the `SAMPLE_HALF_LIFE`,
+/// `SAMPLE_UNIQUENESS` and `SAMPLE_CLASS_BALANCE` environment variables. All are off by default,
+/// leaving every row a weight of one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SampleWeighting {
+    /// Halves the weight of a row each time it is this much older than the latest row.
+    pub half_life: Option<Window>,
+    /// Weighs each row by the average uniqueness of its label: one over the number of labels
+    /// overlapping it, averaged over the rows its horizon spans.
+    pub uniqueness: bool,
+    /// Weighs each row by the inverse frequency of its class, for classifiers.
+    pub class_balance: bool,
+}
+impl SampleWeighting {
+    pub fn from_env() -> Self {
+        let flag = |name: &str| match std::env::var(name) {
+            Ok(value) => value.parse().unwrap(),
+            Err(_) => false,
+        };
+        Self {
+            half_life: std::env::var("SAMPLE_HALF_LIFE")
+                .ok()
+                .map(|half_life| half_life.parse().unwrap()),
+            uniqueness: flag("SAMPLE_UNIQUENESS"),
+            class_balance: flag("SAMPLE_CLASS_BALANCE"),
+        }
+    }
+    /// The weight of each row with these timestamps and targets, labelled over `horizon`,
+    /// scaled to a mean of one. Class balance only applies when `classes` are given.
+    pub fn weights(
+        &self,
+        timestamps: &[i64],
+        targets: &[f32],
+        horizon: Window,
+        classes: Option<&ClassThresholds>,
+    ) -> Vec<f32> {
+        let mut weights = vec![1.0; timestamps.len()];
+        if let Some(half_life) = self.half_life {
+            multiply(&mut weights, &decay(timestamps, half_life));
+        }
+        if self.uniqueness {
+            multiply(&mut weights, &uniqueness(timestamps, horizon));
+        }
+        if let (true, Some(classes)) = (self.class_balance, classes) {
+            multiply(&mut weights, &class_balance(targets, classes));
+        }
+        let mean = weights.iter().sum::<f32>() / weights.len() as f32;
+        if mean > 0.0 {
+            weights.iter_mut().for_each(|weight| *weight /= mean);
+        }
+        weights
+    }
+}
+
+fn multiply(weights: &mut [f32], factors: &[f32]) {
+    for (weight, factor) in weights.iter_mut().zip(factors) {
+        *weight *= factor;
+    }
+}
+
+/// 0.5 to the power of each row's age in half-lives, counted in rows or milliseconds before the
+/// latest row.
+fn decay(timestamps: &[i64], half_life: Window) -> Vec<f32> {
+    let (last, latest) = match timestamps.last() {
+        Some(latest) => (timestamps.len() - 1, *latest),
+        None => return Vec::new(),
+    };
+    timestamps
+        .iter()
+        .enumerate()
+        .map(|(i, timestamp)| {
+            let age = match half_life {
+                Window::Count(rows) => (last - i) as f32 / rows.max(1) as f32,
+                Window::Time(ms) => (latest - timestamp) as f32 / ms.max(1) as f32,
+            };
+            0.5f32.powf(age)
+        })
+        .collect()
+}
+
+/// Average uniqueness of each row's label, which spans the rows from it up to the first one past
+/// `horizon`.
+fn uniqueness(timestamps: &[i64], horizon: Window) -> Vec<f32> {
+    let n = timestamps.len();
+    let mut ends = Vec::with_capacity(n);
+    let mut end = 0;
+    for start in 0..n {
+        end = end.max(start + 1);
+        while end < n && horizon.contains(end - start, timestamps[end] - timestamps[start]) {
+            end += 1;
+        }
+        ends.push(end);
+    }
+    // labels overlapping each row, then prefix sums of their inverse
+    let mut changes = vec![0i64; n + 1];
+    for (start, end) in ends.iter().enumerate() {
+        changes[start] += 1;
+        changes[*end] -= 1;
+    }
+    let mut concurrency = 0;
+    let mut inverse_sums = vec![0.0f64; n + 1];
+    for i in 0..n {
+        concurrency += changes[i];
+        inverse_sums[i + 1] = inverse_sums[i] + 1.0 / concurrency as f64;
+    }
+    ends.iter()
+        .enumerate()
+        .map(|(start, end)| {
+            ((inverse_sums[*end] - inverse_sums[start]) / (end - start) as f64) as f32
+        })
+        .collect()
+}
+
+/// Rows over classes present times the rows of each row's class, so every class carries the
+/// same total weight.
+fn class_balance(targets: &[f32], classes: &ClassThresholds) -> Vec<f32> {
+    let labels: Vec<usize> = targets
+        .iter()
+        .map(|target| classes.label(*target) as usize)
+        .collect();
+    let mut counts = [0usize; 3];
+    for label in &labels {
+        counts[*label] += 1;
+    }
+    let present = counts.iter().filter(|count| **count > 0).count();
+    labels
+        .iter()
+        .map(|label| targets.len() as f32 / (present * counts[*label]) as f32)
+        .collect()
+}
+
+/// Kish's effective sample size, the rows unweighted data would need for the same variance.
+pub fn effective_rows(weights: &[f32]) -> f32 {
+    let sum: f32 = weights.iter().sum();
+    let sum_of_squares: f32 = weights.iter().map(|weight| weight * weight).sum();
+    if sum_of_squares > 0.0 {
+        sum * sum / sum_of_squares
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(actual: &[f32], expected: &[f32]) -> bool {
+        actual.len() == expected.len()
+            && actual
+                .iter()
+                .zip(expected)
+                .all(|(a, e)| (a - e).abs() < 1e-5)
+    }
+
+    #[test]
+    fn weighs_by_recency_uniqueness_and_class() {
+        let timestamps = [0, 1000, 2000, 3000];
+        assert!(close(
+            &decay(&timestamps, Window::Time(1000)),
+            &[0.125, 0.25, 0.5, 1.0]
+        ));
+        assert!(close(
+            &decay(&timestamps, Window::Count(2)),
+            &decay(&timestamps, Window::Time(2000))
+        ));
+        // labels span rows 0-1, 1-2, 2-3 and 3, so rows 1 to 3 are shared by two labels
+        assert!(close(
+            &uniqueness(&timestamps, Window::Count(2)),
+            &[0.75, 0.5, 0.5, 0.5]
+        ));
+        let classes = ClassThresholds {
+            up_ticks: 1.0,
+            down_ticks: 1.0,
+        };
+        assert!(close(
+            &class_balance(&[1.0, 1.0, 1.0, -1.0], &classes),
+            &[2.0 / 3.0, 2.0 / 3.0, 2.0 / 3.0, 2.0]
+        ));
+
+        let weighting = SampleWeighting {
+            half_life: Some(Window::Time(1000)),
+            uniqueness: false,
+            class_balance: true,
+        };
+        // class balance is ignored without classes, and the weights average one
+        let weights = weighting.weights(&timestamps, &[0.0; 4], Window::Count(2), None);
+        assert!(close(&weights, &[0.25, 0.5, 1.0, 2.0].map(|w| w / 0.9375)));
+        assert!(close(
+            &SampleWeighting::default().weights(&timestamps, &[0.0; 4], Window::Count(2), None),
+            &[1.0; 4]
+        ));
+        assert!((effective_rows(&[1.0; 4]) - 4.0).abs() < 1e-6);
+        assert!((effective_rows(&[2.0, 0.0]) - 1.0).abs() < 1e-6);
+    }
+}
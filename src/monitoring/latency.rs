@@ -0,0 +1,153 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Formatter},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use hdrhistogram::Histogram;
+use log::info;
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+pub type LatencyRwl = Arc<RwLock<LatencyTracker>>;
+
+/// Lowest and highest trackable latency in microseconds, values outside are clamped.
+const LOWEST_MICROS: u64 = 1;
+const HIGHEST_MICROS: u64 = 60 * 60 * 1_000_000;
+const SIGNIFICANT_FIGURES: u8 = 3;
+
+pub fn new_latency_rwl() -> LatencyRwl {
+    Arc::new(RwLock::new(LatencyTracker::default()))
+}
+
+/// The hops a message goes through, from the exchange matching engine to an order leaving the bot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LatencyStage {
+    /// Exchange event time `E` to the local receive time of the frame.
+    ExchangeToReceive,
+    /// Local receive time to the handler returning.
+    ReceiveToHandled,
+    /// Handler returning to the model producing a prediction from that data.
+    HandledToPrediction,
+    /// Prediction to the order being handed to the order channel.
+    PredictionToOrder,
+}
+impl Display for LatencyStage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LatencyStage::ExchangeToReceive => write!(f, "exchange_to_receive"),
+            LatencyStage::ReceiveToHandled => write!(f, "receive_to_handled"),
+            LatencyStage::HandledToPrediction => write!(f, "handled_to_prediction"),
+            LatencyStage::PredictionToOrder => write!(f, "prediction_to_order"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencySummary {
+    pub stream: String,
+    pub stage: LatencyStage,
+    pub count: u64,
+    pub min_us: u64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub p999_us: u64,
+    pub max_us: u64,
+    pub mean_us: f64,
+}
+impl Display for LatencySummary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}: n={} p50={}us p90={}us p99={}us p99.9={}us max={}us",
+            self.stream,
+            self.stage,
+            self.count,
+            self.p50_us,
+            self.p90_us,
+            self.p99_us,
+            self.p999_us,
+            self.max_us
+        )
+    }
+}
+
+/// Per-stream HDR histograms of every [`LatencyStage`], in microseconds.
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    histograms: BTreeMap<(String, LatencyStage), Histogram<u64>>,
+    last_handled: HashMap<String, Instant>,
+}
+impl LatencyTracker {
+    pub fn record(&mut self, stream: &str, stage: LatencyStage, latency: Duration) {
+        let micros = latency.as_micros().min(u64::MAX as u128) as u64;
+        self.histograms
+            .entry((stream.to_string(), stage))
+            .or_insert_with(|| {
+                Histogram::new_with_bounds(LOWEST_MICROS, HIGHEST_MICROS, SIGNIFICANT_FIGURES)
+                    .unwrap()
+            })
+            .saturating_record(micros.max(LOWEST_MICROS));
+    }
+    /// Records the exchange to receive and receive to handled latencies of a single message.
+    /// A negative exchange latency (local clock behind the exchange) is recorded as zero.
+    pub fn record_message(
+        &mut self,
+        stream: &str,
+        event_time: Option<DateTime<Utc>>,
+        received_at: DateTime<Utc>,
+        received_instant: Instant,
+    ) {
+        if let Some(event_time) = event_time {
+            let exchange_latency = (received_at - event_time).to_std().unwrap_or_default();
+            self.record(stream, LatencyStage::ExchangeToReceive, exchange_latency);
+        }
+        let handled = Instant::now();
+        self.record(
+            stream,
+            LatencyStage::ReceiveToHandled,
+            handled - received_instant,
+        );
+        self.last_handled.insert(stream.to_string(), handled);
+    }
+    /// Records the time since the last message of `stream` was handled, if there was one.
+    pub fn record_since_handled(&mut self, stream: &str, stage: LatencyStage, now: Instant) {
+        if let Some(handled) = self.last_handled.get(stream).copied() {
+            self.record(stream, stage, now.saturating_duration_since(handled));
+        }
+    }
+    pub fn summaries(&self) -> Vec<LatencySummary> {
+        self.histograms
+            .iter()
+            .filter(|(_, h)| !h.is_empty())
+            .map(|((stream, stage), h)| LatencySummary {
+                stream: stream.clone(),
+                stage: *stage,
+                count: h.len(),
+                min_us: h.min(),
+                p50_us: h.value_at_quantile(0.5),
+                p90_us: h.value_at_quantile(0.9),
+                p99_us: h.value_at_quantile(0.99),
+                p999_us: h.value_at_quantile(0.999),
+                max_us: h.max(),
+                mean_us: h.mean(),
+            })
+            .collect()
+    }
+}
+
+/// Logs the latency percentiles of every stream and stage every `interval_secs` seconds.
+pub async fn log_latency_report(latency_rwl: LatencyRwl, interval_secs: u64) {
+    let mut interval = tokio::time::interval(Duration::from_secs(interval_secs));
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        for summary in latency_rwl.read().await.summaries() {
+            info!("Latency {}", summary);
+        }
+    }
+}
@@ -0,0 +1,185 @@
+use prometheus::{
+    exponential_buckets, Encoder, Gauge, GaugeVec, Histogram, HistogramOpts, IntCounter,
+    IntCounterVec, IntGauge, Opts, Registry, TextEncoder,
+};
+
+use super::latency::LatencyTracker;
+
+/// Prometheus metrics of the bot, registered in their own [`Registry`].
+pub struct Metrics {
+    registry: Registry,
+    pub messages: IntCounterVec,
+    pub parse_errors: IntCounterVec,
+    pub reconnects: IntCounter,
+    pub book_resyncs: IntCounter,
+    pub dataframe_len: IntGauge,
+    pub training_duration: Histogram,
+    pub model_mae: IntGauge,
+    pub model_evaluations: IntCounterVec,
+    pub predictions: IntCounter,
+    pub orders: IntCounterVec,
+    pub position: IntGauge,
+    pub realized_pnl: Gauge,
+    pub feature_errors: IntCounterVec,
+    pub feature_psi: GaugeVec,
+    pub feature_ks: GaugeVec,
+    pub trading_halted: IntGauge,
+    latency: GaugeVec,
+}
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("nshft".to_string()), None).unwrap();
+        let messages = IntCounterVec::new(
+            Opts::new(
+                "messages_total",
+                "Websocket messages received per event type",
+            ),
+            &["event"],
+        )
+        .unwrap();
+        let parse_errors = IntCounterVec::new(
+            Opts::new(
+                "parse_errors_total",
+                "Messages that failed to parse per event type",
+            ),
+            &["event"],
+        )
+        .unwrap();
+        let reconnects =
+            IntCounter::new("reconnects_total", "Websocket reconnection attempts").unwrap();
+        let book_resyncs =
+            IntCounter::new("book_resyncs_total", "Times the order book was re-seeded").unwrap();
+        let dataframe_len = IntGauge::new(
+            "dataframe_observations",
+            "Observations held in the feature dataframe",
+        )
+        .unwrap();
+        let training_duration = Histogram::with_opts(
+            HistogramOpts::new("training_duration_seconds", "Duration of a model fit")
+                .buckets(exponential_buckets(1.0, 2.0, 10).unwrap()),
+        )
+        .unwrap();
+        let model_mae = IntGauge::new(
+            "model_mae_ticks",
+            "Mean absolute error in ticks of the last trained model",
+        )
+        .unwrap();
+        let model_evaluations = IntCounterVec::new(
+            Opts::new(
+                "model_evaluations_total",
+                "Trained models per outcome, promoted or rejected",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        let predictions = IntCounter::new("predictions_total", "Predictions made").unwrap();
+        let orders =
+            IntCounterVec::new(Opts::new("orders_total", "Orders sent per side"), &["side"])
+                .unwrap();
+        let position = IntGauge::new("position", "Current position, 1 long and -1 short").unwrap();
+        let realized_pnl = Gauge::new(
+            "realized_pnl",
+            "Realized PnL in quote currency per unit of position",
+        )
+        .unwrap();
+        let feature_errors = IntCounterVec::new(
+            Opts::new(
+                "feature_errors_total",
+                "Predictions skipped per undefined or non-finite feature",
+            ),
+            &["feature"],
+        )
+        .unwrap();
+        let feature_psi = GaugeVec::new(
+            Opts::new(
+                "feature_psi",
+                "Population stability index of live features against training",
+            ),
+            &["feature"],
+        )
+        .unwrap();
+        let feature_ks = GaugeVec::new(
+            Opts::new(
+                "feature_ks",
+                "Kolmogorov-Smirnov distance of live features from training",
+            ),
+            &["feature"],
+        )
+        .unwrap();
+        let trading_halted = IntGauge::new(
+            "trading_halted",
+            "1 while feature drift keeps new positions from being opened",
+        )
+        .unwrap();
+        let latency = GaugeVec::new(
+            Opts::new(
+                "latency_microseconds",
+                "Latency percentiles per stream and stage",
+            ),
+            &["stream", "stage", "quantile"],
+        )
+        .unwrap();
+        registry.register(Box::new(messages.clone())).unwrap();
+        registry.register(Box::new(parse_errors.clone())).unwrap();
+        registry.register(Box::new(reconnects.clone())).unwrap();
+        registry.register(Box::new(book_resyncs.clone())).unwrap();
+        registry.register(Box::new(dataframe_len.clone())).unwrap();
+        registry
+            .register(Box::new(training_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(model_mae.clone())).unwrap();
+        registry
+            .register(Box::new(model_evaluations.clone()))
+            .unwrap();
+        registry.register(Box::new(predictions.clone())).unwrap();
+        registry.register(Box::new(orders.clone())).unwrap();
+        registry.register(Box::new(position.clone())).unwrap();
+        registry.register(Box::new(realized_pnl.clone())).unwrap();
+        registry.register(Box::new(feature_errors.clone())).unwrap();
+        registry.register(Box::new(feature_psi.clone())).unwrap();
+        registry.register(Box::new(feature_ks.clone())).unwrap();
+        registry.register(Box::new(trading_halted.clone())).unwrap();
+        registry.register(Box::new(latency.clone())).unwrap();
+        Self {
+            registry,
+            messages,
+            parse_errors,
+            reconnects,
+            book_resyncs,
+            dataframe_len,
+            training_duration,
+            model_mae,
+            model_evaluations,
+            predictions,
+            orders,
+            position,
+            realized_pnl,
+            feature_errors,
+            feature_psi,
+            feature_ks,
+            trading_halted,
+            latency,
+        }
+    }
+    /// Encodes every metric in the Prometheus text format, refreshing the latency percentiles first.
+    pub fn encode(&self, latency: &LatencyTracker) -> String {
+        for summary in latency.summaries() {
+            let stage = summary.stage.to_string();
+            for (quantile, value) in [
+                ("0.5", summary.p50_us),
+                ("0.9", summary.p90_us),
+                ("0.99", summary.p99_us),
+                ("0.999", summary.p999_us),
+            ] {
+                self.latency
+                    .with_label_values(&[&summary.stream, &stage, quantile])
+                    .set(value as f64);
+            }
+        }
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::Arc;
+
+use self::{
+    latency::{new_latency_rwl, LatencyRwl},
+    metrics::Metrics,
+    status::{new_status_rwl, StatusRwl},
+};
+
+pub mod latency;
+pub mod metrics;
+pub mod server;
+pub mod status;
+
+/// Handles to everything the bot reports about itself, cheap to clone into every task.
+#[derive(Clone)]
+pub struct Telemetry {
+    pub metrics: Arc<Metrics>,
+    pub latency: LatencyRwl,
+    pub status: StatusRwl,
+}
+
+pub fn new_telemetry(symbol: &str) -> Telemetry {
+    Telemetry {
+        metrics: Arc::new(Metrics::new()),
+        latency: new_latency_rwl(),
+        status: new_status_rwl(symbol),
+    }
+}
@@ -0,0 +1,257 @@
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use log::{error, info};
+use serde::Serialize;
+use serde_json::json;
+use tokio::sync::Notify;
+
+use crate::{binance::models::orderbook::OrderBooksRWL, DATA_FRESHNESS_MS, HTTP_ADDR};
+
+use super::Telemetry;
+
+const DEFAULT_BOOK_DEPTH: usize = 10;
+
+/// The address the API listens on, `HTTP_ADDR` or loopback only by default.
+pub fn addr_from_env() -> SocketAddr {
+    std::env::var("HTTP_ADDR")
+        .unwrap_or_else(|_| HTTP_ADDR.to_string())
+        .parse()
+        .unwrap()
+}
+
+/// Serves the local HTTP API until the server fails. If `addr` can't be bound the error is logged
+/// and this never returns, so the rest of the process keeps trading without the API:
+/// - `GET /metrics` Prometheus metrics
+/// - `GET /health` liveness, 503 unless connected with a valid book and fresh data
+/// - `GET /status` symbol, top of book, last prediction, position and model
+/// - `GET /book?depth=N` the top `N` levels of the order book
+/// - `POST /export` starts an export of the training data, notifying `export_trigger`
+pub async fn serve(
+    addr: SocketAddr,
+    telemetry: Telemetry,
+    orderbooks_rwl: OrderBooksRWL,
+    export_trigger: Arc<Notify>,
+) {
+    let make_service = make_service_fn(move |_| {
+        let telemetry = telemetry.clone();
+        let orderbooks_rwl = orderbooks_rwl.clone();
+        let export_trigger = export_trigger.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                route(
+                    request,
+                    telemetry.clone(),
+                    orderbooks_rwl.clone(),
+                    export_trigger.clone(),
+                )
+            }))
+        }
+    });
+    let server = match Server::try_bind(&addr) {
+        Ok(builder) => builder,
+        Err(e) => {
+            error!("Could not serve the HTTP API on {}: {:?}", addr, e);
+            return std::future::pending().await;
+        }
+    };
+    info!("Serving HTTP API on http://{}", addr);
+    if let Err(e) = server.serve(make_service).await {
+        error!("HTTP server error: {:?}", e);
+    }
+}
+
+async fn route(
+    request: Request<Body>,
+    telemetry: Telemetry,
+    orderbooks_rwl: OrderBooksRWL,
+    export_trigger: Arc<Notify>,
+) -> Result<Response<Body>, Infallible> {
+    let method = match request.uri().path() {
+        "/metrics" | "/health" | "/status" | "/book" => Method::GET,
+        "/export" => Method::POST,
+        _ => return Ok(empty_response(StatusCode::NOT_FOUND)),
+    };
+    if request.method() != method {
+        return Ok(empty_response(StatusCode::METHOD_NOT_ALLOWED));
+    }
+    let response = match request.uri().path() {
+        "/metrics" => {
+            let body = telemetry.metrics.encode(&*telemetry.latency.read().await);
+            Response::builder()
+                .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+                .body(Body::from(body))
+                .unwrap()
+        }
+        "/health" => {
+            let status = telemetry.status.read().await.clone();
+            let book_valid = {
+                let book = orderbooks_rwl.read().await;
+                book.is_valid && !book.is_empty()
+            };
+            let data_fresh = status.is_data_fresh(DATA_FRESHNESS_MS);
+            let healthy = status.connected && book_valid && data_fresh;
+            json_response(
+                if healthy {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                },
+                &json!({
+                    "healthy": healthy,
+                    "connected": status.connected,
+                    "book_valid": book_valid,
+                    "data_fresh": data_fresh,
+                    "last_message_at": status.last_message_at,
+                }),
+            )
+        }
+        "/status" => {
+            let status = telemetry.status.read().await.clone();
+            let (best_bid, best_ask) = {
+                let book = orderbooks_rwl.read().await;
+                (book.bids.first().cloned(), book.asks.first().cloned())
+            };
+            json_response(
+                StatusCode::OK,
+                &json!({
+                    "symbol": status.symbol,
+                    "best_bid": best_bid,
+                    "best_ask": best_ask,
+                    "last_prediction": status.last_prediction,
+                    "position": status.position,
+                    "trading_halted": status.trading_halted,
+                    "model_version": status.model_version,
+                    "registry_version": status.registry_version,
+                    "model_mae": status.model_mae,
+                    "last_training_at": status.last_training_at,
+                }),
+            )
+        }
+        "/book" => {
+            let depth = request
+                .uri()
+                .query()
+                .and_then(|query| {
+                    url::form_urlencoded::parse(query.as_bytes())
+                        .find(|(key, _)| key == "depth")
+                        .map(|(_, value)| value.parse::<usize>())
+                })
+                .unwrap_or(Ok(DEFAULT_BOOK_DEPTH));
+            match depth {
+                Ok(depth) => {
+                    let book = orderbooks_rwl.read().await;
+                    json_response(
+                        StatusCode::OK,
+                        &json!({
+                            "time": book.time,
+                            "last_update_id": book.last_update_id,
+                            "is_valid": book.is_valid,
+                            "bids": &book.bids[..depth.min(book.bids.len())],
+                            "asks": &book.asks[..depth.min(book.asks.len())],
+                        }),
+                    )
+                }
+                Err(_) => json_response(
+                    StatusCode::BAD_REQUEST,
+                    &json!({"error": "depth must be a non-negative integer"}),
+                ),
+            }
+        }
+        "/export" => {
+            export_trigger.notify_one();
+            json_response(StatusCode::ACCEPTED, &json!({"export": "started"}))
+        }
+        _ => empty_response(StatusCode::NOT_FOUND),
+    };
+    Ok(response)
+}
+
+fn json_response<T: Serialize>(status: StatusCode, body: &T) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(serde_json::to_string(body).unwrap()))
+        .unwrap()
+}
+
+fn empty_response(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        binance::models::orderbook::new_orderbooks_rwl, monitoring::new_telemetry, test_support,
+    };
+
+    async fn status_of(method: Method, path: &str, export_trigger: &Arc<Notify>) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        route(
+            request,
+            new_telemetry(test_support::SYMBOL),
+            new_orderbooks_rwl(),
+            export_trigger.clone(),
+        )
+        .await
+        .unwrap()
+        .status()
+    }
+
+    #[tokio::test]
+    async fn exports_only_on_post() {
+        let export_trigger = Arc::new(Notify::new());
+        assert_eq!(
+            status_of(Method::GET, "/export", &export_trigger).await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            status_of(Method::POST, "/status", &export_trigger).await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            status_of(Method::GET, "/missing", &export_trigger).await,
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            status_of(Method::GET, "/status", &export_trigger).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status_of(Method::POST, "/export", &export_trigger).await,
+            StatusCode::ACCEPTED
+        );
+        // the notification is stored until the export task waits for it
+        tokio::time::timeout(std::time::Duration::from_secs(1), export_trigger.notified())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn keeps_running_when_the_port_is_taken() {
+        let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let serving = serve(
+            taken.local_addr().unwrap(),
+            new_telemetry(test_support::SYMBOL),
+            new_orderbooks_rwl(),
+            Arc::new(Notify::new()),
+        );
+        assert!(
+            tokio::time::timeout(std::time::Duration::from_millis(200), serving)
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,52 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::Serialize;
+use tokio::sync::RwLock;
+
+use crate::model::predictor::ClassProbabilities;
+
+pub type StatusRwl = Arc<RwLock<BotStatus>>;
+
+pub fn new_status_rwl(symbol: &str) -> StatusRwl {
+    Arc::new(RwLock::new(BotStatus {
+        symbol: symbol.to_string(),
+        ..Default::default()
+    }))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PredictionStatus {
+    pub ticks: i32,
+    /// Class probabilities, when the model is a classifier.
+    pub probabilities: Option<ClassProbabilities>,
+    pub price: Decimal,
+    pub time: DateTime<Utc>,
+}
+
+/// Latest state of the bot as shown by the `/health` and `/status` endpoints.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct BotStatus {
+    pub symbol: String,
+    pub connected: bool,
+    pub last_message_at: Option<DateTime<Utc>>,
+    pub last_prediction: Option<PredictionStatus>,
+    pub position: i32,
+    /// True while feature drift keeps new positions from being opened.
+    pub trading_halted: bool,
+    /// Models served by this process so far.
+    pub model_version: u64,
+    /// Registry version of the served model, if it was saved or loaded from the registry.
+    pub registry_version: Option<u64>,
+    pub model_mae: Option<i32>,
+    pub last_training_at: Option<DateTime<Utc>>,
+}
+impl BotStatus {
+    /// Returns true if a market data message was received less than `max_age_ms` ago.
+    pub fn is_data_fresh(&self, max_age_ms: i64) -> bool {
+        self.last_message_at
+            .map(|time| (Utc::now() - time).num_milliseconds() <= max_age_ms)
+            .unwrap_or(false)
+    }
+}
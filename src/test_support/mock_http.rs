@@ -0,0 +1,74 @@
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Request, Response, Server, StatusCode,
+};
+use serde_json::Value;
+use tokio::task::JoinHandle;
+
+/// Local HTTP server answering `/fapi/v1/exchangeInfo` and `/fapi/v1/depth` with fixed bodies.
+pub struct MockHttpServer {
+    pub base_url: String,
+    requests: Arc<Mutex<Vec<String>>>,
+    handle: JoinHandle<()>,
+}
+impl MockHttpServer {
+    pub async fn start(exchange_info: Value, depth: Value) -> Self {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let make_service = make_service_fn({
+            let requests = requests.clone();
+            move |_| {
+                let requests = requests.clone();
+                let exchange_info = exchange_info.clone();
+                let depth = depth.clone();
+                async move {
+                    Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                        requests.lock().unwrap().push(request.uri().to_string());
+                        let body = match request.uri().path() {
+                            "/fapi/v1/exchangeInfo" => Some(exchange_info.clone()),
+                            "/fapi/v1/depth" => Some(depth.clone()),
+                            _ => None,
+                        };
+                        async move {
+                            Ok::<_, Infallible>(match body {
+                                Some(body) => Response::builder()
+                                    .header(CONTENT_TYPE, "application/json")
+                                    .body(Body::from(body.to_string()))
+                                    .unwrap(),
+                                None => Response::builder()
+                                    .status(StatusCode::NOT_FOUND)
+                                    .body(Body::empty())
+                                    .unwrap(),
+                            })
+                        }
+                    }))
+                }
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0))).serve(make_service);
+        let base_url = format!("http://{}", server.local_addr());
+        let handle = tokio::spawn(async move {
+            let _ = server.await;
+        });
+        Self {
+            base_url,
+            requests,
+            handle,
+        }
+    }
+    /// Paths and queries of every request received.
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+impl Drop for MockHttpServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
@@ -0,0 +1,150 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
+
+/// A step of a scripted websocket session.
+#[derive(Debug, Clone)]
+pub enum Step {
+    /// Sends `{"stream": stream, "data": data}`.
+    Frame { stream: String, data: Value },
+    /// Sends a ping and waits for the pong.
+    Ping,
+    /// Closes the connection.
+    Disconnect,
+}
+impl Step {
+    pub fn depth_update(data: Value) -> Self {
+        Step::Frame {
+            stream: super::depth_stream(),
+            data,
+        }
+    }
+    pub fn trade(data: Value) -> Self {
+        Step::Frame {
+            stream: super::trade_stream(),
+            data,
+        }
+    }
+}
+
+/// Local websocket server speaking the Binance combined stream protocol. The n-th connection
+/// acknowledges the SUBSCRIBE request and plays the n-th session script; connections past the
+/// last script are held open without sending anything.
+pub struct MockWsServer {
+    pub base_url: String,
+    connections: Arc<AtomicUsize>,
+    pongs: Arc<AtomicUsize>,
+    subscriptions: Arc<Mutex<Vec<Value>>>,
+    handle: JoinHandle<()>,
+}
+impl MockWsServer {
+    pub async fn start(sessions: Vec<Vec<Step>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("ws://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let pongs = Arc::new(AtomicUsize::new(0));
+        let subscriptions = Arc::new(Mutex::new(Vec::new()));
+        let handle = tokio::spawn({
+            let connections = connections.clone();
+            let pongs = pongs.clone();
+            let subscriptions = subscriptions.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let index = connections.fetch_add(1, Ordering::SeqCst);
+                    let steps = sessions.get(index).cloned().unwrap_or_default();
+                    tokio::spawn(play_session(
+                        stream,
+                        steps,
+                        pongs.clone(),
+                        subscriptions.clone(),
+                    ));
+                }
+            }
+        });
+        Self {
+            base_url,
+            connections,
+            pongs,
+            subscriptions,
+            handle,
+        }
+    }
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+    pub fn pongs(&self) -> usize {
+        self.pongs.load(Ordering::SeqCst)
+    }
+    /// The SUBSCRIBE requests received, one per connection.
+    pub fn subscriptions(&self) -> Vec<Value> {
+        self.subscriptions.lock().unwrap().clone()
+    }
+}
+impl Drop for MockWsServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+async fn play_session(
+    stream: TcpStream,
+    steps: Vec<Step>,
+    pongs: Arc<AtomicUsize>,
+    subscriptions: Arc<Mutex<Vec<Value>>>,
+) {
+    let mut ws = accept_async(stream).await.unwrap();
+    while let Some(Ok(message)) = ws.next().await {
+        if let Message::Text(text) = message {
+            let request: Value = serde_json::from_str(&text).unwrap();
+            ws.send(Message::Text(
+                json!({"result": null, "id": request["id"]}).to_string(),
+            ))
+            .await
+            .unwrap();
+            subscriptions.lock().unwrap().push(request);
+            break;
+        }
+    }
+    for step in steps {
+        match step {
+            Step::Frame { stream, data } => {
+                let frame = json!({"stream": stream, "data": data}).to_string();
+                if ws.send(Message::Text(frame)).await.is_err() {
+                    return;
+                }
+            }
+            Step::Ping => {
+                ws.send(Message::Ping(b"ping".to_vec())).await.unwrap();
+                wait_for_pong(&mut ws, &pongs).await;
+            }
+            Step::Disconnect => {
+                let _ = ws.close(None).await;
+                return;
+            }
+        }
+    }
+    // drain until the client goes away
+    while let Some(Ok(message)) = ws.next().await {
+        if let Message::Pong(_) = message {
+            pongs.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+async fn wait_for_pong(ws: &mut WebSocketStream<TcpStream>, pongs: &AtomicUsize) {
+    while let Some(Ok(message)) = ws.next().await {
+        if let Message::Pong(_) = message {
+            pongs.fetch_add(1, Ordering::SeqCst);
+            return;
+        }
+    }
+}
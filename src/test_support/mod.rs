@@ -0,0 +1,169 @@
+//! Offline stand-ins for Binance: a websocket server speaking the combined stream protocol,
+//! an HTTP server for the REST endpoints, and builders for the frames they serve.
+use std::{
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use chrono::Utc;
+use rust_decimal::Decimal;
+use serde_json::{json, Value};
+
+use crate::{
+    binance::models::{
+        fapi_exchange_info::{Filter, Symbol},
+        orderbook::BookFeatures,
+        trades::TradeFeatures,
+    },
+    model::{data_handling::Observation, order_flow::OrderFlowFeatures, registry::ModelRegistry},
+};
+
+pub mod mock_http;
+pub mod mock_ws;
+
+pub const SYMBOL: &str = "BTCUSDT";
+pub const TICK_SIZE: &str = "0.10";
+
+pub fn dec(value: &str) -> Decimal {
+    Decimal::from_str(value).unwrap()
+}
+
+pub fn trade_stream() -> String {
+    format!("{}@trade", SYMBOL.to_lowercase())
+}
+
+pub fn depth_stream() -> String {
+    format!("{}@depth@100ms", SYMBOL.to_lowercase())
+}
+
+/// A perpetual market with a `TICK_SIZE` price filter.
+pub fn market() -> Symbol {
+    Symbol {
+        symbol: SYMBOL.to_string(),
+        pair: SYMBOL.to_string(),
+        contract_type: "PERPETUAL".to_string(),
+        status: "TRADING".to_string(),
+        filters: vec![Filter {
+            filter_type: "PRICE_FILTER".to_string(),
+            tick_size: Some(TICK_SIZE.to_string()),
+            ..Default::default()
+        }],
+        ..Default::default()
+    }
+}
+
+pub fn exchange_info() -> Value {
+    json!({
+        "timezone": "UTC",
+        "serverTime": Utc::now().timestamp_millis(),
+        "symbols": [market()],
+    })
+}
+
+fn levels(levels: &[(&str, &str)]) -> Value {
+    levels
+        .iter()
+        .map(|(price, size)| json!([price, size]))
+        .collect()
+}
+
+/// Body of the REST depth endpoint.
+pub fn depth_snapshot(last_update_id: i64, bids: &[(&str, &str)], asks: &[(&str, &str)]) -> Value {
+    json!({
+        "lastUpdateId": last_update_id,
+        "E": Utc::now().timestamp_millis(),
+        "T": Utc::now().timestamp_millis(),
+        "bids": levels(bids),
+        "asks": levels(asks),
+    })
+}
+
+/// Data of a `depthUpdate` event covering update ids `first..=last`, following `previous`.
+pub fn depth_update(
+    first: i64,
+    last: i64,
+    previous: i64,
+    bids: &[(&str, &str)],
+    asks: &[(&str, &str)],
+) -> Value {
+    let now = Utc::now().timestamp_millis();
+    json!({
+        "e": "depthUpdate",
+        "E": now,
+        "T": now,
+        "s": SYMBOL,
+        "U": first,
+        "u": last,
+        "pu": previous,
+        "b": levels(bids),
+        "a": levels(asks),
+    })
+}
+
+/// Data of a `trade` event.
+pub fn trade(id: i64, price: &str, quantity: &str, buyer_is_the_market_maker: bool) -> Value {
+    let now = Utc::now().timestamp_millis();
+    json!({
+        "e": "trade",
+        "E": now,
+        "T": now,
+        "s": SYMBOL,
+        "t": id,
+        "p": price,
+        "q": quantity,
+        "X": "MARKET",
+        "m": buyer_is_the_market_maker,
+    })
+}
+
+/// Observations of a price oscillating by a few ticks around 100, after a full window of book
+/// updates.
+pub fn observations(count: usize) -> Vec<Observation> {
+    (0..count)
+        .map(|i| {
+            let price = dec("100") + Decimal::from((i % 20) as i64 - 10) * dec("0.1");
+            let net_qty = if i % 3 == 0 { dec("-0.5") } else { dec("0.5") };
+            let bid_total = dec("10") + Decimal::from((i % 7) as i64);
+            Observation::from_trade_and_book(
+                TradeFeatures {
+                    timestamp: 1_700_000_000_000 + i as i64 * 100,
+                    price,
+                    net_qty,
+                    notional: price * net_qty,
+                },
+                BookFeatures {
+                    bid_total,
+                    num_ticks_from_best_bid: dec("3"),
+                    ask_total: dec("12"),
+                    num_ticks_from_best_ask: dec("4"),
+                    bids_asks_ratio: bid_total / dec("12"),
+                    bid_notional: dec("1000"),
+                    ask_notional: dec("1200"),
+                    mid_price: price,
+                    spread_ticks: Decimal::ONE,
+                    imbalance_1: (bid_total - dec("12")) / (bid_total + dec("12")),
+                    ..Default::default()
+                },
+            )
+            .with_order_flow(OrderFlowFeatures {
+                ofi_rolling: Some(Decimal::ZERO),
+                bid_depletion_rate: Some(Decimal::ZERO),
+                bid_replenishment_rate: Some(Decimal::ZERO),
+                ask_depletion_rate: Some(Decimal::ZERO),
+                ask_replenishment_rate: Some(Decimal::ZERO),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// A registry in a fresh directory under the system temp dir.
+pub fn temp_registry() -> ModelRegistry {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    ModelRegistry::new(std::env::temp_dir().join(format!(
+        "nshft-registry-{}-{}-{}",
+        std::process::id(),
+        Utc::now().timestamp_nanos_opt().unwrap(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    )))
+}